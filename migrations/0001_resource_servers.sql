@@ -0,0 +1,7 @@
+-- Registry of resource servers (RFC 8707) and the scopes each one owns
+CREATE TABLE IF NOT EXISTS public.resource_servers
+(
+    uri    VARCHAR   NOT NULL PRIMARY KEY,
+    name   VARCHAR   NOT NULL,
+    scopes VARCHAR[] NOT NULL DEFAULT '{}'
+);
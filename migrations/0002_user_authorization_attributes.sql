@@ -0,0 +1,5 @@
+-- Authorization attributes carried in access tokens (RFC 9068 section 2.2.3.1)
+ALTER TABLE public.users
+    ADD COLUMN IF NOT EXISTS groups       VARCHAR[] NOT NULL DEFAULT '{}',
+    ADD COLUMN IF NOT EXISTS roles        VARCHAR[] NOT NULL DEFAULT '{}',
+    ADD COLUMN IF NOT EXISTS entitlements VARCHAR[] NOT NULL DEFAULT '{}';
@@ -0,0 +1,10 @@
+-- Scopes each user has granted to each client
+CREATE TABLE IF NOT EXISTS public.consents
+(
+    user_id    OID         NOT NULL,
+    client_id  OID         NOT NULL,
+    scopes     VARCHAR[]   NOT NULL DEFAULT '{}',
+    created_at TIMESTAMPTZ NOT NULL DEFAULT NOW(),
+    updated_at TIMESTAMPTZ NOT NULL DEFAULT NOW(),
+    PRIMARY KEY (user_id, client_id)
+);
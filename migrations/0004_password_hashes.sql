@@ -0,0 +1,16 @@
+-- Passwords are verified in the application against PHC strings (Argon2id, or bcrypt/PBKDF2 for imported hashes)
+ALTER TABLE public.users
+    RENAME COLUMN password TO password_hash;
+
+-- Hash the existing plaintext passwords with bcrypt, they are upgraded to Argon2id on the next successful login.
+-- Only values in one of the hash formats the application verifies are kept as they are, a plaintext password
+-- that merely starts with '$' is hashed too
+CREATE EXTENSION IF NOT EXISTS pgcrypto;
+
+UPDATE public.users
+SET password_hash = crypt(password_hash, gen_salt('bf', 12))
+WHERE password_hash !~ ('^\$(' ||
+    'argon2(id|i|d)\$(v=[0-9]+\$)?m=[0-9]+,t=[0-9]+,p=[0-9]+\$[A-Za-z0-9+/]+\$[A-Za-z0-9+/]+' || '|' ||
+    '2[aby]\$[0-9]{2}\$[./A-Za-z0-9]{53}' || '|' ||
+    'pbkdf2(-sha256|-sha512)?\$i=[0-9]+(,l=[0-9]+)?\$[A-Za-z0-9+/.]+\$[A-Za-z0-9+/.]+' ||
+    ')$');
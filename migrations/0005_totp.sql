@@ -0,0 +1,23 @@
+-- Multi-factor authentication can be enforced per user and per client
+ALTER TABLE public.users
+    ADD COLUMN IF NOT EXISTS mfa_required BOOLEAN NOT NULL DEFAULT FALSE;
+
+ALTER TABLE public.clients
+    ADD COLUMN IF NOT EXISTS mfa_required BOOLEAN NOT NULL DEFAULT FALSE;
+
+-- Confirmed TOTP (RFC 6238) secrets, base32 encoded
+CREATE TABLE IF NOT EXISTS public.user_totp
+(
+    user_id    OID         NOT NULL PRIMARY KEY,
+    secret     VARCHAR     NOT NULL,
+    created_at TIMESTAMPTZ NOT NULL DEFAULT NOW()
+);
+
+-- Single-use recovery codes, stored as SHA-256 hex digests
+CREATE TABLE IF NOT EXISTS public.user_recovery_codes
+(
+    user_id   OID         NOT NULL,
+    code_hash VARCHAR     NOT NULL,
+    used_at   TIMESTAMPTZ NULL,
+    PRIMARY KEY (user_id, code_hash)
+);
@@ -0,0 +1,13 @@
+-- WebAuthn credentials (passkeys and security keys) registered by users
+CREATE TABLE IF NOT EXISTS public.webauthn_credentials
+(
+    credential_id VARCHAR     NOT NULL PRIMARY KEY, -- base64url encoded
+    user_id       OID         NOT NULL,
+    public_key    BYTEA       NOT NULL,             -- COSE_Key
+    sign_count    BIGINT      NOT NULL DEFAULT 0,
+    name          VARCHAR     NOT NULL,
+    created_at    TIMESTAMPTZ NOT NULL DEFAULT NOW(),
+    last_used_at  TIMESTAMPTZ NULL
+);
+
+CREATE INDEX IF NOT EXISTS webauthn_credentials_user_id_idx ON public.webauthn_credentials (user_id);
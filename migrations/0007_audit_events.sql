@@ -0,0 +1,14 @@
+-- Security relevant events such as account lockouts
+CREATE TABLE IF NOT EXISTS public.audit_events
+(
+    id         BIGSERIAL   NOT NULL PRIMARY KEY,
+    event      VARCHAR     NOT NULL,
+    user_id    OID         NULL,
+    subject    VARCHAR     NOT NULL, -- the email address or IP address the event is about
+    ip_address VARCHAR     NULL,
+    details    VARCHAR     NULL,
+    created_at TIMESTAMPTZ NOT NULL DEFAULT NOW()
+);
+
+CREATE INDEX IF NOT EXISTS audit_events_user_id_idx ON public.audit_events (user_id);
+CREATE INDEX IF NOT EXISTS audit_events_created_at_idx ON public.audit_events (created_at);
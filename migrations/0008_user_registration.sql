@@ -0,0 +1,23 @@
+-- Self-registered users have to verify their email address, existing users are trusted
+ALTER TABLE public.users
+    ADD COLUMN IF NOT EXISTS email_verified BOOLEAN NOT NULL DEFAULT TRUE,
+    ADD COLUMN IF NOT EXISTS created_at     TIMESTAMPTZ NOT NULL DEFAULT NOW();
+
+-- Registration inserts users without an ID, generate one unless the table already does
+DO
+$$
+    BEGIN
+        IF (SELECT column_default
+            FROM information_schema.columns
+            WHERE table_schema = 'public'
+              AND table_name = 'users'
+              AND column_name = 'id') IS NULL THEN
+            CREATE SEQUENCE IF NOT EXISTS public.users_id_seq;
+            PERFORM setval('public.users_id_seq', COALESCE((SELECT MAX(id::BIGINT) FROM public.users), 0) + 1, FALSE);
+            ALTER TABLE public.users
+                ALTER COLUMN id SET DEFAULT nextval('public.users_id_seq')::OID;
+        END IF;
+    END
+$$;
+
+CREATE INDEX IF NOT EXISTS users_email_lower_idx ON public.users (LOWER(email));
@@ -0,0 +1,30 @@
+-- Upstream OpenID Connect / OAuth 2.0 providers users can sign in with
+CREATE TABLE IF NOT EXISTS public.identity_providers
+(
+    id                     VARCHAR NOT NULL PRIMARY KEY, -- used in URLs, e.g. "corporate"
+    name                   VARCHAR NOT NULL,             -- shown on the login button
+    issuer                 VARCHAR NOT NULL,
+    client_id              VARCHAR NOT NULL,
+    client_secret          VARCHAR NOT NULL,
+    scopes                 VARCHAR NOT NULL DEFAULT 'openid email profile',
+    -- Discovered from the issuer's OpenID Connect metadata when not set
+    authorization_endpoint VARCHAR NULL,
+    token_endpoint         VARCHAR NULL,
+    userinfo_endpoint      VARCHAR NULL,
+    -- Link to existing users by email even if the provider doesn't assert it is verified
+    trust_email            BOOLEAN NOT NULL DEFAULT FALSE,
+    enabled                BOOLEAN NOT NULL DEFAULT TRUE
+);
+
+-- Upstream identities linked to users
+CREATE TABLE IF NOT EXISTS public.user_identities
+(
+    provider_id VARCHAR     NOT NULL REFERENCES public.identity_providers (id) ON DELETE CASCADE,
+    subject     VARCHAR     NOT NULL,
+    user_id     OID         NOT NULL,
+    email       VARCHAR     NULL,
+    created_at  TIMESTAMPTZ NOT NULL DEFAULT NOW(),
+    PRIMARY KEY (provider_id, subject)
+);
+
+CREATE INDEX IF NOT EXISTS user_identities_user_id_idx ON public.user_identities (user_id);
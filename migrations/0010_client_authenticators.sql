@@ -0,0 +1,3 @@
+-- The user authentication backends a client accepts, tried in order
+ALTER TABLE public.clients
+    ADD COLUMN IF NOT EXISTS authenticators VARCHAR[] NOT NULL DEFAULT '{database}';
@@ -0,0 +1,27 @@
+-- Registry of the scopes clients can request
+CREATE TABLE IF NOT EXISTS public.scopes
+(
+    name            VARCHAR NOT NULL PRIMARY KEY,
+    description     VARCHAR NOT NULL,                 -- shown to users on the login and consent pages
+    sensitivity     VARCHAR NOT NULL DEFAULT 'low' CHECK (sensitivity IN ('low', 'medium', 'high')),
+    resource_server VARCHAR NULL REFERENCES public.resource_servers (uri) ON DELETE SET NULL,
+    is_default      BOOLEAN NOT NULL DEFAULT FALSE    -- granted when a request omits the scope
+);
+
+INSERT INTO public.scopes (name, description, is_default)
+VALUES ('openid', 'Sign you in', TRUE),
+       ('profile', 'View your basic profile', FALSE),
+       ('email', 'View your email address', FALSE),
+       ('offline_access', 'Keep access while you are not signed in', FALSE)
+ON CONFLICT (name) DO NOTHING;
+
+-- Register the scopes already in use, so existing clients keep working
+INSERT INTO public.scopes (name, description, resource_server)
+SELECT DISTINCT ON (scope) scope, scope, uri
+FROM public.resource_servers, unnest(scopes) AS scope
+ON CONFLICT (name) DO NOTHING;
+
+INSERT INTO public.scopes (name, description)
+SELECT DISTINCT scope, scope
+FROM public.clients, unnest(allowed_scopes) AS scope
+ON CONFLICT (name) DO NOTHING;
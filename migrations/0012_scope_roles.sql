@@ -0,0 +1,3 @@
+-- Roles or groups a user needs one of to be granted the scope, everyone may get it when empty
+ALTER TABLE public.scopes
+    ADD COLUMN IF NOT EXISTS roles VARCHAR[] NOT NULL DEFAULT '{}';
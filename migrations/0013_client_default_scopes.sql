@@ -0,0 +1,4 @@
+-- Scopes granted when an authorization request omits the scope parameter,
+-- the registered default scopes the client is allowed are used when empty
+ALTER TABLE public.clients
+    ADD COLUMN IF NOT EXISTS default_scopes VARCHAR[] NOT NULL DEFAULT '{}';
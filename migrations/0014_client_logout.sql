@@ -0,0 +1,5 @@
+-- Logout endpoints of clients (OpenID Connect RP-Initiated, Front-Channel and Back-Channel Logout)
+ALTER TABLE public.clients
+    ADD COLUMN IF NOT EXISTS post_logout_redirect_uris VARCHAR[] NOT NULL DEFAULT '{}',
+    ADD COLUMN IF NOT EXISTS frontchannel_logout_uri   VARCHAR   NULL,
+    ADD COLUMN IF NOT EXISTS backchannel_logout_uri    VARCHAR   NULL;
@@ -0,0 +1,20 @@
+-- Custom claims added to access tokens, ID tokens and userinfo responses
+CREATE TABLE IF NOT EXISTS public.claim_mappings
+(
+    id        SERIAL    NOT NULL PRIMARY KEY,
+    client_id OID       NULL,     -- applies to every client when not set
+    scope     VARCHAR   NULL,     -- only applies when the scope is granted, if set
+    claim     VARCHAR   NOT NULL,
+    -- user_attribute: the users column named by value
+    -- static: value as JSON, or as a string if it isn't valid JSON
+    -- group_membership: whether the user is in the group or has the role named by value
+    source    VARCHAR   NOT NULL CHECK (source IN ('user_attribute', 'static', 'group_membership')),
+    value     VARCHAR   NOT NULL,
+    targets   VARCHAR[] NOT NULL DEFAULT '{access_token,id_token,userinfo}',
+    -- Protocol claims and the claims issued from users can't be overridden
+    CHECK (claim NOT IN ('iss', 'sub', 'aud', 'exp', 'iat', 'nbf', 'jti', 'auth_time', 'client_id',
+                         'scope', 'amr', 'acr', 'azp', 'nonce', 'sid', 'at_hash', 'c_hash', 'cnf',
+                         'events', 'email', 'email_verified', 'groups', 'roles', 'entitlements'))
+);
+
+CREATE INDEX IF NOT EXISTS claim_mappings_client_id_idx ON public.claim_mappings (client_id);
@@ -0,0 +1,3 @@
+-- When a client last used the grant of a user to obtain tokens
+ALTER TABLE public.consents
+    ADD COLUMN IF NOT EXISTS last_used_at TIMESTAMPTZ NULL;
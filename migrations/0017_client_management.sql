@@ -0,0 +1,26 @@
+-- Clients can be disabled without deleting them, and limited to some grant types
+ALTER TABLE public.clients
+    ADD COLUMN IF NOT EXISTS enabled     BOOLEAN   NOT NULL DEFAULT TRUE,
+    ADD COLUMN IF NOT EXISTS grant_types VARCHAR[] NOT NULL DEFAULT '{authorization_code,refresh_token}';
+
+-- Clients created through the admin API get the next ID, unless the table already generates one
+DO
+$$
+    BEGIN
+        IF (SELECT column_default
+            FROM information_schema.columns
+            WHERE table_schema = 'public'
+              AND table_name = 'clients'
+              AND column_name = 'id') IS NULL THEN
+            CREATE SEQUENCE IF NOT EXISTS public.clients_id_seq;
+            PERFORM setval('public.clients_id_seq', COALESCE((SELECT MAX(id::BIGINT) FROM public.clients), 0) + 1, FALSE);
+            ALTER TABLE public.clients
+                ALTER COLUMN id SET DEFAULT nextval('public.clients_id_seq')::OID;
+        END IF;
+    END
+$$;
+
+-- Access tokens with this scope can use the admin API, it's only granted to admins
+INSERT INTO public.scopes (name, description, sensitivity, roles)
+VALUES ('admin', 'Manage the clients and users of this server', 'high', '{admin}')
+ON CONFLICT (name) DO NOTHING;
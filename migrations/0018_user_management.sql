@@ -0,0 +1,3 @@
+-- Disabled users can't sign in, and no tokens are issued for them
+ALTER TABLE public.users
+    ADD COLUMN IF NOT EXISTS enabled BOOLEAN NOT NULL DEFAULT TRUE;
@@ -0,0 +1,12 @@
+-- LDAP entries linked to the shadow accounts provisioned for them, keyed by a stable
+-- identifier of the entry (entryUUID by default) so accounts are never matched by email
+CREATE TABLE IF NOT EXISTS public.ldap_users
+(
+    entry_id   VARCHAR     NOT NULL PRIMARY KEY,
+    user_id    OID         NOT NULL UNIQUE,
+    dn         VARCHAR     NOT NULL,
+    created_at TIMESTAMPTZ NOT NULL DEFAULT NOW()
+);
+
+-- Shadow accounts provisioned before are not linked, their entries have to be inserted here
+-- by hand, e.g. with the entryUUID from `ldapsearch -b <dn> entryUUID` as 'entryuuid:<uuid>'
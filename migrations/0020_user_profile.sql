@@ -0,0 +1,9 @@
+-- Profile claims of users (OpenID Connect Core section 5.1)
+ALTER TABLE public.users
+    ADD COLUMN IF NOT EXISTS name                  VARCHAR NULL,
+    ADD COLUMN IF NOT EXISTS phone_number          VARCHAR NULL, -- E.164, e.g. +14155550100
+    ADD COLUMN IF NOT EXISTS phone_number_verified BOOLEAN NOT NULL DEFAULT FALSE;
+
+INSERT INTO public.scopes (name, description)
+VALUES ('phone', 'View your phone number')
+ON CONFLICT (name) DO NOTHING;
@@ -0,0 +1,13 @@
+-- Mappings can't override the standard claims of OpenID Connect Core section 5.1 either.
+-- Existing mappings of them are kept but skipped when issuing tokens, NOT VALID only checks
+-- new and changed rows.
+ALTER TABLE public.claim_mappings
+    DROP CONSTRAINT IF EXISTS claim_mappings_claim_check,
+    ADD CONSTRAINT claim_mappings_claim_check
+        CHECK (claim NOT IN ('iss', 'sub', 'aud', 'exp', 'iat', 'nbf', 'jti', 'auth_time', 'client_id',
+                             'scope', 'amr', 'acr', 'azp', 'nonce', 'sid', 'at_hash', 'c_hash', 'cnf',
+                             'events', 'groups', 'roles', 'entitlements', 'name', 'given_name',
+                             'family_name', 'middle_name', 'nickname', 'preferred_username',
+                             'profile', 'picture', 'website', 'email', 'email_verified', 'gender',
+                             'birthdate', 'zoneinfo', 'locale', 'phone_number',
+                             'phone_number_verified', 'address', 'updated_at')) NOT VALID;
@@ -0,0 +1,6 @@
+-- Email addresses are unique regardless of case, so concurrent sign ups and admin requests
+-- can't create the same user twice. Duplicates have to be merged before this migration, e.g.
+-- find them with: SELECT LOWER(email) FROM public.users GROUP BY 1 HAVING COUNT(*) > 1;
+CREATE UNIQUE INDEX IF NOT EXISTS users_email_lower_key ON public.users (LOWER(email));
+
+DROP INDEX IF EXISTS public.users_email_lower_idx;
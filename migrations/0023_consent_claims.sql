@@ -0,0 +1,3 @@
+-- Claims each user has granted to each client on their own, besides the claims of the scopes
+ALTER TABLE public.consents
+    ADD COLUMN IF NOT EXISTS claims VARCHAR[] NOT NULL DEFAULT '{}';
@@ -0,0 +1,182 @@
+use crate::config::{admin_scope, issuer_url, jwt_secret};
+use crate::storage::get_scope;
+use crate::GLOBAL_DATABASE;
+use axum::http::header::AUTHORIZATION;
+use axum::http::{HeaderMap, StatusCode};
+use jsonwebtoken::{decode, Algorithm, DecodingKey, Validation};
+use log::warn;
+use serde::Deserialize;
+
+// Pages of admin API lists hold at most this many items
+const MAX_PER_PAGE: i64 = 100;
+
+// The access token claims the admin API needs
+#[derive(Deserialize)]
+struct AdminTokenClaims {
+    sub: String,
+    scope: String,
+}
+
+/// Query parameters of the admin API lists.
+#[derive(Deserialize)]
+pub struct ListParams {
+    page: Option<i64>,     // Starts at 1
+    per_page: Option<i64>, // Defaults to 20
+    search: Option<String>,
+}
+
+impl ListParams {
+    pub fn page(&self) -> i64 {
+        self.page.unwrap_or(1).max(1)
+    }
+
+    pub fn per_page(&self) -> i64 {
+        self.per_page.unwrap_or(20).clamp(1, MAX_PER_PAGE)
+    }
+
+    pub fn offset(&self) -> i64 {
+        (self.page() - 1).saturating_mul(self.per_page())
+    }
+
+    pub fn search(&self) -> &str {
+        self.search.as_deref().unwrap_or("").trim()
+    }
+}
+
+/// Verifies that the request has an access token for this server with the admin scope, issued
+/// to an admin who is still enabled and permitted the scope, and returns their ID. Otherwise
+/// returns the status and error for `bearer_error`.
+pub async fn authorize_admin(headers: &HeaderMap) -> Result<String, (StatusCode, &'static str)> {
+    let token = headers
+        .get(AUTHORIZATION)
+        .and_then(|header| header.to_str().ok())
+        .and_then(|header| header.strip_prefix("Bearer "))
+        .ok_or((StatusCode::UNAUTHORIZED, "invalid_token"))?;
+
+    // Tokens for resource servers aren't accepted, the admin API is this server's own
+    let mut validation = Validation::new(Algorithm::HS256);
+    validation.set_issuer(&[issuer_url()]);
+    validation.set_audience(&[issuer_url()]);
+
+    let token_data = decode::<AdminTokenClaims>(
+        token,
+        &DecodingKey::from_secret(jwt_secret().as_bytes()),
+        &validation,
+    )
+    .map_err(|err| {
+        warn!("Invalid access token for the admin API: {}", err);
+        (StatusCode::UNAUTHORIZED, "invalid_token")
+    })?;
+
+    // Only access tokens, other tokens signed with the same key aren't accepted
+    if token_data.header.typ.as_deref() != Some("at+jwt") {
+        return Err((StatusCode::UNAUTHORIZED, "invalid_token"));
+    }
+
+    let admin_scope = admin_scope();
+    if !token_data
+        .claims
+        .scope
+        .split_whitespace()
+        .any(|s| s == admin_scope)
+    {
+        warn!(
+            "Access token of user {} without the admin scope",
+            token_data.claims.sub
+        );
+        return Err((StatusCode::FORBIDDEN, "insufficient_scope"));
+    }
+
+    // Disabling an admin or taking away their role takes effect before the token expires
+    let admin = match token_data.claims.sub.parse::<u32>() {
+        Ok(admin_id) => {
+            GLOBAL_DATABASE
+                .get()
+                .unwrap()
+                .get_user_by_id(&admin_id)
+                .await
+        }
+        Err(_) => None,
+    };
+    let admin = match admin {
+        Some(admin) if admin.enabled => admin,
+        _ => {
+            warn!(
+                "Access token of unknown or disabled user {} for the admin API",
+                token_data.claims.sub
+            );
+            return Err((StatusCode::UNAUTHORIZED, "invalid_token"));
+        }
+    };
+    if !get_scope(&admin_scope)
+        .await
+        .is_some_and(|scope| scope.is_permitted(&admin))
+    {
+        warn!("User {} is no longer permitted the admin scope", admin.id);
+        return Err((StatusCode::FORBIDDEN, "insufficient_scope"));
+    }
+
+    Ok(token_data.claims.sub)
+}
+
+/// Records a change made through the admin API in the audit log, with the admin who made it.
+pub async fn record_admin_event(event: &str, admin_id: &str, user_id: Option<u32>, subject: &str) {
+    GLOBAL_DATABASE
+        .get()
+        .unwrap()
+        .record_audit_event(
+            event,
+            user_id,
+            subject,
+            None,
+            Some(&format!("admin={}", admin_id)),
+        )
+        .await;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn params(page: Option<i64>, per_page: Option<i64>, search: Option<&str>) -> ListParams {
+        ListParams {
+            page,
+            per_page,
+            search: search.map(str::to_string),
+        }
+    }
+
+    #[test]
+    fn list_params_default_to_the_first_page() {
+        let params = params(None, None, None);
+        assert_eq!(params.page(), 1);
+        assert_eq!(params.per_page(), 20);
+        assert_eq!(params.offset(), 0);
+        assert_eq!(params.search(), "");
+    }
+
+    #[test]
+    fn list_params_offset_by_whole_pages() {
+        assert_eq!(params(Some(3), Some(25), None).offset(), 50);
+        assert_eq!(params(Some(2), None, None).offset(), 20);
+    }
+
+    #[test]
+    fn list_params_are_clamped() {
+        let params = params(Some(-4), Some(1000), None);
+        assert_eq!(params.page(), 1);
+        assert_eq!(params.per_page(), MAX_PER_PAGE);
+        assert_eq!(params.offset(), 0);
+
+        assert_eq!(self::params(Some(0), Some(0), None).per_page(), 1);
+        assert_eq!(self::params(Some(i64::MAX), None, None).offset(), i64::MAX);
+    }
+
+    #[test]
+    fn list_params_trim_the_search() {
+        assert_eq!(
+            params(None, None, Some("  alice@example.com ")).search(),
+            "alice@example.com"
+        );
+    }
+}
@@ -0,0 +1,97 @@
+pub mod ldap;
+
+use crate::authenticators::ldap::LdapAuthenticator;
+use crate::config::ldap_url;
+use crate::passwords::authenticate_user;
+use crate::storage::{Client, User};
+use crate::{GLOBAL_AUTHENTICATORS, GLOBAL_DATABASE};
+use async_trait::async_trait;
+use log::warn;
+
+// Used by clients that don't configure their authenticators
+const DEFAULT_AUTHENTICATOR: &str = "database";
+
+/// A backend verifying the credentials users sign in with.
+#[async_trait]
+pub trait Authenticator: Send + Sync {
+    /// The name clients refer to the backend by.
+    fn name(&self) -> &'static str;
+
+    /// Verifies the credentials and returns the user they belong to.
+    async fn authenticate(&self, login: &str, password: &str) -> Option<User>;
+}
+
+/// Users with a password hash in the `users` table. Users linked to an LDAP entry are left to
+/// the directory.
+pub struct DatabaseAuthenticator;
+
+#[async_trait]
+impl Authenticator for DatabaseAuthenticator {
+    fn name(&self) -> &'static str {
+        DEFAULT_AUTHENTICATOR
+    }
+
+    async fn authenticate(&self, login: &str, password: &str) -> Option<User> {
+        let user = authenticate_user(login, password).await?;
+
+        match GLOBAL_DATABASE
+            .get()
+            .unwrap()
+            .get_credential_source(&user.id)
+            .await
+        {
+            Some(source) if source.allows_local_login() => Some(user),
+            _ => {
+                warn!("Local password login of directory user {}", user.id);
+                None
+            }
+        }
+    }
+}
+
+/// Creates the authentication backends that are configured.
+pub fn authenticators_from_config() -> Vec<Box<dyn Authenticator>> {
+    let mut authenticators: Vec<Box<dyn Authenticator>> = vec![Box::new(DatabaseAuthenticator)];
+
+    if let Some(url) = ldap_url() {
+        authenticators.push(Box::new(LdapAuthenticator::from_config(url)));
+    }
+
+    authenticators
+}
+
+/// Tries the authentication backends of the client in order, the first one accepting the
+/// credentials signs the user in.
+pub async fn authenticate(client_data: &Client, login: &str, password: &str) -> Option<User> {
+    let default_names = vec![DEFAULT_AUTHENTICATOR.to_string()];
+    let names = if client_data.authenticators.is_empty() {
+        &default_names
+    } else {
+        &client_data.authenticators
+    };
+
+    let authenticators = GLOBAL_AUTHENTICATORS.get().unwrap();
+    for name in names {
+        match authenticators
+            .iter()
+            .find(|authenticator| authenticator.name() == name)
+        {
+            Some(authenticator) => {
+                if let Some(user) = authenticator.authenticate(login, password).await {
+                    // Disabled users are rejected like invalid credentials
+                    if !user.enabled {
+                        warn!("Login attempt of disabled user {}", user.id);
+                        return None;
+                    }
+                    return Some(user);
+                }
+            }
+            None => warn!(
+                "Client {} uses the unavailable authenticator {}",
+                client_data.id, name
+            ),
+        }
+    }
+
+    None
+}
@@ -0,0 +1,314 @@
+use crate::authenticators::Authenticator;
+use crate::config::{
+    ldap_attribute_map, ldap_base_dn, ldap_bind_dn, ldap_bind_password, ldap_id_attribute,
+    ldap_user_filter,
+};
+use crate::passwords::UNUSABLE_PASSWORD_HASH;
+use crate::storage::User;
+use crate::GLOBAL_DATABASE;
+use async_trait::async_trait;
+use ldap3::{ldap_escape, LdapConnAsync, LdapConnSettings, Scope, SearchEntry};
+use log::{debug, info, warn};
+use std::time::Duration;
+
+/// Users of an LDAP directory. The entry of the user is searched for, optionally bound as a
+/// service account, and the password is verified by binding as that entry. Users get a shadow
+/// account in the `users` table, linked to the entry by its ID attribute, with the mapped
+/// attributes synced at every sign in. Existing local accounts are never adopted.
+pub struct LdapAuthenticator {
+    url: String,
+    bind_dn: Option<String>,
+    bind_password: String,
+    base_dn: String,
+    user_filter: String,
+    id_attribute: String,
+    attribute_map: Vec<(String, String)>,
+}
+
+impl LdapAuthenticator {
+    pub fn from_config(url: String) -> Self {
+        LdapAuthenticator {
+            url,
+            bind_dn: ldap_bind_dn(),
+            bind_password: ldap_bind_password(),
+            base_dn: ldap_base_dn(),
+            user_filter: ldap_user_filter(),
+            id_attribute: ldap_id_attribute(),
+            attribute_map: ldap_attribute_map(),
+        }
+    }
+
+    // Finds the entry of the user and returns it if the password binds to it
+    async fn verify(&self, login: &str, password: &str) -> Option<SearchEntry> {
+        let settings = LdapConnSettings::new().set_conn_timeout(Duration::from_secs(10));
+        let (conn, mut ldap) = LdapConnAsync::with_settings(settings, &self.url)
+            .await
+            .map_err(|err| warn!("Failed to connect to LDAP server: {}", err))
+            .ok()?;
+        ldap3::drive!(conn);
+
+        if let Some(bind_dn) = &self.bind_dn {
+            ldap.simple_bind(bind_dn, &self.bind_password)
+                .await
+                .and_then(|result| result.success())
+                .map_err(|err| warn!("LDAP service bind failed: {}", err))
+                .ok()?;
+        }
+
+        let filter = self.user_filter.replace("{login}", &ldap_escape(login));
+        // Operational attributes like entryUUID are only returned when asked for
+        let attributes = self
+            .attribute_map
+            .iter()
+            .map(|(_, attribute)| attribute.as_str())
+            .chain([self.id_attribute.as_str()])
+            .collect::<Vec<&str>>();
+        let (entries, _) = ldap
+            .search(&self.base_dn, Scope::Subtree, &filter, attributes)
+            .await
+            .and_then(|result| result.success())
+            .map_err(|err| warn!("LDAP search failed: {}", err))
+            .ok()?;
+
+        // Unknown and ambiguous logins are both rejected
+        if entries.len() != 1 {
+            debug!("LDAP search for {} found {} entries", login, entries.len());
+            return None;
+        }
+        let entry = SearchEntry::construct(entries.into_iter().next()?);
+
+        let bound = ldap
+            .simple_bind(&entry.dn, password)
+            .await
+            .and_then(|result| result.success())
+            .is_ok();
+        let _ = ldap.unbind().await;
+
+        bound.then_some(entry)
+    }
+
+    // The values of the attributes mapped to the claim, None if the claim isn't mapped
+    fn claim_values(&self, entry: &SearchEntry, claim: &str) -> Option<Vec<String>> {
+        let attributes = self
+            .attribute_map
+            .iter()
+            .filter(|(mapped_claim, _)| mapped_claim == claim)
+            .map(|(_, attribute)| attribute)
+            .collect::<Vec<&String>>();
+
+        if attributes.is_empty() {
+            return None;
+        }
+
+        Some(
+            entry
+                .attrs
+                .iter()
+                .filter(|(name, _)| {
+                    attributes
+                        .iter()
+                        .any(|attribute| attribute.eq_ignore_ascii_case(name))
+                })
+                .flat_map(|(_, values)| values.iter().cloned())
+                .collect(),
+        )
+    }
+
+    // The stable identifier of the entry, binary values like objectGUID are hex encoded
+    fn entry_id(&self, entry: &SearchEntry) -> String {
+        let value = entry
+            .attrs
+            .iter()
+            .find(|(name, _)| self.id_attribute.eq_ignore_ascii_case(name))
+            .and_then(|(_, values)| values.first().cloned());
+        let binary_value = || {
+            entry
+                .bin_attrs
+                .iter()
+                .find(|(name, _)| self.id_attribute.eq_ignore_ascii_case(name))
+                .and_then(|(_, values)| values.first())
+                .map(|value| value.iter().map(|byte| format!("{:02x}", byte)).collect())
+        };
+
+        match value.or_else(binary_value) {
+            Some(id) => format!("{}:{}", self.id_attribute.to_lowercase(), id),
+            None => format!("dn:{}", entry.dn.to_lowercase()),
+        }
+    }
+
+    async fn sync_user(&self, entry: &SearchEntry) -> Option<User> {
+        let email = match self
+            .claim_values(entry, "email")
+            .and_then(|values| values.into_iter().next())
+        {
+            Some(email) => email,
+            None => {
+                warn!("LDAP entry {} has no email address", entry.dn);
+                return None;
+            }
+        };
+
+        let database = GLOBAL_DATABASE.get().unwrap();
+        let entry_id = self.entry_id(entry);
+        let user = match database.get_ldap_user(&entry_id).await {
+            Some(user_id) => match database.get_user_by_id(&user_id).await {
+                Some(user) => user,
+                None => {
+                    warn!("The user of LDAP entry {} no longer exists", entry.dn);
+                    return None;
+                }
+            },
+            None => {
+                // A directory entry claiming the email address of a local account doesn't
+                // prove it owns that account
+                if database.get_user(&email).await.is_some() {
+                    warn!(
+                        "Refusing to link LDAP entry {} to the existing user with email {}",
+                        entry.dn, email
+                    );
+                    return None;
+                }
+
+                // The directory holds the password. The shadow account gets no usable one, and
+                // neither the database authenticator nor password resets accept linked users
+                let user_id = database
+                    .create_ldap_user(&entry_id, &entry.dn, &email, UNUSABLE_PASSWORD_HASH)
+                    .await?;
+                info!("Provisioned user {} from LDAP entry {}", user_id, entry.dn);
+                database.get_user_by_id(&user_id).await?
+            }
+        };
+
+        let groups = self
+            .claim_values(entry, "groups")
+            .unwrap_or_else(|| user.groups.clone());
+        let roles = self
+            .claim_values(entry, "roles")
+            .unwrap_or_else(|| user.roles.clone());
+        let entitlements = self
+            .claim_values(entry, "entitlements")
+            .unwrap_or_else(|| user.entitlements.clone());
+
+        if (&groups, &roles, &entitlements) == (&user.groups, &user.roles, &user.entitlements) {
+            return Some(user);
+        }
+
+        if !database
+            .update_user_attributes(&user.id, &groups, &roles, &entitlements)
+            .await
+        {
+            return None;
+        }
+
+        Some(User {
+            groups,
+            roles,
+            entitlements,
+            ..user
+        })
+    }
+}
+
+#[async_trait]
+impl Authenticator for LdapAuthenticator {
+    fn name(&self) -> &'static str {
+        "ldap"
+    }
+
+    async fn authenticate(&self, login: &str, password: &str) -> Option<User> {
+        // An empty password would be an unauthenticated bind, which most servers accept
+        if password.is_empty() {
+            return None;
+        }
+
+        let entry = self.verify(login, password).await?;
+        self.sync_user(&entry).await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::HashMap;
+
+    fn authenticator(attribute_map: &[(&str, &str)]) -> LdapAuthenticator {
+        LdapAuthenticator {
+            url: "ldap://localhost".to_string(),
+            bind_dn: None,
+            bind_password: String::new(),
+            base_dn: "ou=people,dc=example,dc=com".to_string(),
+            user_filter: "(mail={login})".to_string(),
+            id_attribute: "entryUUID".to_string(),
+            attribute_map: attribute_map
+                .iter()
+                .map(|(claim, attribute)| (claim.to_string(), attribute.to_string()))
+                .collect(),
+        }
+    }
+
+    fn entry(attrs: &[(&str, &[&str])]) -> SearchEntry {
+        SearchEntry {
+            dn: "uid=Alice,ou=people,dc=example,dc=com".to_string(),
+            attrs: attrs
+                .iter()
+                .map(|(name, values)| {
+                    (
+                        name.to_string(),
+                        values.iter().map(|value| value.to_string()).collect(),
+                    )
+                })
+                .collect(),
+            bin_attrs: HashMap::new(),
+        }
+    }
+
+    #[test]
+    fn maps_attributes_to_claims() {
+        let authenticator = authenticator(&[
+            ("email", "mail"),
+            ("groups", "memberOf"),
+            ("groups", "businessCategory"),
+        ]);
+        let entry = entry(&[
+            ("mail", &["alice@example.com"]),
+            ("MemberOf", &["cn=admins", "cn=staff"]),
+            ("businessCategory", &["sales"]),
+            ("title", &["Engineer"]),
+        ]);
+
+        assert_eq!(
+            authenticator.claim_values(&entry, "email"),
+            Some(vec!["alice@example.com".to_string()])
+        );
+        let mut groups = authenticator.claim_values(&entry, "groups").unwrap();
+        groups.sort();
+        assert_eq!(groups, ["cn=admins", "cn=staff", "sales"]);
+        // Unmapped claims keep the values of the user, mapped ones are cleared
+        assert_eq!(authenticator.claim_values(&entry, "roles"), None);
+        assert_eq!(
+            authenticator.claim_values(&self::entry(&[]), "groups"),
+            Some(vec![])
+        );
+    }
+
+    #[test]
+    fn identifies_entries_by_the_id_attribute() {
+        let authenticator = authenticator(&[("email", "mail")]);
+        let uuid = "f81d4fae-7dec-11d0-a765-00a0c91e6bf6";
+
+        assert_eq!(
+            authenticator.entry_id(&entry(&[("entryUUID", &[uuid])])),
+            format!("entryuuid:{}", uuid)
+        );
+        assert_eq!(
+            authenticator.entry_id(&entry(&[("mail", &["alice@example.com"])])),
+            "dn:uid=alice,ou=people,dc=example,dc=com"
+        );
+
+        let mut binary_entry = entry(&[]);
+        binary_entry
+            .bin_attrs
+            .insert("entryUUID".to_string(), vec![vec![0x0a, 0xff]]);
+        assert_eq!(authenticator.entry_id(&binary_entry), "entryuuid:0aff");
+    }
+}
@@ -0,0 +1,141 @@
+use crate::config::cookie_secret;
+use crate::cookies::{build_cookie, get_cookie};
+use crate::pages::get_login_error_html;
+use crate::webauthn::{decode_base64url, encode_base64url};
+use crate::GLOBAL_CACHE;
+use axum::http::HeaderMap;
+use axum::response::{IntoResponse, Response};
+use hmac::{Hmac, Mac};
+use log::warn;
+use rand::distributions::Alphanumeric;
+use rand::Rng;
+use sha2::Sha256;
+
+pub const BROWSER_COOKIE: &str = "browser_id";
+
+// As long as the authorization requests bound to the cookie are cached
+const BROWSER_COOKIE_TTL: u64 = 600;
+
+/// Binds an authorization request to the browser it was started in. The browser keeps the
+/// random ID of its signed cookie, so concurrent requests in other tabs stay valid.
+/// Returns the `Set-Cookie` value and the CSRF token for the forms of the request.
+pub fn bind_request(headers: &HeaderMap, request_id: &str) -> (String, String) {
+    let browser_id = get_browser_id(headers).unwrap_or_else(|| {
+        rand::thread_rng()
+            .sample_iter(&Alphanumeric)
+            .take(32)
+            .map(char::from)
+            .collect()
+    });
+
+    GLOBAL_CACHE
+        .get()
+        .unwrap()
+        .set_request_browser(request_id, &browser_id);
+
+    let cookie_value = format!("{}.{}", browser_id, encode_base64url(&sign(&browser_id)));
+    (
+        build_cookie(BROWSER_COOKIE, &cookie_value, BROWSER_COOKIE_TTL),
+        csrf_token(request_id, &browser_id),
+    )
+}
+
+/// The CSRF token for the forms of an authorization request, for the browser it is bound to.
+pub fn request_csrf_token(request_id: &str) -> String {
+    GLOBAL_CACHE
+        .get()
+        .unwrap()
+        .get_request_browser(request_id)
+        .map(|browser_id| csrf_token(request_id, &browser_id))
+        .unwrap_or_default()
+}
+
+/// Checks that the request comes from the browser the authorization request was started in.
+pub fn verify_request_binding(headers: &HeaderMap, request_id: &str) -> bool {
+    match (
+        get_browser_id(headers),
+        GLOBAL_CACHE.get().unwrap().get_request_browser(request_id),
+    ) {
+        (Some(browser_id), Some(bound_browser_id)) => browser_id == bound_browser_id,
+        _ => false,
+    }
+}
+
+/// Checks the browser binding and the CSRF token submitted with a form of the request.
+pub fn verify_csrf_token(headers: &HeaderMap, request_id: &str, token: &str) -> bool {
+    if !verify_request_binding(headers, request_id) {
+        return false;
+    }
+
+    let (Some(browser_id), Some(token)) = (get_browser_id(headers), decode_base64url(token)) else {
+        return false;
+    };
+
+    new_mac(&format!("CSRF.{}.{}", request_id, browser_id))
+        .verify_slice(&token)
+        .is_ok()
+}
+
+/// The CSRF token for confirming the logout of a browser SSO session, by the key in its cookie.
+pub fn logout_csrf_token(session_key: &str) -> String {
+    encode_base64url(&sign(&format!("LOGOUT.{}", session_key)))
+}
+
+pub fn verify_logout_csrf_token(session_key: &str, token: &str) -> bool {
+    let Some(token) = decode_base64url(token) else {
+        return false;
+    };
+
+    new_mac(&format!("LOGOUT.{}", session_key))
+        .verify_slice(&token)
+        .is_ok()
+}
+
+/// Checks a form posted for an authorization request. The form must be posted by the browser
+/// that started the request, with its CSRF token, so another site can't submit it in the name
+/// of the user. Otherwise returns the login error page.
+// The error is the response handlers return right away, boxing it gains nothing
+#[allow(clippy::result_large_err)]
+pub fn require_csrf_token(
+    headers: &HeaderMap,
+    request_id: &str,
+    token: &str,
+) -> Result<(), Response> {
+    if verify_csrf_token(headers, request_id, token) {
+        return Ok(());
+    }
+
+    warn!(
+        "Form of request {} failed the browser binding check",
+        request_id
+    );
+    Err(get_login_error_html().into_response())
+}
+
+// The browser ID of a correctly signed cookie
+fn get_browser_id(headers: &HeaderMap) -> Option<String> {
+    let cookie = get_cookie(headers, BROWSER_COOKIE)?;
+    let (browser_id, signature) = cookie.split_once('.')?;
+
+    new_mac(browser_id)
+        .verify_slice(&decode_base64url(signature)?)
+        .ok()?;
+
+    Some(browser_id.to_string())
+}
+
+// The CSRF token is derived from the request and the browser, so it doesn't need to be stored
+fn csrf_token(request_id: &str, browser_id: &str) -> String {
+    encode_base64url(&sign(&format!("CSRF.{}.{}", request_id, browser_id)))
+}
+
+fn sign(message: &str) -> Vec<u8> {
+    new_mac(message).finalize().into_bytes().to_vec()
+}
+
+fn new_mac(message: &str) -> Hmac<Sha256> {
+    let mut mac = Hmac::<Sha256>::new_from_slice(cookie_secret().as_bytes())
+        .expect("HMAC accepts keys of any length");
+    mac.update(message.as_bytes());
+    mac
+}
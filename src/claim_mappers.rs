@@ -0,0 +1,193 @@
+use crate::config::claim_mapping_attributes;
+use crate::storage::{ClaimMapping, Client, User};
+use crate::{GLOBAL_CLAIMS_MAPPERS, GLOBAL_DATABASE};
+use async_trait::async_trait;
+use log::warn;
+use serde_json::{Map, Value};
+
+/// Protocol claims, the claims issued from users and the other standard claims of OpenID
+/// Connect Core section 5.1, mappers can't override them. Relying parties expect the standard
+/// claims to mean what the specification says.
+pub const RESERVED_CLAIMS: [&str; 41] = [
+    "iss",
+    "sub",
+    "aud",
+    "exp",
+    "iat",
+    "nbf",
+    "jti",
+    "auth_time",
+    "client_id",
+    "scope",
+    "amr",
+    "acr",
+    "azp",
+    "nonce",
+    "sid",
+    "at_hash",
+    "c_hash",
+    "cnf",
+    "events",
+    "groups",
+    "roles",
+    "entitlements",
+    "name",
+    "given_name",
+    "family_name",
+    "middle_name",
+    "nickname",
+    "preferred_username",
+    "profile",
+    "picture",
+    "website",
+    "email",
+    "email_verified",
+    "gender",
+    "birthdate",
+    "zoneinfo",
+    "locale",
+    "phone_number",
+    "phone_number_verified",
+    "address",
+    "updated_at",
+];
+
+/// Where mapped claims are issued.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum ClaimsTarget {
+    AccessToken,
+    IdToken,
+    Userinfo,
+}
+
+impl ClaimsTarget {
+    /// The name used in the `targets` of claim mappings.
+    pub fn name(&self) -> &'static str {
+        match self {
+            ClaimsTarget::AccessToken => "access_token",
+            ClaimsTarget::IdToken => "id_token",
+            ClaimsTarget::Userinfo => "userinfo",
+        }
+    }
+}
+
+/// Adds custom claims about the user, e.g. a tenant ID or feature flags.
+#[async_trait]
+pub trait ClaimsMapper: Send + Sync {
+    /// Returns the claims to add for the client and the granted scopes.
+    async fn map_claims(
+        &self,
+        target: ClaimsTarget,
+        client_data: &Client,
+        scope: &str,
+        user: &User,
+    ) -> Map<String, Value>;
+}
+
+/// Maps the claims configured in the `claim_mappings` table.
+pub struct DeclarativeClaimsMapper;
+
+#[async_trait]
+impl ClaimsMapper for DeclarativeClaimsMapper {
+    async fn map_claims(
+        &self,
+        target: ClaimsTarget,
+        client_data: &Client,
+        scope: &str,
+        user: &User,
+    ) -> Map<String, Value> {
+        let mappings = client_data
+            .claim_mappings
+            .iter()
+            .filter(|mapping| mapping.targets.iter().any(|t| t == target.name()))
+            .filter(|mapping| {
+                mapping
+                    .scope
+                    .as_ref()
+                    .is_none_or(|mapped_scope| scope.split_whitespace().any(|s| s == mapped_scope))
+            })
+            .collect::<Vec<&ClaimMapping>>();
+
+        // The columns of the user are only loaded if they are mapped
+        let attributes = if mappings
+            .iter()
+            .any(|mapping| mapping.source == "user_attribute")
+        {
+            GLOBAL_DATABASE
+                .get()
+                .unwrap()
+                .get_user_attributes(&user.id, &claim_mapping_attributes())
+                .await
+        } else {
+            Map::new()
+        };
+
+        let mut claims = Map::new();
+        for mapping in mappings {
+            let value = match mapping.source.as_str() {
+                // Only the allowed columns are loaded, others are left out like empty ones
+                "user_attribute" => match attributes.get(&mapping.value) {
+                    Some(Value::Null) | None => continue,
+                    Some(value) => value.clone(),
+                },
+                "static" => serde_json::from_str(&mapping.value)
+                    .unwrap_or_else(|_| Value::from(mapping.value.clone())),
+                "group_membership" => Value::from(
+                    user.groups.contains(&mapping.value) || user.roles.contains(&mapping.value),
+                ),
+                source => {
+                    warn!("Unknown claim mapping source {}", source);
+                    continue;
+                }
+            };
+            claims.insert(mapping.claim.clone(), value);
+        }
+
+        claims
+    }
+}
+
+/// Creates the claims mappers, later mappers override the claims of earlier ones.
+pub fn claims_mappers_from_config() -> Vec<Box<dyn ClaimsMapper>> {
+    vec![Box::new(DeclarativeClaimsMapper)]
+}
+
+/// Adds the claims of every mapper. Reserved claims are never overridden.
+pub async fn apply_claims_mappers(
+    target: ClaimsTarget,
+    client_data: &Client,
+    scope: &str,
+    user: &User,
+    claims: &mut Map<String, Value>,
+) {
+    for mapper in GLOBAL_CLAIMS_MAPPERS.get().unwrap() {
+        let mapped_claims = mapper.map_claims(target, client_data, scope, user).await;
+        for (name, value) in mapped_claims {
+            if RESERVED_CLAIMS.contains(&name.as_str()) {
+                warn!(
+                    "Claims mapper tried to override the reserved claim {} for client {}",
+                    name, client_data.id
+                );
+                continue;
+            }
+            claims.insert(name, value);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::claims::SUPPORTED_CLAIMS;
+
+    #[test]
+    fn user_claims_are_reserved() {
+        for claim in SUPPORTED_CLAIMS {
+            assert!(
+                RESERVED_CLAIMS.contains(&claim),
+                "{} is not reserved",
+                claim
+            );
+        }
+    }
+}
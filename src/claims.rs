@@ -0,0 +1,333 @@
+use crate::storage::User;
+use log::debug;
+use serde::{Deserialize, Serialize};
+use serde_json::{Map, Value};
+use std::collections::HashMap;
+
+/// A request for an individual claim (OpenID Connect Core section 5.5.1).
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+pub struct ClaimRequest {
+    #[serde(default)]
+    pub essential: bool,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub value: Option<Value>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub values: Option<Vec<Value>>,
+}
+
+impl ClaimRequest {
+    /// Whether the value satisfies the requested value or values, if any.
+    pub fn allows(&self, value: &Value) -> bool {
+        self.value
+            .as_ref()
+            .is_none_or(|requested| requested == value)
+            && self
+                .values
+                .as_ref()
+                .is_none_or(|requested| requested.contains(value))
+    }
+}
+
+/// The `claims` request parameter, naming the claims to return in the ID token and from
+/// the userinfo endpoint (OpenID Connect Core section 5.5). `null` requests a claim as is.
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+pub struct ClaimsRequest {
+    #[serde(default)]
+    pub userinfo: HashMap<String, Option<ClaimRequest>>,
+    #[serde(default)]
+    pub id_token: HashMap<String, Option<ClaimRequest>>,
+}
+
+impl ClaimsRequest {
+    pub fn parse(claims: &str) -> Option<Self> {
+        serde_json::from_str(claims).ok()
+    }
+}
+
+/// The claims that can be returned about users. Groups, roles and entitlements are in the
+/// access token, they aren't claims about the user a scope or claims request can ask for.
+pub const SUPPORTED_CLAIMS: [&str; 6] = [
+    "sub",
+    "email",
+    "email_verified",
+    "name",
+    "phone_number",
+    "phone_number_verified",
+];
+
+// The claims requested by a scope (OpenID Connect Core section 5.4)
+fn scope_claims(scope: &str) -> &'static [&'static str] {
+    match scope {
+        "profile" => &["name"],
+        "email" => &["email", "email_verified"],
+        "phone" => &["phone_number", "phone_number_verified"],
+        _ => &[],
+    }
+}
+
+/// The claims about the user requested on their own that the scope doesn't cover, the user has
+/// to consent to these separately.
+pub fn unscoped_claims(scope: &str, request: &ClaimsRequest) -> Vec<String> {
+    let covered = scope
+        .split_whitespace()
+        .flat_map(scope_claims)
+        .collect::<Vec<&&str>>();
+    let mut claims = request
+        .userinfo
+        .keys()
+        .chain(request.id_token.keys())
+        .filter(|name| name.as_str() != "sub" && SUPPORTED_CLAIMS.contains(&name.as_str()))
+        .filter(|name| !covered.contains(&&name.as_str()))
+        .cloned()
+        .collect::<Vec<String>>();
+    claims.sort();
+    claims.dedup();
+    claims
+}
+
+/// Leaves out the requested claims the scope doesn't cover and that weren't approved.
+pub fn restrict_claims(
+    requested: &HashMap<String, Option<ClaimRequest>>,
+    scope: &str,
+    approved: &[String],
+) -> HashMap<String, Option<ClaimRequest>> {
+    requested
+        .iter()
+        .filter(|(name, _)| {
+            approved.contains(name)
+                || scope
+                    .split_whitespace()
+                    .any(|scope| scope_claims(scope).contains(&name.as_str()))
+        })
+        .map(|(name, request)| (name.clone(), request.clone()))
+        .collect()
+}
+
+/// Describes a claim to the user asked to consent to it.
+pub fn claim_description(name: &str) -> &'static str {
+    match name {
+        "email" => "Your email address",
+        "email_verified" => "Whether your email address is verified",
+        "name" => "Your name",
+        "phone_number" => "Your phone number",
+        "phone_number_verified" => "Whether your phone number is verified",
+        _ => "Information about you",
+    }
+}
+
+// The claims about the user that have a value
+fn user_claims(user: &User) -> Map<String, Value> {
+    let mut claims = Map::new();
+    claims.insert("email".to_string(), Value::from(user.email.clone()));
+    claims.insert(
+        "email_verified".to_string(),
+        Value::from(user.email_verified),
+    );
+    if let Some(name) = &user.name {
+        claims.insert("name".to_string(), Value::from(name.clone()));
+    }
+    if let Some(phone_number) = &user.phone_number {
+        claims.insert(
+            "phone_number".to_string(),
+            Value::from(phone_number.clone()),
+        );
+        claims.insert(
+            "phone_number_verified".to_string(),
+            Value::from(user.phone_number_verified),
+        );
+    }
+
+    claims
+}
+
+/// Selects the claims about the user requested by the scopes and the claims request. The
+/// claims request can only pick claims of the granted scopes and the approved claims, the user
+/// consented to those. Claims requested with a value or values are only returned if they
+/// match, essential claims that can't be returned don't fail the request.
+pub fn select_claims(
+    user: &User,
+    scope: &str,
+    requested: Option<&HashMap<String, Option<ClaimRequest>>>,
+    approved: &[String],
+) -> Map<String, Value> {
+    let available = user_claims(user);
+    let granted = scope
+        .split_whitespace()
+        .flat_map(scope_claims)
+        .collect::<Vec<&&str>>();
+    let mut selected = Map::new();
+
+    for name in &granted {
+        if let Some(value) = available.get(**name) {
+            selected.insert(name.to_string(), value.clone());
+        }
+    }
+
+    for (name, request) in requested.into_iter().flatten() {
+        let request = request.clone().unwrap_or_default();
+        match available
+            .get(name)
+            .filter(|_| granted.contains(&&name.as_str()) || approved.contains(name))
+            .filter(|value| request.allows(value))
+        {
+            Some(value) => {
+                selected.insert(name.clone(), value.clone());
+            }
+            None => {
+                // Also when the scope would return it with another value
+                selected.remove(name);
+                if request.essential {
+                    debug!(
+                        "Essential claim {} is not available for user {}",
+                        name, user.id
+                    );
+                }
+            }
+        }
+    }
+
+    selected
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn user() -> User {
+        User {
+            id: 1,
+            email: "alice@example.com".to_string(),
+            password_hash: String::new(),
+            groups: vec!["staff".to_string()],
+            roles: Vec::new(),
+            entitlements: Vec::new(),
+            mfa_required: false,
+            email_verified: true,
+            enabled: true,
+            name: Some("Alice".to_string()),
+            phone_number: Some("+14155550100".to_string()),
+            phone_number_verified: false,
+        }
+    }
+
+    fn requested(claims: Value) -> HashMap<String, Option<ClaimRequest>> {
+        serde_json::from_value(claims).unwrap()
+    }
+
+    #[test]
+    fn parses_claims_requests() {
+        let request = ClaimsRequest::parse(
+            r#"{
+                "userinfo": {
+                    "email": {"essential": true},
+                    "phone_number": null
+                },
+                "id_token": {
+                    "email_verified": {"value": true},
+                    "groups": {"values": ["staff", "admins"]}
+                }
+            }"#,
+        )
+        .unwrap();
+
+        assert!(request.userinfo["email"].as_ref().unwrap().essential);
+        assert!(request.userinfo["phone_number"].is_none());
+        let email_verified = request.id_token["email_verified"].as_ref().unwrap();
+        assert!(!email_verified.essential);
+        assert_eq!(email_verified.value, Some(json!(true)));
+        assert_eq!(
+            request.id_token["groups"].as_ref().unwrap().values,
+            Some(vec![json!("staff"), json!("admins")])
+        );
+
+        let request = ClaimsRequest::parse(r#"{"id_token": {}}"#).unwrap();
+        assert!(request.userinfo.is_empty());
+        assert!(ClaimsRequest::parse("email").is_none());
+        assert!(ClaimsRequest::parse(r#"{"userinfo": {"email": true}}"#).is_none());
+    }
+
+    #[test]
+    fn claim_requests_match_values() {
+        let request = ClaimRequest {
+            values: Some(vec![json!("a"), json!("b")]),
+            ..Default::default()
+        };
+        assert!(request.allows(&json!("b")));
+        assert!(!request.allows(&json!("c")));
+        assert!(ClaimRequest::default().allows(&json!("c")));
+    }
+
+    #[test]
+    fn selects_the_claims_of_the_scopes() {
+        let claims = select_claims(&user(), "openid profile phone", None, &[]);
+        assert_eq!(
+            Value::Object(claims),
+            json!({
+                "name": "Alice",
+                "phone_number": "+14155550100",
+                "phone_number_verified": false,
+            })
+        );
+    }
+
+    #[test]
+    fn claims_requests_are_limited_to_the_granted_scopes() {
+        let requested = requested(json!({
+            "email": {"essential": true},
+            "phone_number": null,
+            "groups": null,
+        }));
+        let claims = select_claims(&user(), "openid phone", Some(&requested), &[]);
+
+        assert_eq!(claims.get("phone_number"), Some(&json!("+14155550100")));
+        assert!(claims.get("email").is_none());
+        assert!(claims.get("groups").is_none());
+    }
+
+    #[test]
+    fn approved_claims_are_returned_without_their_scope() {
+        let requested = requested(json!({
+            "email_verified": {"essential": true},
+            "phone_number": null,
+        }));
+        let claims = select_claims(
+            &user(),
+            "openid",
+            Some(&requested),
+            &["email_verified".to_string()],
+        );
+
+        assert_eq!(Value::Object(claims), json!({"email_verified": true}));
+    }
+
+    #[test]
+    fn claims_outside_the_scopes_need_their_own_consent() {
+        let request = ClaimsRequest::parse(
+            r#"{
+                "userinfo": {"phone_number": null, "sub": null, "groups": null},
+                "id_token": {"email_verified": null, "name": null, "phone_number": null}
+            }"#,
+        )
+        .unwrap();
+
+        assert_eq!(
+            unscoped_claims("openid profile", &request),
+            vec!["email_verified", "phone_number"]
+        );
+        assert!(unscoped_claims("openid profile email phone", &request).is_empty());
+    }
+
+    #[test]
+    fn claims_requested_with_another_value_are_left_out() {
+        let requested = requested(json!({
+            "email_verified": {"value": false},
+            "name": {"values": ["Alice", "Bob"]},
+        }));
+        let claims = select_claims(&user(), "profile email", Some(&requested), &[]);
+
+        assert!(claims.get("email_verified").is_none());
+        assert_eq!(claims.get("email"), Some(&json!("alice@example.com")));
+        assert_eq!(claims.get("name"), Some(&json!("Alice")));
+    }
+}
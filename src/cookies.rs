@@ -0,0 +1,24 @@
+use axum::http::header::COOKIE;
+use axum::http::HeaderMap;
+
+pub const SESSION_COOKIE: &str = "session_id";
+
+/// Returns the value of a cookie sent with the request.
+pub fn get_cookie(headers: &HeaderMap, name: &str) -> Option<String> {
+    headers
+        .get_all(COOKIE)
+        .iter()
+        .filter_map(|header| header.to_str().ok())
+        .flat_map(|header| header.split(';'))
+        .filter_map(|cookie| cookie.trim().split_once('='))
+        .find(|(cookie_name, _)| *cookie_name == name)
+        .map(|(_, value)| value.to_string())
+}
+
+/// Builds a secure, HttpOnly `Set-Cookie` header value. A `max_age` of zero removes the cookie.
+pub fn build_cookie(name: &str, value: &str, max_age: u64) -> String {
+    format!(
+        "{}={}; Max-Age={}; Path=/; HttpOnly; Secure; SameSite=Lax",
+        name, value, max_age
+    )
+}
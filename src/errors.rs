@@ -1,85 +1,253 @@
-use axum::response::{IntoResponse, Redirect, Response};
-use urlencoding::encode;
+use crate::response_modes::{authorization_response, ResponseMode};
+use axum::http::header::WWW_AUTHENTICATE;
+use axum::http::StatusCode;
+use axum::response::{IntoResponse, Response};
 
 fn create_error_response(
-    redirect_uri: &String,
+    redirect_uri: &str,
+    response_mode: ResponseMode,
     error: &str,
     description: &str,
     state: Option<&String>,
 ) -> Response {
-    let encoded_description = encode(description);
-    let mut error_redirect_uri = format!(
-        "{}?error={}&error_description={}",
-        redirect_uri, error, encoded_description
-    );
+    let mut params = vec![("error", error), ("error_description", description)];
 
     if let Some(state) = state {
-        error_redirect_uri.push_str("&state=");
-        error_redirect_uri.push_str(state);
+        params.push(("state", state));
     }
 
-    Redirect::permanent(&error_redirect_uri).into_response()
+    authorization_response(redirect_uri, response_mode, &params)
 }
 
-pub fn unsupported_response_type_error(redirect_uri: &String, state: Option<&String>) -> Response {
+pub fn unsupported_response_type_error(
+    redirect_uri: &str,
+    response_mode: ResponseMode,
+    state: Option<&String>,
+) -> Response {
     create_error_response(
         redirect_uri,
+        response_mode,
         "unsupported_response_type",
         "The response_type parameter is missing or unsupported",
         state,
     )
 }
 
-pub fn invalid_client_error(redirect_uri: &String, state: Option<&String>) -> Response {
+pub fn missing_scope_error(
+    redirect_uri: &str,
+    response_mode: ResponseMode,
+    state: Option<&String>,
+) -> Response {
     create_error_response(
         redirect_uri,
-        "invalid_client",
-        "The client_id parameter is missing",
+        response_mode,
+        "invalid_scope",
+        "The scope parameter is missing",
         state,
     )
 }
 
-pub fn missing_scope_error(redirect_uri: &String, state: Option<&String>) -> Response {
+pub fn invalid_scope_error(
+    redirect_uri: &str,
+    response_mode: ResponseMode,
+    state: Option<&String>,
+) -> Response {
     create_error_response(
         redirect_uri,
+        response_mode,
         "invalid_scope",
-        "The scope parameter is missing",
+        "The scope parameter contains unknown scopes",
         state,
     )
 }
 
-pub fn invalid_scope_error(redirect_uri: &String, state: Option<&String>) -> Response {
+pub fn unsupported_response_mode_error(
+    redirect_uri: &str,
+    response_mode: ResponseMode,
+    state: Option<&String>,
+) -> Response {
     create_error_response(
         redirect_uri,
-        "invalid_scope",
-        "The scope parameter contains unknown scopes",
+        response_mode,
+        "invalid_request",
+        "The response_mode parameter is unsupported",
         state,
     )
 }
 
-pub fn database_error(redirect_uri: &String, state: Option<&String>) -> Response {
+pub fn invalid_target_error(
+    redirect_uri: &str,
+    response_mode: ResponseMode,
+    state: Option<&String>,
+) -> Response {
     create_error_response(
         redirect_uri,
-        "server_error",
-        "A database error occurred",
+        response_mode,
+        "invalid_target",
+        "The resource parameter is invalid or unknown",
         state,
     )
 }
 
-pub fn invalid_redirect_uri_error(redirect_uri: &String, state: Option<&String>) -> Response {
+pub fn failed_authorization_error(
+    redirect_uri: &str,
+    response_mode: ResponseMode,
+    state: Option<&String>,
+) -> Response {
     create_error_response(
         redirect_uri,
-        "invalid_redirect_uri",
-        "The redirect uri parameter is invalid",
+        response_mode,
+        "access_denied",
+        "The authorization request has been denied",
         state,
     )
 }
 
-pub fn failed_authorization_error(redirect_uri: &String, state: Option<&String>) -> Response {
+pub fn login_throttled_error(
+    redirect_uri: &str,
+    response_mode: ResponseMode,
+    state: Option<&String>,
+) -> Response {
     create_error_response(
         redirect_uri,
+        response_mode,
         "access_denied",
-        "The authorization request has been denied",
+        "Too many failed login attempts, try again later",
+        state,
+    )
+}
+
+pub fn scopes_not_permitted_error(
+    redirect_uri: &str,
+    response_mode: ResponseMode,
+    state: Option<&String>,
+) -> Response {
+    create_error_response(
+        redirect_uri,
+        response_mode,
+        "access_denied",
+        "The user is not permitted any of the requested scopes",
         state,
     )
 }
+
+pub fn invalid_prompt_error(
+    redirect_uri: &str,
+    response_mode: ResponseMode,
+    state: Option<&String>,
+) -> Response {
+    create_error_response(
+        redirect_uri,
+        response_mode,
+        "invalid_request",
+        "The prompt parameter is invalid",
+        state,
+    )
+}
+
+pub fn invalid_max_age_error(
+    redirect_uri: &str,
+    response_mode: ResponseMode,
+    state: Option<&String>,
+) -> Response {
+    create_error_response(
+        redirect_uri,
+        response_mode,
+        "invalid_request",
+        "The max_age parameter is invalid",
+        state,
+    )
+}
+
+pub fn invalid_claims_error(
+    redirect_uri: &str,
+    response_mode: ResponseMode,
+    state: Option<&String>,
+) -> Response {
+    create_error_response(
+        redirect_uri,
+        response_mode,
+        "invalid_request",
+        "The claims parameter is invalid",
+        state,
+    )
+}
+
+pub fn invalid_id_token_hint_error(
+    redirect_uri: &str,
+    response_mode: ResponseMode,
+    state: Option<&String>,
+) -> Response {
+    create_error_response(
+        redirect_uri,
+        response_mode,
+        "invalid_request",
+        "The id_token_hint parameter is invalid",
+        state,
+    )
+}
+
+pub fn login_required_error(
+    redirect_uri: &str,
+    response_mode: ResponseMode,
+    state: Option<&String>,
+) -> Response {
+    create_error_response(
+        redirect_uri,
+        response_mode,
+        "login_required",
+        "The user must log in",
+        state,
+    )
+}
+
+pub fn consent_required_error(
+    redirect_uri: &str,
+    response_mode: ResponseMode,
+    state: Option<&String>,
+) -> Response {
+    create_error_response(
+        redirect_uri,
+        response_mode,
+        "consent_required",
+        "The user must consent to the requested scopes",
+        state,
+    )
+}
+
+pub fn interaction_required_error(
+    redirect_uri: &str,
+    response_mode: ResponseMode,
+    state: Option<&String>,
+) -> Response {
+    create_error_response(
+        redirect_uri,
+        response_mode,
+        "interaction_required",
+        "The user must complete the login interactively",
+        state,
+    )
+}
+
+pub fn unauthorized_client_error(
+    redirect_uri: &str,
+    response_mode: ResponseMode,
+    state: Option<&String>,
+) -> Response {
+    create_error_response(
+        redirect_uri,
+        response_mode,
+        "unauthorized_client",
+        "The client is not allowed to use the authorization code grant",
+        state,
+    )
+}
+
+/// An error of a request with a bearer token (RFC 6750 section 3).
+pub fn bearer_error(status: StatusCode, error: &str) -> Response {
+    (
+        status,
+        [(WWW_AUTHENTICATE, format!("Bearer error=\"{}\"", error))],
+    )
+        .into_response()
+}
@@ -0,0 +1,420 @@
+use crate::config::federation_redirect_uri;
+use crate::storage::IdentityProvider;
+use crate::webauthn::{decode_base64url, encode_base64url};
+use log::warn;
+use rand::distributions::Alphanumeric;
+use rand::Rng;
+use serde::Deserialize;
+use serde_json::Value;
+use sha2::{Digest, Sha256};
+use std::sync::OnceLock;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+/// The endpoints of an upstream identity provider.
+pub struct ProviderEndpoints {
+    pub authorization_endpoint: String,
+    pub token_endpoint: String,
+    pub userinfo_endpoint: Option<String>,
+}
+
+/// The identity asserted by an upstream identity provider.
+pub struct UpstreamIdentity {
+    pub subject: String,
+    pub email: Option<String>,
+    pub email_verified: bool,
+}
+
+#[derive(Deserialize)]
+struct ProviderMetadata {
+    issuer: String,
+    authorization_endpoint: String,
+    token_endpoint: String,
+    userinfo_endpoint: Option<String>,
+}
+
+#[derive(Deserialize)]
+struct TokenResponse {
+    access_token: String,
+    id_token: Option<String>,
+}
+
+/// Generates a random value for the `state`, `nonce` and PKCE `code_verifier` parameters.
+pub fn generate_secret() -> String {
+    rand::thread_rng()
+        .sample_iter(&Alphanumeric)
+        .take(43)
+        .map(char::from)
+        .collect()
+}
+
+/// Gets the configured endpoints of the provider, discovering the missing ones from the
+/// OpenID Connect metadata of its issuer.
+pub async fn resolve_endpoints(provider: &IdentityProvider) -> Option<ProviderEndpoints> {
+    if let (Some(authorization_endpoint), Some(token_endpoint)) =
+        (&provider.authorization_endpoint, &provider.token_endpoint)
+    {
+        return Some(ProviderEndpoints {
+            authorization_endpoint: authorization_endpoint.clone(),
+            token_endpoint: token_endpoint.clone(),
+            userinfo_endpoint: provider.userinfo_endpoint.clone(),
+        });
+    }
+
+    let metadata_url = format!(
+        "{}/.well-known/openid-configuration",
+        provider.issuer.trim_end_matches('/')
+    );
+    let metadata: ProviderMetadata = get_json(http_client().get(&metadata_url)).await?;
+
+    // The metadata must be about the configured issuer (OpenID Connect Discovery section 4.3)
+    if metadata.issuer != provider.issuer {
+        warn!(
+            "Identity provider {} metadata has issuer {}",
+            provider.id, metadata.issuer
+        );
+        return None;
+    }
+
+    Some(ProviderEndpoints {
+        authorization_endpoint: provider
+            .authorization_endpoint
+            .clone()
+            .unwrap_or(metadata.authorization_endpoint),
+        token_endpoint: provider
+            .token_endpoint
+            .clone()
+            .unwrap_or(metadata.token_endpoint),
+        userinfo_endpoint: provider
+            .userinfo_endpoint
+            .clone()
+            .or(metadata.userinfo_endpoint),
+    })
+}
+
+/// Builds the URL sending the user to the provider, using PKCE with S256.
+pub fn authorization_url(
+    provider: &IdentityProvider,
+    endpoints: &ProviderEndpoints,
+    state: &str,
+    nonce: &str,
+    code_verifier: &str,
+) -> String {
+    let code_challenge = encode_base64url(&Sha256::digest(code_verifier.as_bytes()));
+    let redirect_uri = federation_redirect_uri();
+    let params = [
+        ("response_type", "code"),
+        ("client_id", provider.client_id.as_str()),
+        ("redirect_uri", redirect_uri.as_str()),
+        ("scope", provider.scopes.as_str()),
+        ("state", state),
+        ("nonce", nonce),
+        ("code_challenge", code_challenge.as_str()),
+        ("code_challenge_method", "S256"),
+    ];
+
+    let query = params
+        .iter()
+        .map(|(name, value)| format!("{}={}", name, urlencoding::encode(value)))
+        .collect::<Vec<String>>()
+        .join("&");
+    let separator = if endpoints.authorization_endpoint.contains('?') {
+        '&'
+    } else {
+        '?'
+    };
+
+    format!("{}{}{}", endpoints.authorization_endpoint, separator, query)
+}
+
+/// Exchanges the authorization code and returns the identity of the user. The ID token comes
+/// straight from the token endpoint over TLS, so its claims are checked but not its signature
+/// (OpenID Connect Core section 3.1.3.7). Plain OAuth 2.0 providers are asked for user info.
+pub async fn exchange_code(
+    provider: &IdentityProvider,
+    endpoints: &ProviderEndpoints,
+    code: &str,
+    code_verifier: &str,
+    nonce: &str,
+) -> Option<UpstreamIdentity> {
+    let redirect_uri = federation_redirect_uri();
+    let params = [
+        ("grant_type", "authorization_code"),
+        ("code", code),
+        ("redirect_uri", redirect_uri.as_str()),
+        ("client_id", provider.client_id.as_str()),
+        ("client_secret", provider.client_secret.as_str()),
+        ("code_verifier", code_verifier),
+    ];
+
+    let token_response: TokenResponse =
+        get_json(http_client().post(&endpoints.token_endpoint).form(&params)).await?;
+
+    let id_token_claims = match &token_response.id_token {
+        Some(id_token) => Some(validate_id_token(provider, id_token, nonce)?),
+        None => None,
+    };
+
+    // Fall back to the userinfo endpoint for claims missing from the ID token
+    let needs_userinfo = id_token_claims
+        .as_ref()
+        .is_none_or(|claims| claims.get("email").is_none());
+    let userinfo = match (&endpoints.userinfo_endpoint, needs_userinfo) {
+        (Some(userinfo_endpoint), true) => {
+            get_json::<Value>(
+                http_client()
+                    .get(userinfo_endpoint)
+                    .bearer_auth(&token_response.access_token),
+            )
+            .await
+        }
+        _ => None,
+    };
+
+    upstream_identity(&provider.id, id_token_claims, userinfo)
+}
+
+// Maps the claims of the ID token and the user info to the identity they assert
+fn upstream_identity(
+    provider_id: &str,
+    id_token_claims: Option<Value>,
+    userinfo: Option<Value>,
+) -> Option<UpstreamIdentity> {
+    let subject_of = |claims: &Value| {
+        claims
+            .get("sub")
+            .or_else(|| claims.get("id"))
+            .and_then(|sub| match sub {
+                Value::String(sub) => Some(sub.clone()),
+                Value::Number(sub) => Some(sub.to_string()),
+                _ => None,
+            })
+    };
+
+    let (subject, claims) = match (id_token_claims, userinfo) {
+        (Some(id_token_claims), Some(userinfo)) => {
+            // The user info must be about the same user (OpenID Connect Core section 5.3.2)
+            let subject = subject_of(&id_token_claims)?;
+            if subject_of(&userinfo).as_deref() != Some(subject.as_str()) {
+                warn!(
+                    "Identity provider {} returned user info of another subject",
+                    provider_id
+                );
+                return None;
+            }
+            (subject, userinfo)
+        }
+        (Some(claims), None) | (None, Some(claims)) => (subject_of(&claims)?, claims),
+        (None, None) => {
+            warn!("Identity provider {} returned no identity", provider_id);
+            return None;
+        }
+    };
+
+    Some(UpstreamIdentity {
+        subject,
+        email: claims
+            .get("email")
+            .and_then(Value::as_str)
+            .map(str::to_string),
+        email_verified: matches!(claims.get("email_verified"), Some(Value::Bool(true)))
+            || claims.get("email_verified").and_then(Value::as_str) == Some("true"),
+    })
+}
+
+fn validate_id_token(provider: &IdentityProvider, id_token: &str, nonce: &str) -> Option<Value> {
+    let payload = id_token.split('.').nth(1)?;
+    let claims: Value = serde_json::from_slice(&decode_base64url(payload)?)
+        .map_err(|err| warn!("Invalid ID token from {}: {}", provider.id, err))
+        .ok()?;
+
+    let now = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .expect("Time went backwards")
+        .as_secs();
+    let audience_matches = match claims.get("aud") {
+        Some(Value::String(audience)) => *audience == provider.client_id,
+        Some(Value::Array(audiences)) => audiences
+            .iter()
+            .any(|audience| audience.as_str() == Some(provider.client_id.as_str())),
+        _ => false,
+    };
+
+    if claims.get("iss").and_then(Value::as_str) != Some(provider.issuer.as_str()) {
+        warn!("ID token from {} has an unexpected issuer", provider.id);
+        return None;
+    }
+    if !audience_matches {
+        warn!("ID token from {} is not for this client", provider.id);
+        return None;
+    }
+    if claims.get("exp").and_then(Value::as_u64).unwrap_or(0) < now {
+        warn!("ID token from {} has expired", provider.id);
+        return None;
+    }
+    if claims.get("nonce").and_then(Value::as_str) != Some(nonce) {
+        warn!("ID token from {} has an unexpected nonce", provider.id);
+        return None;
+    }
+
+    Some(claims)
+}
+
+async fn get_json<T: for<'de> Deserialize<'de>>(request: reqwest::RequestBuilder) -> Option<T> {
+    let response = request
+        .header("Accept", "application/json")
+        .send()
+        .await
+        .map_err(|err| warn!("Request to identity provider failed: {}", err))
+        .ok()?;
+
+    if !response.status().is_success() {
+        warn!(
+            "Identity provider responded with {} to {}",
+            response.status(),
+            response.url()
+        );
+        return None;
+    }
+
+    response
+        .json::<T>()
+        .await
+        .map_err(|err| warn!("Invalid response from identity provider: {}", err))
+        .ok()
+}
+
+fn http_client() -> &'static reqwest::Client {
+    static CLIENT: OnceLock<reqwest::Client> = OnceLock::new();
+    CLIENT.get_or_init(|| {
+        reqwest::Client::builder()
+            .timeout(Duration::from_secs(10))
+            .build()
+            .expect("Failed to build the HTTP client")
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn provider() -> IdentityProvider {
+        IdentityProvider {
+            id: "upstream".to_string(),
+            name: "Upstream".to_string(),
+            issuer: "https://idp.example.com".to_string(),
+            client_id: "crate".to_string(),
+            client_secret: "secret".to_string(),
+            scopes: "openid email".to_string(),
+            authorization_endpoint: None,
+            token_endpoint: None,
+            userinfo_endpoint: None,
+            trust_email: false,
+        }
+    }
+
+    fn id_token(claims: &Value) -> String {
+        format!(
+            "{}.{}.",
+            encode_base64url(br#"{"alg":"RS256"}"#),
+            encode_base64url(claims.to_string().as_bytes())
+        )
+    }
+
+    fn id_token_claims() -> Value {
+        let exp = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap()
+            .as_secs()
+            + 300;
+        json!({
+            "iss": "https://idp.example.com",
+            "aud": ["other", "crate"],
+            "sub": "alice",
+            "exp": exp,
+            "nonce": "nonce",
+        })
+    }
+
+    #[test]
+    fn maps_id_token_claims() {
+        let identity = upstream_identity(
+            "upstream",
+            Some(json!({"sub": "alice", "email": "alice@example.com", "email_verified": true})),
+            None,
+        )
+        .unwrap();
+        assert_eq!(identity.subject, "alice");
+        assert_eq!(identity.email.as_deref(), Some("alice@example.com"));
+        assert!(identity.email_verified);
+    }
+
+    #[test]
+    fn maps_oauth_user_info() {
+        // Plain OAuth 2.0 providers like GitHub use a numeric id and no verification claim
+        let identity = upstream_identity(
+            "upstream",
+            None,
+            Some(json!({"id": 42, "email": "alice@example.com"})),
+        )
+        .unwrap();
+        assert_eq!(identity.subject, "42");
+        assert!(!identity.email_verified);
+
+        let identity = upstream_identity(
+            "upstream",
+            None,
+            Some(json!({"sub": "alice", "email_verified": "true"})),
+        )
+        .unwrap();
+        assert!(identity.email.is_none());
+        assert!(identity.email_verified);
+    }
+
+    #[test]
+    fn takes_claims_from_user_info_of_the_same_subject() {
+        let identity = upstream_identity(
+            "upstream",
+            Some(json!({"sub": "alice"})),
+            Some(json!({"sub": "alice", "email": "alice@example.com"})),
+        )
+        .unwrap();
+        assert_eq!(identity.email.as_deref(), Some("alice@example.com"));
+
+        assert!(upstream_identity(
+            "upstream",
+            Some(json!({"sub": "alice"})),
+            Some(json!({"sub": "mallory", "email": "alice@example.com"})),
+        )
+        .is_none());
+    }
+
+    #[test]
+    fn rejects_missing_subjects() {
+        assert!(upstream_identity("upstream", None, None).is_none());
+        assert!(upstream_identity("upstream", Some(json!({"email": "a@b.c"})), None).is_none());
+        assert!(upstream_identity("upstream", Some(json!({"sub": true})), None).is_none());
+    }
+
+    #[test]
+    fn validates_id_tokens() {
+        let provider = provider();
+        let claims = id_token_claims();
+        assert_eq!(
+            validate_id_token(&provider, &id_token(&claims), "nonce"),
+            Some(claims.clone())
+        );
+        assert!(validate_id_token(&provider, &id_token(&claims), "other").is_none());
+        assert!(validate_id_token(&provider, "not a token", "nonce").is_none());
+
+        for (claim, value) in [
+            ("iss", json!("https://evil.example.com")),
+            ("aud", json!("other")),
+            ("exp", json!(1)),
+        ] {
+            let mut claims = claims.clone();
+            claims[claim] = value;
+            assert!(validate_id_token(&provider, &id_token(&claims), "nonce").is_none());
+        }
+    }
+}
@@ -1,23 +1,46 @@
+use crate::binding::{bind_request, request_csrf_token};
+use crate::claims::{claim_description, unscoped_claims};
+use crate::config::session_ttl;
+use crate::cookies::{build_cookie, SESSION_COOKIE};
 use crate::errors::{
-    database_error, invalid_client_error, invalid_redirect_uri_error, invalid_scope_error,
+    consent_required_error, failed_authorization_error, interaction_required_error,
+    invalid_id_token_hint_error, invalid_scope_error, invalid_target_error, login_required_error,
+    missing_scope_error, scopes_not_permitted_error, unauthorized_client_error,
 };
-use crate::pages::get_login_html;
-use crate::storage::{check_client_id, get_client_data, AuthorizeRequestData};
-use crate::GLOBAL_CACHE;
+use crate::id_tokens::verify_id_token;
+use crate::lockout::is_account_locked;
+use crate::mfa::{build_totp, generate_totp_secret, has_second_factor};
+use crate::pages::{
+    get_consent_html, get_login_error_html, get_login_html, get_mfa_enroll_html, get_mfa_html,
+};
+use crate::response_modes::authorization_response;
+use crate::storage::{
+    check_client_id, get_client_data, get_resource_server, get_scope, get_scopes, restrict_scopes,
+    AuthCodeData, Authentication, AuthorizeRequestData, Client, User,
+};
+use crate::{GLOBAL_CACHE, GLOBAL_DATABASE};
+use axum::http::header::SET_COOKIE;
+use axum::http::HeaderMap;
 use axum::response::{IntoResponse, Response};
-use rand::random;
+use rand::distributions::Alphanumeric;
+use rand::{random, Rng};
 use std::time::{SystemTime, UNIX_EPOCH};
 
-pub async fn authorization_code_flow(request_data: &AuthorizeRequestData) -> Response {
+/// Gets the client of an authorization request once its redirect URI is known to be registered
+/// for it. Until then errors can't be sent to the redirect URI (RFC 6749 section 4.1.2.1), so
+/// the error message for a local error page is returned instead.
+pub async fn registered_client(
+    request_data: &AuthorizeRequestData,
+) -> Result<Client, &'static str> {
     // Check if client_id is valid
     if !check_client_id(&request_data.client_id).await {
-        return invalid_client_error(&request_data.redirect_uri, request_data.state.as_ref());
+        return Err("Invalid client");
     }
 
     // Get the client data
     let client_data = match get_client_data(request_data.client_id.as_str()).await {
         Some(data) => data,
-        None => return database_error(&request_data.redirect_uri, request_data.state.as_ref()),
+        None => return Err("Failed to load the client"),
     };
 
     // Match the redirect uri with allowed ones
@@ -25,16 +48,156 @@ pub async fn authorization_code_flow(request_data: &AuthorizeRequestData) -> Res
         .redirect_uris
         .contains(&request_data.redirect_uri)
     {
-        return invalid_redirect_uri_error(&request_data.redirect_uri, request_data.state.as_ref());
+        return Err("Invalid redirect URI");
+    }
+
+    Ok(client_data)
+}
+
+pub async fn authorization_code_flow(
+    headers: &HeaderMap,
+    request_data: &AuthorizeRequestData,
+    client_data: Client,
+    session: Option<Authentication>,
+) -> Response {
+    if !client_data
+        .grant_types
+        .iter()
+        .any(|grant_type| grant_type == "authorization_code")
+    {
+        return unauthorized_client_error(
+            &request_data.redirect_uri,
+            request_data.response_mode(),
+            request_data.state.as_ref(),
+        );
     }
 
+    // Requests omitting the scope get the default scopes (RFC 6749 section 3.3)
+    let default_request_data;
+    let request_data = if request_data.scope.trim().is_empty() {
+        let scope = default_scopes(&client_data).await.join(" ");
+        if scope.is_empty() {
+            return missing_scope_error(
+                &request_data.redirect_uri,
+                request_data.response_mode(),
+                request_data.state.as_ref(),
+            );
+        }
+
+        default_request_data = AuthorizeRequestData {
+            scope,
+            ..request_data.clone()
+        };
+        &default_request_data
+    } else {
+        request_data
+    };
+
     // Match the requested scopes with allowed ones
     let scopes = request_data.scope.split(" ").collect::<Vec<&str>>();
     if !scopes
         .iter()
         .all(|s| client_data.allowed_scopes.contains(&s.to_string()))
     {
-        return invalid_scope_error(&request_data.redirect_uri, request_data.state.as_ref());
+        return invalid_scope_error(
+            &request_data.redirect_uri,
+            request_data.response_mode(),
+            request_data.state.as_ref(),
+        );
+    }
+
+    // Every requested scope must be registered, its description is shown to the user
+    let registered_scopes = match get_scopes(&request_data.scope).await {
+        Some(scopes) => scopes,
+        None => {
+            return invalid_scope_error(
+                &request_data.redirect_uri,
+                request_data.response_mode(),
+                request_data.state.as_ref(),
+            )
+        }
+    };
+
+    // Match the requested resource with the registered resource servers
+    if let Some(resource) = &request_data.resource {
+        let resource_server = match get_resource_server(resource).await {
+            Some(server) => server,
+            None => {
+                return invalid_target_error(
+                    &request_data.redirect_uri,
+                    request_data.response_mode(),
+                    request_data.state.as_ref(),
+                )
+            }
+        };
+
+        // At least one of the requested scopes must be owned by the resource server
+        if restrict_scopes(&request_data.scope, &resource_server.scopes).is_empty() {
+            return invalid_scope_error(
+                &request_data.redirect_uri,
+                request_data.response_mode(),
+                request_data.state.as_ref(),
+            );
+        }
+    }
+
+    // The user the client expects, if it passes an ID token it got earlier
+    let hinted_user_id = match &request_data.id_token_hint {
+        Some(id_token_hint) => match verify_id_token(id_token_hint).await {
+            Some(claims) if claims.aud == client_data.id.to_string() => Some(claims.sub),
+            _ => {
+                return invalid_id_token_hint_error(
+                    &request_data.redirect_uri,
+                    request_data.response_mode(),
+                    request_data.state.as_ref(),
+                )
+            }
+        },
+        None => None,
+    };
+
+    // The session is only reused if the client doesn't ask to log in again, the login is
+    // recent enough for max_age and the user is the one of the id_token_hint
+    let now = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .expect("Time went backwards")
+        .as_secs();
+    let session = session.filter(|authentication| {
+        !request_data.has_prompt("login")
+            && !request_data.has_prompt("select_account")
+            && request_data
+                .max_age()
+                .is_none_or(|max_age| now.saturating_sub(authentication.auth_time) <= max_age)
+            && hinted_user_id
+                .as_ref()
+                .is_none_or(|user_id| *user_id == authentication.user_id)
+    });
+
+    // Users with an active session skip the login form, unless the session
+    // lacks a second factor that is required for the user or the client
+    let session = match session {
+        Some(authentication) => {
+            let complete = has_second_factor(&authentication.amr)
+                || !is_mfa_required(&client_data, &authentication.user_id).await;
+            if !complete && request_data.has_prompt("none") {
+                return interaction_required_error(
+                    &request_data.redirect_uri,
+                    request_data.response_mode(),
+                    request_data.state.as_ref(),
+                );
+            }
+            complete.then_some(authentication)
+        }
+        None => None,
+    };
+
+    // With prompt=none the login form can't be shown
+    if session.is_none() && request_data.has_prompt("none") {
+        return login_required_error(
+            &request_data.redirect_uri,
+            request_data.response_mode(),
+            request_data.state.as_ref(),
+        );
     }
 
     // Generate a request id using a random and the current timestamp
@@ -44,7 +207,338 @@ pub async fn authorization_code_flow(request_data: &AuthorizeRequestData) -> Res
         .unwrap()
         .set_request(&request_id, request_data);
 
-    get_login_html(client_data.name.as_str(), &request_id, &request_data.scope).into_response()
+    // Only the browser that started the request can continue it
+    let (browser_cookie, csrf_token) = bind_request(headers, &request_id);
+
+    if let Some(authentication) = session {
+        let response =
+            finish_authorization(&request_id, request_data, &client_data, &authentication).await;
+        return ([(SET_COOKIE, browser_cookie)], response).into_response();
+    }
+
+    let providers = GLOBAL_DATABASE
+        .get()
+        .unwrap()
+        .get_identity_providers()
+        .await;
+
+    (
+        [(SET_COOKIE, browser_cookie)],
+        get_login_html(
+            client_data.name.as_str(),
+            &request_id,
+            &registered_scopes,
+            &csrf_token,
+            request_data.login_hint.as_deref().unwrap_or_default(),
+            &providers,
+        ),
+    )
+        .into_response()
+}
+
+/// Gets the default scopes of the client, or the registered default scopes the client is
+/// allowed when it has none.
+pub async fn default_scopes(client_data: &Client) -> Vec<String> {
+    if !client_data.default_scopes.is_empty() {
+        return client_data.default_scopes.clone();
+    }
+
+    GLOBAL_DATABASE
+        .get()
+        .unwrap()
+        .get_default_scopes()
+        .await
+        .into_iter()
+        .filter(|scope| client_data.allowed_scopes.contains(scope))
+        .collect()
+}
+
+/// Checks whether multi-factor authentication is enforced for the client or the user.
+pub async fn is_mfa_required(client_data: &Client, user_id: &str) -> bool {
+    if client_data.mfa_required {
+        return true;
+    }
+
+    GLOBAL_DATABASE
+        .get()
+        .unwrap()
+        .get_user_by_id(&user_id.parse::<u32>().unwrap_or_default())
+        .await
+        .map(|user| user.mfa_required)
+        .unwrap_or(true)
+}
+
+/// Starts a browser SSO session for the authentication and returns the `Set-Cookie` value.
+/// The session gets a separate public ID, recorded in the authentication so grants can be tied
+/// to the session, while the cookie value stays a secret of the browser.
+pub fn start_session(authentication: &mut Authentication) -> String {
+    let session_key = generate_session_id();
+    let session_ttl = session_ttl();
+    authentication.session_id = Some(generate_session_id());
+
+    GLOBAL_CACHE
+        .get()
+        .unwrap()
+        .set_session(&session_key, authentication, session_ttl);
+
+    build_cookie(SESSION_COOKIE, &session_key, session_ttl)
+}
+
+fn generate_session_id() -> String {
+    rand::thread_rng()
+        .sample_iter(&Alphanumeric)
+        .take(32)
+        .map(char::from)
+        .collect()
+}
+
+/// Ends every browser SSO session of a user and revokes their refresh tokens.
+pub fn revoke_user_sessions(user_id: &str) {
+    let cache = GLOBAL_CACHE.get().unwrap();
+    cache.delete_user_sessions(user_id);
+    cache.revoke_user_refresh_tokens(user_id);
+}
+
+/// Continues a login once the first factor is verified. Users with a second factor enrolled
+/// always need it, others only when it is enforced for them or the client.
+pub async fn continue_login(
+    request_id: &str,
+    request_data: &AuthorizeRequestData,
+    client_data: &Client,
+    user: &User,
+    authentication: &Authentication,
+) -> Response {
+    let database = GLOBAL_DATABASE.get().unwrap();
+    let has_totp = database.get_totp_secret(&user.id).await.is_some();
+    let has_webauthn = !database.get_webauthn_credentials(&user.id).await.is_empty();
+    if has_totp || has_webauthn || user.mfa_required || client_data.mfa_required {
+        let cache = GLOBAL_CACHE.get().unwrap();
+        cache.set_request_pending_authentication(request_id, authentication);
+
+        if has_totp || has_webauthn {
+            return get_mfa_html(
+                request_id,
+                &request_csrf_token(request_id),
+                has_totp,
+                has_webauthn,
+                false,
+            )
+            .into_response();
+        }
+
+        // MFA is required but the user has not enrolled yet
+        let secret = generate_totp_secret();
+        let totp = match build_totp(&secret, &user.email) {
+            Some(totp) => totp,
+            None => return get_login_error_html().into_response(),
+        };
+        cache.set_request_pending_totp(request_id, &secret);
+
+        return get_mfa_enroll_html(
+            request_id,
+            &request_csrf_token(request_id),
+            &secret,
+            &totp.get_url(),
+            &totp.get_qr_base64().unwrap_or_default(),
+            false,
+        )
+        .into_response();
+    }
+
+    complete_authentication(request_id, request_data, client_data, authentication).await
+}
+
+/// Completes the login of a user: starts a session so later authorization requests
+/// skip the login form, then continues the authorization request.
+pub async fn complete_authentication(
+    request_id: &str,
+    request_data: &AuthorizeRequestData,
+    client_data: &Client,
+    authentication: &Authentication,
+) -> Response {
+    // Disabled and locked out users can't sign in, whatever the method
+    if !GLOBAL_DATABASE
+        .get()
+        .unwrap()
+        .get_user_by_id(&authentication.user_id.parse::<u32>().unwrap_or_default())
+        .await
+        .is_some_and(|user| user.enabled && !is_account_locked(&user.email))
+    {
+        GLOBAL_CACHE.get().unwrap().delete_request(request_id);
+        return failed_authorization_error(
+            &request_data.redirect_uri,
+            request_data.response_mode(),
+            request_data.state.as_ref(),
+        );
+    }
+
+    let mut authentication = authentication.clone();
+    let session_cookie = start_session(&mut authentication);
+    let response =
+        finish_authorization(request_id, request_data, client_data, &authentication).await;
+
+    ([(SET_COOKIE, session_cookie)], response).into_response()
+}
+
+/// Restricts a space-delimited scope string to the scopes the user's roles permit.
+pub async fn permitted_scopes(scope: &str, user_id: &str) -> String {
+    let user = match GLOBAL_DATABASE
+        .get()
+        .unwrap()
+        .get_user_by_id(&user_id.parse::<u32>().unwrap_or_default())
+        .await
+    {
+        Some(user) => user,
+        None => return String::new(),
+    };
+
+    let mut permitted = Vec::new();
+    for name in scope.split_whitespace() {
+        if get_scope(name)
+            .await
+            .is_some_and(|scope| scope.is_permitted(&user))
+        {
+            permitted.push(name);
+        }
+    }
+
+    permitted.join(" ")
+}
+
+/// Continues an authorization request once the user is authenticated. The requested scopes
+/// are reduced to the ones the user is permitted, the code is issued right away if the user
+/// already granted those, otherwise consent is asked.
+pub async fn finish_authorization(
+    request_id: &str,
+    request_data: &AuthorizeRequestData,
+    client_data: &Client,
+    authentication: &Authentication,
+) -> Response {
+    let user_id = match authentication.user_id.parse::<u32>() {
+        Ok(user_id) => user_id,
+        Err(_) => {
+            GLOBAL_CACHE.get().unwrap().delete_request(request_id);
+            return failed_authorization_error(
+                &request_data.redirect_uri,
+                request_data.response_mode(),
+                request_data.state.as_ref(),
+            );
+        }
+    };
+
+    let scope = permitted_scopes(&request_data.scope, &authentication.user_id).await;
+    if scope.is_empty() {
+        GLOBAL_CACHE.get().unwrap().delete_request(request_id);
+        return scopes_not_permitted_error(
+            &request_data.redirect_uri,
+            request_data.response_mode(),
+            request_data.state.as_ref(),
+        );
+    }
+
+    let database = GLOBAL_DATABASE.get().unwrap();
+    let consented_scopes = database.get_consent(&user_id, &client_data.id).await;
+    let consented_claims = database
+        .get_consented_claims(&user_id, &client_data.id)
+        .await;
+    let claims = request_data
+        .claims_request()
+        .map(|request| unscoped_claims(&scope, &request))
+        .unwrap_or_default();
+
+    // With prompt=consent the user is asked again, even if the scopes were granted before
+    if !request_data.has_prompt("consent")
+        && scope
+            .split_whitespace()
+            .all(|s| consented_scopes.iter().any(|consented| consented == s))
+        && claims.iter().all(|claim| consented_claims.contains(claim))
+    {
+        GLOBAL_CACHE.get().unwrap().delete_request(request_id);
+        return issue_authorization_code(request_data, authentication, &scope, &claims);
+    }
+
+    // With prompt=none the consent page can't be shown
+    if request_data.has_prompt("none") {
+        GLOBAL_CACHE.get().unwrap().delete_request(request_id);
+        return consent_required_error(
+            &request_data.redirect_uri,
+            request_data.response_mode(),
+            request_data.state.as_ref(),
+        );
+    }
+
+    // Otherwise ask the user to approve or deny the requested scopes and claims
+    GLOBAL_CACHE
+        .get()
+        .unwrap()
+        .set_request_authentication(request_id, authentication);
+
+    let scopes = match get_scopes(&scope).await {
+        Some(scopes) => scopes,
+        None => return get_login_error_html().into_response(),
+    };
+
+    let claims = claims
+        .iter()
+        .map(|claim| (claim.as_str(), claim_description(claim)))
+        .collect::<Vec<(&str, &str)>>();
+
+    get_consent_html(
+        &client_data.name,
+        request_id,
+        &request_csrf_token(request_id),
+        &scopes,
+        &claims,
+    )
+    .into_response()
+}
+
+/// Issues an authorization code for the authenticated user and returns it to the client. The
+/// approved claims are the ones requested on their own the user consented to.
+pub fn issue_authorization_code(
+    request_data: &AuthorizeRequestData,
+    authentication: &Authentication,
+    scope: &str,
+    approved_claims: &[String],
+) -> Response {
+    // Generate a code and cache the user details etc. for access and refresh tokens
+    let code: String = rand::thread_rng()
+        .sample_iter(&Alphanumeric)
+        .take(32)
+        .map(char::from)
+        .collect();
+
+    let code_data = AuthCodeData {
+        user_id: authentication.user_id.clone(),
+        scope: scope.to_string(),
+        resource: request_data.resource.clone(),
+        auth_time: authentication.auth_time,
+        amr: authentication.amr.clone(),
+        nonce: request_data.nonce.clone(),
+        session_id: authentication.session_id.clone(),
+        claims: request_data.claims_request(),
+        approved_claims: approved_claims.to_vec(),
+    };
+
+    let cache = GLOBAL_CACHE.get().unwrap();
+    cache.set_auth_code(&request_data.client_id, &code, &code_data);
+
+    // Remember the clients signed in through the session, they are notified on logout
+    if let Some(session_id) = &authentication.session_id {
+        cache.add_session_client(session_id, &request_data.client_id, session_ttl());
+    }
+
+    // Return the auth code and state
+    let mut params = vec![("code", code.as_str())];
+    if let Some(state) = &request_data.state {
+        params.push(("state", state));
+    }
+
+    authorization_response(
+        &request_data.redirect_uri,
+        request_data.response_mode(),
+        &params,
+    )
 }
 
 fn generate_request_id() -> String {
@@ -0,0 +1,113 @@
+use crate::claim_mappers::{apply_claims_mappers, ClaimsTarget};
+use crate::claims::select_claims;
+use crate::config::issuer_url;
+use crate::storage::{get_client_data, Client, RefreshTokenData, User};
+use jsonwebtoken::{decode, encode, Algorithm, DecodingKey, EncodingKey, Header, Validation};
+use log::{error, warn};
+use serde::{Deserialize, Serialize};
+use serde_json::{Map, Value};
+use std::collections::HashSet;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+// ID tokens are valid as long as access tokens
+const ID_TOKEN_TTL: u64 = 3600;
+
+/// The claims of an ID token (OpenID Connect Core section 2).
+#[derive(Serialize, Deserialize)]
+pub struct IdTokenClaims {
+    pub iss: String,
+    pub sub: String,
+    pub aud: String,
+    pub exp: u64,
+    pub iat: u64,
+    pub auth_time: u64,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub nonce: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub sid: Option<String>, // Browser SSO session, for front- and back-channel logout
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub amr: Vec<String>,
+    #[serde(flatten)]
+    pub claims: Map<String, Value>, // Claims about the user
+}
+
+/// Issues an ID token for the grant, signed with HS256 using the client secret as the key
+/// (OpenID Connect Core section 10.1). The claims about the user are the ones requested by
+/// the scope and the claims request, and the custom claims of the client.
+pub async fn issue_id_token(
+    client_data: &Client,
+    user: &User,
+    grant: &RefreshTokenData,
+    scope: &str,
+    nonce: Option<String>,
+) -> Option<String> {
+    let issued_at = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .expect("Time went backwards")
+        .as_secs();
+    let requested_claims = grant.claims.as_ref().map(|claims| &claims.id_token);
+    let mut user_claims = select_claims(user, scope, requested_claims, &grant.approved_claims);
+    apply_claims_mappers(
+        ClaimsTarget::IdToken,
+        client_data,
+        scope,
+        user,
+        &mut user_claims,
+    )
+    .await;
+
+    let claims = IdTokenClaims {
+        iss: issuer_url(),
+        sub: user.id.to_string(),
+        aud: client_data.id.to_string(),
+        exp: issued_at + ID_TOKEN_TTL,
+        iat: issued_at,
+        auth_time: grant.auth_time,
+        nonce,
+        sid: grant.session_id.clone(),
+        amr: grant.amr.clone(),
+        claims: user_claims,
+    };
+
+    encode(
+        &Header::new(Algorithm::HS256),
+        &claims,
+        &EncodingKey::from_secret(client_data.secret.as_bytes()),
+    )
+    .map_err(|err| error!("Failed to generate ID token: {}", err))
+    .ok()
+}
+
+/// Verifies an ID token we issued earlier, e.g. passed back as `id_token_hint`. The token may
+/// have expired, it only identifies the user.
+pub async fn verify_id_token(id_token: &str) -> Option<IdTokenClaims> {
+    // The audience tells which client secret the token is signed with
+    let mut unverified = Validation::new(Algorithm::HS256);
+    unverified.insecure_disable_signature_validation();
+    unverified.validate_exp = false;
+    unverified.validate_aud = false;
+    let audience = decode::<IdTokenClaims>(id_token, &DecodingKey::from_secret(&[]), &unverified)
+        .map_err(|err| warn!("Invalid ID token: {}", err))
+        .ok()?
+        .claims
+        .aud;
+
+    // Client IDs are numeric, anything else can't be looked up
+    audience.parse::<u32>().ok()?;
+    let client_data = get_client_data(&audience).await?;
+
+    let mut validation = Validation::new(Algorithm::HS256);
+    validation.validate_exp = false;
+    validation.required_spec_claims = HashSet::from(["iss".to_string(), "aud".to_string()]);
+    validation.set_issuer(&[issuer_url()]);
+    validation.set_audience(&[&audience]);
+
+    decode::<IdTokenClaims>(
+        id_token,
+        &DecodingKey::from_secret(client_data.secret.as_bytes()),
+        &validation,
+    )
+    .map_err(|err| warn!("Invalid ID token: {}", err))
+    .ok()
+    .map(|token| token.claims)
+}
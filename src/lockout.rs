@@ -0,0 +1,310 @@
+use crate::config::trust_proxy_headers;
+use crate::{GLOBAL_CACHE, GLOBAL_DATABASE};
+use axum::http::HeaderMap;
+use log::{info, warn};
+use std::net::SocketAddr;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+// Failed login attempts are counted over a sliding window of this many seconds
+const FAILURE_WINDOW: u64 = 900;
+// Failures after which every further attempt is delayed, doubling with each failure
+const BACKOFF_THRESHOLD: u64 = 3;
+const MAX_BACKOFF: u64 = 300;
+// Failures after which logins are refused until the lockout expires or an admin lifts it
+const ACCOUNT_LOCKOUT_THRESHOLD: u64 = 10;
+// Failed second factors after which the account is locked, fewer since the password is known
+const MFA_LOCKOUT_THRESHOLD: u64 = 5;
+const IP_LOCKOUT_THRESHOLD: u64 = 50;
+const LOCKOUT_DURATION: u64 = 900;
+
+// Registration emails sent per address and per client IP address within the window
+const REGISTRATION_WINDOW: u64 = 3600;
+const REGISTRATIONS_PER_EMAIL: u64 = 3;
+const REGISTRATIONS_PER_IP: u64 = 10;
+
+/// Whether a login attempt may check the password.
+pub enum LoginThrottle {
+    Allowed,
+    /// Too soon after the last failure, the attempt is allowed again in this many seconds.
+    Delayed(u64),
+    Locked,
+}
+
+/// The IP address of the client, from `X-Forwarded-For` when behind a trusted proxy.
+pub fn client_ip(headers: &HeaderMap, address: &SocketAddr) -> String {
+    if trust_proxy_headers() {
+        let forwarded_for = headers
+            .get("x-forwarded-for")
+            .and_then(|value| value.to_str().ok())
+            .and_then(|value| value.split(',').next())
+            .map(|value| value.trim().to_string())
+            .filter(|value| !value.is_empty());
+
+        if let Some(ip) = forwarded_for {
+            return ip;
+        }
+    }
+
+    address.ip().to_string()
+}
+
+/// Checks the lockouts and backoff delays of the account and the client IP address.
+pub fn check_login_attempt(email: &str, ip: &str) -> LoginThrottle {
+    let cache = GLOBAL_CACHE.get().unwrap();
+    let keys = [email_key(email), ip_key(ip)];
+
+    if keys.iter().any(|key| cache.is_locked_out(key)) {
+        return LoginThrottle::Locked;
+    }
+
+    let now = now();
+    let delay = keys
+        .iter()
+        .map(|key| {
+            let (failures, last_failure) = cache.get_login_failures(key, now, FAILURE_WINDOW);
+            backoff(failures).saturating_sub(now.saturating_sub(last_failure))
+        })
+        .max()
+        .unwrap_or(0);
+
+    if delay > 0 {
+        return LoginThrottle::Delayed(delay);
+    }
+
+    LoginThrottle::Allowed
+}
+
+/// Whether the account is locked out, after too many failed logins or second factors.
+pub fn is_account_locked(email: &str) -> bool {
+    GLOBAL_CACHE.get().unwrap().is_locked_out(&email_key(email))
+}
+
+/// Records a failed login attempt, locking the account or the client IP address once too many
+/// attempts failed within the window.
+pub async fn record_login_failure(email: &str, ip: &str) {
+    let cache = GLOBAL_CACHE.get().unwrap();
+    let now = now();
+
+    let account_failures = cache.add_login_failure(&email_key(email), now, FAILURE_WINDOW);
+    if account_failures >= ACCOUNT_LOCKOUT_THRESHOLD {
+        cache.set_lockout(&email_key(email), LOCKOUT_DURATION);
+        cache.clear_login_failures(&email_key(email));
+        warn!(
+            "Locked account {} after {} failed logins",
+            email, account_failures
+        );
+
+        let user_id = GLOBAL_DATABASE
+            .get()
+            .unwrap()
+            .get_user(email)
+            .await
+            .map(|user| user.id);
+        record_lockout("account_locked", user_id, email, ip, account_failures).await;
+    }
+
+    let ip_failures = cache.add_login_failure(&ip_key(ip), now, FAILURE_WINDOW);
+    if ip_failures >= IP_LOCKOUT_THRESHOLD {
+        cache.set_lockout(&ip_key(ip), LOCKOUT_DURATION);
+        cache.clear_login_failures(&ip_key(ip));
+        warn!(
+            "Locked IP address {} after {} failed logins",
+            ip, ip_failures
+        );
+
+        record_lockout("ip_locked", None, ip, ip, ip_failures).await;
+    }
+}
+
+/// Resets the failed attempts of an account after a successful login. The failures of the IP
+/// address are kept, so a valid account can't be used to reset them.
+pub fn record_login_success(email: &str) {
+    GLOBAL_CACHE
+        .get()
+        .unwrap()
+        .clear_login_failures(&email_key(email));
+}
+
+/// Checks the lockout of the account and the backoff delay after failed second factors.
+pub fn check_mfa_attempt(user_id: u32, email: &str) -> LoginThrottle {
+    let cache = GLOBAL_CACHE.get().unwrap();
+
+    if cache.is_locked_out(&email_key(email)) {
+        return LoginThrottle::Locked;
+    }
+
+    let now = now();
+    let (failures, last_failure) = cache.get_login_failures(&mfa_key(user_id), now, FAILURE_WINDOW);
+    let delay = backoff(failures).saturating_sub(now.saturating_sub(last_failure));
+    if delay > 0 {
+        return LoginThrottle::Delayed(delay);
+    }
+
+    LoginThrottle::Allowed
+}
+
+/// Records a failed second factor. They are counted per user, so signing in with the password
+/// again doesn't reset them, and lock the account like failed logins do.
+pub async fn record_mfa_failure(user_id: u32, email: &str, ip: &str) {
+    let cache = GLOBAL_CACHE.get().unwrap();
+    let now = now();
+
+    let mfa_failures = cache.add_login_failure(&mfa_key(user_id), now, FAILURE_WINDOW);
+    if mfa_failures >= MFA_LOCKOUT_THRESHOLD {
+        cache.set_lockout(&email_key(email), LOCKOUT_DURATION);
+        cache.clear_login_failures(&mfa_key(user_id));
+        warn!(
+            "Locked account {} after {} failed second factors",
+            email, mfa_failures
+        );
+
+        record_lockout("account_locked", Some(user_id), email, ip, mfa_failures).await;
+    }
+
+    let ip_failures = cache.add_login_failure(&ip_key(ip), now, FAILURE_WINDOW);
+    if ip_failures >= IP_LOCKOUT_THRESHOLD {
+        cache.set_lockout(&ip_key(ip), LOCKOUT_DURATION);
+        cache.clear_login_failures(&ip_key(ip));
+        warn!(
+            "Locked IP address {} after {} failed logins",
+            ip, ip_failures
+        );
+
+        record_lockout("ip_locked", None, ip, ip, ip_failures).await;
+    }
+}
+
+/// Resets the failed second factors of a user after one was verified.
+pub fn record_mfa_success(user_id: u32) {
+    GLOBAL_CACHE
+        .get()
+        .unwrap()
+        .clear_login_failures(&mfa_key(user_id));
+}
+
+/// Counts a registration, which sends an email to the address, and returns whether it is within
+/// the limits of the address and of the client IP address.
+pub fn allow_registration(email: &str, ip: &str) -> bool {
+    let cache = GLOBAL_CACHE.get().unwrap();
+    let now = now();
+
+    let email_registrations = cache.add_rate_limited_action(
+        &format!("REGISTRATION_{}", email_key(email)),
+        now,
+        REGISTRATION_WINDOW,
+    );
+    let ip_registrations = cache.add_rate_limited_action(
+        &format!("REGISTRATION_{}", ip_key(ip)),
+        now,
+        REGISTRATION_WINDOW,
+    );
+
+    if email_registrations > REGISTRATIONS_PER_EMAIL || ip_registrations > REGISTRATIONS_PER_IP {
+        warn!(
+            "Registration for {} from {} refused by rate limit",
+            email, ip
+        );
+        return false;
+    }
+
+    true
+}
+
+/// Lifts the lockout of an account and forgets its failed attempts. Returns whether the account
+/// was locked.
+pub async fn unlock_account(email: &str) -> bool {
+    let cache = GLOBAL_CACHE.get().unwrap();
+    let database = GLOBAL_DATABASE.get().unwrap();
+    let user_id = database.get_user(email).await.map(|user| user.id);
+
+    cache.clear_login_failures(&email_key(email));
+    if let Some(user_id) = user_id {
+        cache.clear_login_failures(&mfa_key(user_id));
+    }
+
+    if !cache.clear_lockout(&email_key(email)) {
+        return false;
+    }
+
+    info!("Unlocked account {}", email);
+    database
+        .record_audit_event("account_unlocked", user_id, email, None, None)
+        .await;
+
+    true
+}
+
+async fn record_lockout(event: &str, user_id: Option<u32>, subject: &str, ip: &str, failures: u64) {
+    let details = format!(
+        "{} failed logins within {} seconds, locked for {} seconds",
+        failures, FAILURE_WINDOW, LOCKOUT_DURATION
+    );
+
+    GLOBAL_DATABASE
+        .get()
+        .unwrap()
+        .record_audit_event(event, user_id, subject, Some(ip), Some(&details))
+        .await;
+}
+
+// The delay in seconds required after the last of a number of failed attempts
+fn backoff(failures: u64) -> u64 {
+    if failures < BACKOFF_THRESHOLD {
+        return 0;
+    }
+
+    1u64.checked_shl((failures - BACKOFF_THRESHOLD) as u32)
+        .unwrap_or(MAX_BACKOFF)
+        .min(MAX_BACKOFF)
+}
+
+fn email_key(email: &str) -> String {
+    format!("EMAIL_{}", email.trim().to_lowercase())
+}
+
+fn mfa_key(user_id: u32) -> String {
+    format!("MFA_USER_{}", user_id)
+}
+
+fn ip_key(ip: &str) -> String {
+    format!("IP_{}", ip)
+}
+
+fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .expect("Time went backwards")
+        .as_secs()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn backoff_starts_at_the_threshold_and_doubles() {
+        for failures in 0..BACKOFF_THRESHOLD {
+            assert_eq!(backoff(failures), 0);
+        }
+        assert_eq!(backoff(BACKOFF_THRESHOLD), 1);
+        assert_eq!(backoff(BACKOFF_THRESHOLD + 1), 2);
+        assert_eq!(backoff(BACKOFF_THRESHOLD + 4), 16);
+    }
+
+    #[test]
+    fn backoff_is_capped() {
+        assert_eq!(backoff(BACKOFF_THRESHOLD + 9), MAX_BACKOFF);
+        assert_eq!(backoff(BACKOFF_THRESHOLD + 64), MAX_BACKOFF);
+        assert_eq!(backoff(u64::MAX), MAX_BACKOFF);
+    }
+
+    #[test]
+    fn email_keys_ignore_case_and_whitespace() {
+        assert_eq!(
+            email_key(" Alice@Example.com "),
+            email_key("alice@example.com")
+        );
+        assert_ne!(email_key("alice@example.com"), ip_key("alice@example.com"));
+        assert_ne!(mfa_key(1), email_key("1"));
+    }
+}
@@ -0,0 +1,205 @@
+use crate::config::issuer_url;
+use crate::storage::Client;
+use jsonwebtoken::{encode, Algorithm, EncodingKey, Header};
+use log::{info, warn};
+use rand::distributions::Alphanumeric;
+use rand::Rng;
+use serde::Serialize;
+use serde_json::{json, Value};
+use std::sync::OnceLock;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+// Logout tokens are only meant to be used right away
+const LOGOUT_TOKEN_TTL: u64 = 120;
+
+// The claims of a logout token (OpenID Connect Back-Channel Logout section 2.4)
+#[derive(Serialize)]
+struct LogoutTokenClaims {
+    iss: String,
+    sub: String,
+    aud: String,
+    iat: u64,
+    exp: u64,
+    jti: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    sid: Option<String>,
+    events: Value,
+}
+
+/// Builds the URL logging the user out of the client in an iframe, with the issuer and the
+/// session ID (OpenID Connect Front-Channel Logout section 2).
+pub fn frontchannel_logout_url(logout_uri: &str, session_id: &str) -> String {
+    let separator = if logout_uri.contains('?') { '&' } else { '?' };
+
+    format!(
+        "{}{}iss={}&sid={}",
+        logout_uri,
+        separator,
+        urlencoding::encode(&issuer_url()),
+        urlencoding::encode(session_id)
+    )
+}
+
+/// Posts a logout token to the back-channel logout endpoint of the client. The token is signed
+/// like ID tokens, with HS256 using the client secret as the key.
+pub async fn send_backchannel_logout(
+    client_data: &Client,
+    logout_uri: &str,
+    user_id: &str,
+    session_id: Option<&str>,
+) -> bool {
+    let issued_at = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .expect("Time went backwards")
+        .as_secs();
+
+    let logout_token = match logout_token(
+        &issuer_url(),
+        &client_data.id.to_string(),
+        &client_data.secret,
+        user_id,
+        session_id,
+        issued_at,
+    ) {
+        Some(token) => token,
+        None => return false,
+    };
+
+    let response = http_client()
+        .post(logout_uri)
+        .form(&[("logout_token", logout_token)])
+        .send()
+        .await;
+
+    match response {
+        Ok(response) if response.status().is_success() => {
+            info!("Client {} logged out user {}", client_data.id, user_id);
+            true
+        }
+        Ok(response) => {
+            warn!(
+                "Back-channel logout of client {} responded with {}",
+                client_data.id,
+                response.status()
+            );
+            false
+        }
+        Err(err) => {
+            warn!(
+                "Back-channel logout of client {} failed: {}",
+                client_data.id, err
+            );
+            false
+        }
+    }
+}
+
+// Builds the logout token for a client, signed with its secret (Back-Channel Logout section 2.4)
+fn logout_token(
+    issuer: &str,
+    client_id: &str,
+    client_secret: &str,
+    user_id: &str,
+    session_id: Option<&str>,
+    issued_at: u64,
+) -> Option<String> {
+    let claims = LogoutTokenClaims {
+        iss: issuer.to_string(),
+        sub: user_id.to_string(),
+        aud: client_id.to_string(),
+        iat: issued_at,
+        exp: issued_at + LOGOUT_TOKEN_TTL,
+        jti: rand::thread_rng()
+            .sample_iter(&Alphanumeric)
+            .take(32)
+            .map(char::from)
+            .collect(),
+        sid: session_id.map(str::to_string),
+        events: json!({ "http://schemas.openid.net/event/backchannel-logout": {} }),
+    };
+    let header = Header {
+        typ: Some("logout+jwt".to_string()),
+        ..Header::new(Algorithm::HS256)
+    };
+
+    encode(
+        &header,
+        &claims,
+        &EncodingKey::from_secret(client_secret.as_bytes()),
+    )
+    .map_err(|err| warn!("Failed to generate logout token: {}", err))
+    .ok()
+}
+
+fn http_client() -> &'static reqwest::Client {
+    static CLIENT: OnceLock<reqwest::Client> = OnceLock::new();
+    CLIENT.get_or_init(|| {
+        // Logout endpoints must not redirect (Back-Channel Logout section 2.8)
+        reqwest::Client::builder()
+            .timeout(Duration::from_secs(5))
+            .redirect(reqwest::redirect::Policy::none())
+            .build()
+            .expect("Failed to build the HTTP client")
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use jsonwebtoken::{decode, decode_header, DecodingKey, Validation};
+
+    fn decode_logout_token(token: &str, secret: &str) -> Option<Value> {
+        let mut validation = Validation::new(Algorithm::HS256);
+        validation.validate_exp = false;
+        validation.set_audience(&["7"]);
+        validation.set_issuer(&["https://auth.example.com"]);
+
+        decode::<Value>(
+            token,
+            &DecodingKey::from_secret(secret.as_bytes()),
+            &validation,
+        )
+        .ok()
+        .map(|token| token.claims)
+    }
+
+    #[test]
+    fn builds_logout_tokens() {
+        let token = logout_token(
+            "https://auth.example.com",
+            "7",
+            "secret",
+            "42",
+            Some("sid"),
+            1000,
+        )
+        .unwrap();
+
+        assert_eq!(
+            decode_header(&token).unwrap().typ.as_deref(),
+            Some("logout+jwt")
+        );
+        let claims = decode_logout_token(&token, "secret").unwrap();
+        assert_eq!(claims["sub"], "42");
+        assert_eq!(claims["sid"], "sid");
+        assert_eq!(claims["iat"], 1000);
+        assert_eq!(claims["exp"], 1000 + LOGOUT_TOKEN_TTL);
+        assert_eq!(claims["jti"].as_str().unwrap().len(), 32);
+        assert_eq!(
+            claims["events"],
+            json!({ "http://schemas.openid.net/event/backchannel-logout": {} })
+        );
+        // Logout tokens must not look like ID tokens (Back-Channel Logout section 2.4)
+        assert!(claims.get("nonce").is_none());
+    }
+
+    #[test]
+    fn logout_tokens_are_signed_with_the_client_secret() {
+        let token =
+            logout_token("https://auth.example.com", "7", "secret", "42", None, 1000).unwrap();
+
+        assert!(decode_logout_token(&token, "other").is_none());
+        let claims = decode_logout_token(&token, "secret").unwrap();
+        assert!(claims.get("sid").is_none());
+    }
+}
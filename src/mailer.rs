@@ -0,0 +1,121 @@
+use crate::config::{mail_directory, mail_from, mailer, smtp_url};
+use async_trait::async_trait;
+use lettre::message::header::ContentType;
+use lettre::message::Mailbox;
+use lettre::{AsyncSmtpTransport, AsyncTransport, Message, Tokio1Executor};
+use log::{error, info};
+use rand::random;
+use std::path::PathBuf;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// A plain text email to a single recipient.
+pub struct Email {
+    pub to: String,
+    pub subject: String,
+    pub body: String,
+}
+
+/// Delivers emails, e.g. account verification links.
+#[async_trait]
+pub trait Mailer: Send + Sync {
+    /// Sends the email and returns whether it was accepted for delivery.
+    async fn send(&self, email: &Email) -> bool;
+}
+
+/// Sends emails through an SMTP relay.
+pub struct SmtpMailer {
+    transport: AsyncSmtpTransport<Tokio1Executor>,
+    from: Mailbox,
+}
+
+/// Writes emails as `.eml` files to a directory instead of sending them, for development and tests.
+pub struct DirectoryMailer {
+    directory: PathBuf,
+    from: Mailbox,
+}
+
+/// Creates the mailer selected by the configuration.
+pub fn mailer_from_config() -> Result<Box<dyn Mailer>, Box<dyn std::error::Error>> {
+    let from = mail_from().parse::<Mailbox>()?;
+
+    match mailer().as_str() {
+        "smtp" => Ok(Box::new(SmtpMailer {
+            transport: AsyncSmtpTransport::<Tokio1Executor>::from_url(&smtp_url())?.build(),
+            from,
+        })),
+        "directory" => Ok(Box::new(DirectoryMailer {
+            directory: PathBuf::from(mail_directory()),
+            from,
+        })),
+        other => Err(format!("Unknown mailer: {}", other).into()),
+    }
+}
+
+#[async_trait]
+impl Mailer for SmtpMailer {
+    async fn send(&self, email: &Email) -> bool {
+        let message = match build_message(&self.from, email) {
+            Some(message) => message,
+            None => return false,
+        };
+
+        match self.transport.send(message).await {
+            Ok(_) => true,
+            Err(err) => {
+                error!("Failed to send email to {}: {}", email.to, err);
+                false
+            }
+        }
+    }
+}
+
+#[async_trait]
+impl Mailer for DirectoryMailer {
+    async fn send(&self, email: &Email) -> bool {
+        let message = match build_message(&self.from, email) {
+            Some(message) => message,
+            None => return false,
+        };
+
+        if let Err(err) = tokio::fs::create_dir_all(&self.directory).await {
+            error!("Failed to create mail directory: {}", err);
+            return false;
+        }
+
+        let timestamp = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .expect("Time went backwards")
+            .as_millis();
+        let path = self
+            .directory
+            .join(format!("{}-{:x}.eml", timestamp, random::<u32>()));
+
+        match tokio::fs::write(&path, message.formatted()).await {
+            Ok(_) => {
+                info!("Wrote email to {} to {}", email.to, path.display());
+                true
+            }
+            Err(err) => {
+                error!("Failed to write email to {}: {}", path.display(), err);
+                false
+            }
+        }
+    }
+}
+
+fn build_message(from: &Mailbox, email: &Email) -> Option<Message> {
+    let to = email
+        .to
+        .parse::<Mailbox>()
+        .map_err(|err| error!("Invalid recipient {}: {}", email.to, err))
+        .ok()?;
+
+    Message::builder()
+        .from(from.clone())
+        .to(to)
+        .subject(&email.subject)
+        .header(ContentType::TEXT_PLAIN)
+        .body(email.body.clone())
+        .map_err(|err| error!("Failed to build email: {}", err))
+        .ok()
+}
@@ -1,27 +1,95 @@
+mod admin;
+mod authenticators;
+mod binding;
+mod claim_mappers;
+mod claims;
+mod config;
+mod cookies;
 mod errors;
+mod federation;
 mod flows;
+mod id_tokens;
+mod lockout;
+mod logout;
+mod mailer;
+mod mfa;
 mod pages;
+mod passwords;
+mod response_modes;
+mod serve_admin_clients;
+mod serve_admin_users;
+mod serve_applications;
 mod serve_authorization;
+mod serve_consent;
+mod serve_federation;
 mod serve_login;
+mod serve_logout;
+mod serve_metadata;
+mod serve_mfa;
+mod serve_password_reset;
+mod serve_register;
 mod serve_tokens;
+mod serve_userinfo;
+mod serve_webauthn;
 mod storage;
+mod webauthn;
 
+use crate::authenticators::{authenticators_from_config, Authenticator};
+use crate::claim_mappers::{claims_mappers_from_config, ClaimsMapper};
+use crate::lockout::unlock_account;
+use crate::mailer::{mailer_from_config, Mailer};
+use crate::serve_admin_clients::{
+    serve_admin_client, serve_admin_client_create, serve_admin_client_delete,
+    serve_admin_client_disable, serve_admin_client_enable, serve_admin_client_secret,
+    serve_admin_client_update, serve_admin_clients,
+};
+use crate::serve_admin_users::{
+    serve_admin_user, serve_admin_user_create, serve_admin_user_delete, serve_admin_user_disable,
+    serve_admin_user_enable, serve_admin_user_reset, serve_admin_user_unlock,
+    serve_admin_user_update, serve_admin_users,
+};
+use crate::serve_applications::{
+    serve_application_revoke, serve_applications, serve_applications_list,
+};
 use crate::serve_authorization::serve_authorization;
+use crate::serve_consent::serve_consent;
+use crate::serve_federation::{
+    serve_federated_callback, serve_federated_link, serve_federated_login,
+};
 use crate::serve_login::serve_login;
+use crate::serve_logout::{serve_logout, serve_logout_form};
+use crate::serve_metadata::serve_metadata;
+use crate::serve_mfa::{
+    serve_account_mfa, serve_account_mfa_enroll, serve_mfa, serve_mfa_continue, serve_mfa_enroll,
+};
+use crate::serve_password_reset::{
+    serve_forgot_password, serve_forgot_password_page, serve_reset_password,
+    serve_reset_password_page,
+};
+use crate::serve_register::{serve_register, serve_register_page, serve_verify_email};
 use crate::serve_tokens::serve_tokens;
+use crate::serve_userinfo::serve_userinfo;
+use crate::serve_webauthn::{
+    serve_webauthn, serve_webauthn_login, serve_webauthn_login_options, serve_webauthn_register,
+    serve_webauthn_register_options,
+};
 use crate::storage::cache::Cache;
 use crate::storage::database::Database;
-use axum::routing::{get, post};
+use axum::routing::{delete, get, post};
 use axum::Router;
 use dotenv::{dotenv, from_filename};
 use log::info;
 use std::env;
+use std::net::SocketAddr;
 use tokio::sync::OnceCell;
 use tokio_postgres::NoTls;
 
 // Static global instances
 static GLOBAL_CACHE: OnceCell<Cache> = OnceCell::const_new();
 static GLOBAL_DATABASE: OnceCell<Database> = OnceCell::const_new();
+static GLOBAL_MAILER: OnceCell<Box<dyn Mailer>> = OnceCell::const_new();
+static GLOBAL_AUTHENTICATORS: OnceCell<Vec<Box<dyn Authenticator>>> = OnceCell::const_new();
+static GLOBAL_CLAIMS_MAPPERS: OnceCell<Vec<Box<dyn ClaimsMapper>>> = OnceCell::const_new();
 
 async fn initialize_database() -> Result<(), Box<dyn std::error::Error>> {
     // Load database URL from environment
@@ -60,6 +128,33 @@ fn initialize_cache() -> Result<(), Box<dyn std::error::Error>> {
     Ok(())
 }
 
+fn initialize_mailer() -> Result<(), Box<dyn std::error::Error>> {
+    let mailer = mailer_from_config()?;
+    if GLOBAL_MAILER.set(mailer).is_err() {
+        panic!("Global mailer should only be initialized once");
+    }
+
+    Ok(())
+}
+
+fn initialize_authenticators() {
+    if GLOBAL_AUTHENTICATORS
+        .set(authenticators_from_config())
+        .is_err()
+    {
+        panic!("Global authenticators should only be initialized once");
+    }
+}
+
+fn initialize_claims_mappers() {
+    if GLOBAL_CLAIMS_MAPPERS
+        .set(claims_mappers_from_config())
+        .is_err()
+    {
+        panic!("Global claims mappers should only be initialized once");
+    }
+}
+
 #[tokio::main]
 async fn main() -> Result<(), Box<dyn std::error::Error>> {
     // Load environment variables
@@ -68,21 +163,132 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
     from_filename(env_file).ok();
     env_logger::init();
 
-    // Initialize the database and cache
+    // Initialize the database, cache, mailer, authenticators and claims mappers
     initialize_database().await?;
     initialize_cache()?;
+    initialize_mailer()?;
+    initialize_authenticators();
+    initialize_claims_mappers();
 
     info!("Initialization complete!");
+
+    // Administrative commands, `unlock <email>` lifts the login lockout of an account
+    let args: Vec<String> = env::args().collect();
+    if args.get(1).map(String::as_str) == Some("unlock") {
+        let email = args.get(2).expect("Usage: unlock <email>");
+        if unlock_account(email).await {
+            info!("Account {} has been unlocked", email);
+        } else {
+            info!("Account {} was not locked", email);
+        }
+        return Ok(());
+    }
+
     info!("Starting the server!");
 
     let app = Router::new()
         .route("/", get(root))
+        .route(
+            "/.well-known/oauth-authorization-server",
+            get(serve_metadata),
+        )
         .route("/authorize", get(serve_authorization))
         .route("/login", post(serve_login))
-        .route("/token", post(serve_tokens));
+        .route("/logout", get(serve_logout).post(serve_logout_form))
+        .route("/register", get(serve_register_page).post(serve_register))
+        .route("/verify-email", get(serve_verify_email))
+        .route(
+            "/forgot-password",
+            get(serve_forgot_password_page).post(serve_forgot_password),
+        )
+        .route(
+            "/reset-password",
+            get(serve_reset_password_page).post(serve_reset_password),
+        )
+        .route("/federated/login/:provider_id", post(serve_federated_login))
+        .route("/federated/callback", get(serve_federated_callback))
+        .route("/federated/link", post(serve_federated_link))
+        .route("/consent", post(serve_consent))
+        .route("/mfa", post(serve_mfa))
+        .route("/mfa/enroll", post(serve_mfa_enroll))
+        .route("/mfa/continue", post(serve_mfa_continue))
+        .route("/webauthn", get(serve_webauthn))
+        .route(
+            "/webauthn/register/options",
+            post(serve_webauthn_register_options),
+        )
+        .route("/webauthn/register", post(serve_webauthn_register))
+        .route(
+            "/webauthn/login/options",
+            post(serve_webauthn_login_options),
+        )
+        .route("/webauthn/login", post(serve_webauthn_login))
+        .route("/token", post(serve_tokens))
+        .route("/userinfo", get(serve_userinfo).post(serve_userinfo))
+        .route("/account/mfa", get(serve_account_mfa))
+        .route("/api/account/mfa", post(serve_account_mfa_enroll))
+        .route("/account/applications", get(serve_applications))
+        .route("/api/account/applications", get(serve_applications_list))
+        .route(
+            "/api/account/applications/:client_id",
+            delete(serve_application_revoke),
+        )
+        .route(
+            "/api/admin/clients",
+            get(serve_admin_clients).post(serve_admin_client_create),
+        )
+        .route(
+            "/api/admin/clients/:client_id",
+            get(serve_admin_client)
+                .patch(serve_admin_client_update)
+                .delete(serve_admin_client_delete),
+        )
+        .route(
+            "/api/admin/clients/:client_id/secret",
+            post(serve_admin_client_secret),
+        )
+        .route(
+            "/api/admin/clients/:client_id/enable",
+            post(serve_admin_client_enable),
+        )
+        .route(
+            "/api/admin/clients/:client_id/disable",
+            post(serve_admin_client_disable),
+        )
+        .route(
+            "/api/admin/users",
+            get(serve_admin_users).post(serve_admin_user_create),
+        )
+        .route(
+            "/api/admin/users/:user_id",
+            get(serve_admin_user)
+                .patch(serve_admin_user_update)
+                .delete(serve_admin_user_delete),
+        )
+        .route(
+            "/api/admin/users/:user_id/enable",
+            post(serve_admin_user_enable),
+        )
+        .route(
+            "/api/admin/users/:user_id/disable",
+            post(serve_admin_user_disable),
+        )
+        .route(
+            "/api/admin/users/:user_id/unlock",
+            post(serve_admin_user_unlock),
+        )
+        .route(
+            "/api/admin/users/:user_id/reset",
+            post(serve_admin_user_reset),
+        );
 
     let listener = tokio::net::TcpListener::bind("0.0.0.0:8080").await.unwrap();
-    axum::serve(listener, app).await.unwrap();
+    axum::serve(
+        listener,
+        app.into_make_service_with_connect_info::<SocketAddr>(),
+    )
+    .await
+    .unwrap();
 
     Ok(())
 }
@@ -0,0 +1,69 @@
+use crate::config::totp_issuer;
+use log::error;
+use rand::distributions::Alphanumeric;
+use rand::Rng;
+use sha2::{Digest, Sha256};
+use totp_rs::{Algorithm, Secret, TOTP};
+
+const RECOVERY_CODE_COUNT: usize = 10;
+
+/// Checks whether the authentication methods include a second factor,
+/// either a TOTP code or a WebAuthn credential.
+pub fn has_second_factor(amr: &[String]) -> bool {
+    amr.iter().any(|method| method == "otp" || method == "hwk")
+}
+
+/// Generates a new random TOTP secret, base32 encoded.
+pub fn generate_totp_secret() -> String {
+    Secret::generate_secret().to_encoded().to_string()
+}
+
+/// Builds a TOTP (RFC 6238) generator using SHA-1, 6 digits and a 30 second step,
+/// which is what authenticator apps support. One step of clock skew is allowed.
+pub fn build_totp(secret: &str, email: &str) -> Option<TOTP> {
+    let secret = Secret::Encoded(secret.to_string())
+        .to_bytes()
+        .map_err(|err| error!("Invalid TOTP secret: {:?}", err))
+        .ok()?;
+
+    TOTP::new(
+        Algorithm::SHA1,
+        6,
+        1,
+        30,
+        secret,
+        Some(totp_issuer()),
+        email.to_string(),
+    )
+    .map_err(|err| error!("Failed to create TOTP: {}", err))
+    .ok()
+}
+
+/// Checks a code against the current TOTP time step.
+pub fn verify_totp(secret: &str, email: &str, code: &str) -> bool {
+    build_totp(secret, email)
+        .and_then(|totp| totp.check_current(code.trim()).ok())
+        .unwrap_or(false)
+}
+
+/// Generates single-use recovery codes formatted as `xxxxx-xxxxx`.
+pub fn generate_recovery_codes() -> Vec<String> {
+    (0..RECOVERY_CODE_COUNT)
+        .map(|_| {
+            let code: String = rand::thread_rng()
+                .sample_iter(&Alphanumeric)
+                .take(10)
+                .map(|c| char::from(c).to_ascii_lowercase())
+                .collect();
+            format!("{}-{}", &code[..5], &code[5..])
+        })
+        .collect()
+}
+
+/// Recovery codes have enough entropy to be stored as a plain SHA-256 digest.
+pub fn hash_recovery_code(code: &str) -> String {
+    format!(
+        "{:x}",
+        Sha256::digest(code.trim().to_lowercase().as_bytes())
+    )
+}
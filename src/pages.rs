@@ -1,9 +1,10 @@
+use crate::serve_applications::AuthorizedApplication;
+use crate::storage::{IdentityProvider, Scope};
 use askama::Template;
 use axum::response::Html;
 
 #[derive(Template)]
 #[template(path = "error.html")]
-
 struct ErrorTemplate<'a> {
     message: &'a str,
     code: &'a str,
@@ -20,22 +21,30 @@ pub fn get_error_html<'a>(message: &'a str, code: &'a str) -> Html<String> {
 
 #[derive(Template)]
 #[template(path = "login.html")]
-
 struct LoginTemplate<'a> {
     client_name: &'a str,
     request_id: &'a str,
-    scope_list: &'a str,
+    scopes: &'a [Scope],
+    csrf_token: &'a str,
+    login_hint: &'a str,
+    providers: &'a [IdentityProvider],
 }
 
 pub fn get_login_html<'a>(
     client_name: &'a str,
     request_id: &'a str,
-    scope_list: &'a str,
+    scopes: &'a [Scope],
+    csrf_token: &'a str,
+    login_hint: &'a str,
+    providers: &'a [IdentityProvider],
 ) -> Html<String> {
     let html = LoginTemplate {
         client_name,
         request_id,
-        scope_list,
+        scopes,
+        csrf_token,
+        login_hint,
+        providers,
     };
 
     Html(
@@ -45,14 +54,390 @@ pub fn get_login_html<'a>(
 }
 
 #[derive(Template)]
-#[template(path = "login-error.html")]
+#[template(path = "consent.html")]
+struct ConsentTemplate<'a> {
+    client_name: &'a str,
+    request_id: &'a str,
+    csrf_token: &'a str,
+    scopes: &'a [Scope],
+    claims: &'a [(&'a str, &'a str)], // Name and description
+}
+
+pub fn get_consent_html<'a>(
+    client_name: &'a str,
+    request_id: &'a str,
+    csrf_token: &'a str,
+    scopes: &'a [Scope],
+    claims: &'a [(&'a str, &'a str)],
+) -> Html<String> {
+    let html = ConsentTemplate {
+        client_name,
+        request_id,
+        csrf_token,
+        scopes,
+        claims,
+    };
+
+    Html(
+        html.render()
+            .unwrap_or("An unknown error occurred".to_string()),
+    )
+}
+
+#[derive(Template)]
+#[template(path = "federation-link.html")]
+struct FederationLinkTemplate<'a> {
+    provider_name: &'a str,
+    identity_email: &'a str,
+    user_email: &'a str,
+    request_id: &'a str,
+    csrf_token: &'a str,
+}
+
+pub fn get_federation_link_html<'a>(
+    provider_name: &'a str,
+    identity_email: &'a str,
+    user_email: &'a str,
+    request_id: &'a str,
+    csrf_token: &'a str,
+) -> Html<String> {
+    let html = FederationLinkTemplate {
+        provider_name,
+        identity_email,
+        user_email,
+        request_id,
+        csrf_token,
+    };
+
+    Html(
+        html.render()
+            .unwrap_or("An unknown error occurred".to_string()),
+    )
+}
+
+#[derive(Template)]
+#[template(path = "mfa.html")]
+struct MfaTemplate<'a> {
+    request_id: &'a str,
+    csrf_token: &'a str,
+    has_totp: bool,
+    has_webauthn: bool,
+    invalid: bool,
+}
+
+pub fn get_mfa_html(
+    request_id: &str,
+    csrf_token: &str,
+    has_totp: bool,
+    has_webauthn: bool,
+    invalid: bool,
+) -> Html<String> {
+    let html = MfaTemplate {
+        request_id,
+        csrf_token,
+        has_totp,
+        has_webauthn,
+        invalid,
+    };
+
+    Html(
+        html.render()
+            .unwrap_or("An unknown error occurred".to_string()),
+    )
+}
+
+#[derive(Template)]
+#[template(path = "mfa-enroll.html")]
+struct MfaEnrollTemplate<'a> {
+    request_id: &'a str,
+    csrf_token: &'a str,
+    secret: &'a str,
+    otpauth_url: &'a str,
+    qr_code: &'a str,
+    invalid: bool,
+}
 
+pub fn get_mfa_enroll_html<'a>(
+    request_id: &'a str,
+    csrf_token: &'a str,
+    secret: &'a str,
+    otpauth_url: &'a str,
+    qr_code: &'a str,
+    invalid: bool,
+) -> Html<String> {
+    let html = MfaEnrollTemplate {
+        request_id,
+        csrf_token,
+        secret,
+        otpauth_url,
+        qr_code,
+        invalid,
+    };
+
+    Html(
+        html.render()
+            .unwrap_or("An unknown error occurred".to_string()),
+    )
+}
+
+#[derive(Template)]
+#[template(path = "recovery-codes.html")]
+struct RecoveryCodesTemplate<'a> {
+    request_id: &'a str,
+    csrf_token: &'a str,
+    recovery_codes: &'a [String],
+}
+
+pub fn get_recovery_codes_html<'a>(
+    request_id: &'a str,
+    csrf_token: &'a str,
+    recovery_codes: &'a [String],
+) -> Html<String> {
+    let html = RecoveryCodesTemplate {
+        request_id,
+        csrf_token,
+        recovery_codes,
+    };
+
+    Html(
+        html.render()
+            .unwrap_or("An unknown error occurred".to_string()),
+    )
+}
+
+#[derive(Template)]
+#[template(path = "account-mfa.html")]
+struct AccountMfaTemplate<'a> {
+    email: &'a str,
+    enrolled: bool,
+    secret: &'a str,
+    otpauth_url: &'a str,
+    qr_code: &'a str,
+}
+
+pub fn get_account_mfa_html<'a>(
+    email: &'a str,
+    enrolled: bool,
+    secret: &'a str,
+    otpauth_url: &'a str,
+    qr_code: &'a str,
+) -> Html<String> {
+    let html = AccountMfaTemplate {
+        email,
+        enrolled,
+        secret,
+        otpauth_url,
+        qr_code,
+    };
+
+    Html(
+        html.render()
+            .unwrap_or("An unknown error occurred".to_string()),
+    )
+}
+
+#[derive(Template)]
+#[template(path = "webauthn.html")]
+struct WebauthnTemplate<'a> {
+    email: &'a str,
+    credential_names: &'a [String],
+}
+
+pub fn get_webauthn_html<'a>(email: &'a str, credential_names: &'a [String]) -> Html<String> {
+    let html = WebauthnTemplate {
+        email,
+        credential_names,
+    };
+
+    Html(
+        html.render()
+            .unwrap_or("An unknown error occurred".to_string()),
+    )
+}
+
+#[derive(Template)]
+#[template(path = "applications.html")]
+struct ApplicationsTemplate<'a> {
+    email: &'a str,
+    applications: &'a [AuthorizedApplication],
+}
+
+pub fn get_applications_html<'a>(
+    email: &'a str,
+    applications: &'a [AuthorizedApplication],
+) -> Html<String> {
+    let html = ApplicationsTemplate {
+        email,
+        applications,
+    };
+
+    Html(
+        html.render()
+            .unwrap_or("An unknown error occurred".to_string()),
+    )
+}
+
+#[derive(Template)]
+#[template(path = "form-post.html")]
+struct FormPostTemplate<'a> {
+    redirect_uri: &'a str,
+    params: &'a [(&'a str, &'a str)],
+}
+
+pub fn get_form_post_html<'a>(
+    redirect_uri: &'a str,
+    params: &'a [(&'a str, &'a str)],
+) -> Html<String> {
+    let html = FormPostTemplate {
+        redirect_uri,
+        params,
+    };
+
+    Html(
+        html.render()
+            .unwrap_or("An unknown error occurred".to_string()),
+    )
+}
+
+#[derive(Template)]
+#[template(path = "login-error.html")]
 struct LoginError {}
 
-pub fn get_login_error_html<'a>() -> Html<String> {
+pub fn get_login_error_html() -> Html<String> {
     Html(
         LoginError {}
             .render()
             .unwrap_or("An unknown error occurred".to_string()),
     )
 }
+
+#[derive(Template)]
+#[template(path = "register.html")]
+struct RegisterTemplate<'a> {
+    email: &'a str,
+    errors: &'a [String],
+    requirements: &'a [String],
+}
+
+pub fn get_register_html<'a>(
+    email: &'a str,
+    errors: &'a [String],
+    requirements: &'a [String],
+) -> Html<String> {
+    let html = RegisterTemplate {
+        email,
+        errors,
+        requirements,
+    };
+
+    Html(
+        html.render()
+            .unwrap_or("An unknown error occurred".to_string()),
+    )
+}
+
+#[derive(Template)]
+#[template(path = "notice.html")]
+struct NoticeTemplate<'a> {
+    title: &'a str,
+    message: &'a str,
+}
+
+pub fn get_notice_html<'a>(title: &'a str, message: &'a str) -> Html<String> {
+    let html = NoticeTemplate { title, message };
+
+    Html(
+        html.render()
+            .unwrap_or("An unknown error occurred".to_string()),
+    )
+}
+
+#[derive(Template)]
+#[template(path = "forgot-password.html")]
+struct ForgotPasswordTemplate {}
+
+pub fn get_forgot_password_html() -> Html<String> {
+    Html(
+        ForgotPasswordTemplate {}
+            .render()
+            .unwrap_or("An unknown error occurred".to_string()),
+    )
+}
+
+#[derive(Template)]
+#[template(path = "reset-password.html")]
+struct ResetPasswordTemplate<'a> {
+    token: &'a str,
+    errors: &'a [String],
+    requirements: &'a [String],
+}
+
+pub fn get_reset_password_html<'a>(
+    token: &'a str,
+    errors: &'a [String],
+    requirements: &'a [String],
+) -> Html<String> {
+    let html = ResetPasswordTemplate {
+        token,
+        errors,
+        requirements,
+    };
+
+    Html(
+        html.render()
+            .unwrap_or("An unknown error occurred".to_string()),
+    )
+}
+
+#[derive(Template)]
+#[template(path = "logout-confirm.html")]
+struct LogoutConfirmTemplate<'a> {
+    client_name: Option<&'a str>,
+    client_id: Option<&'a str>,
+    post_logout_redirect_uri: Option<&'a str>,
+    state: Option<&'a str>,
+    csrf_token: &'a str,
+}
+
+pub fn get_logout_confirm_html<'a>(
+    client_name: Option<&'a str>,
+    client_id: Option<&'a str>,
+    post_logout_redirect_uri: Option<&'a str>,
+    state: Option<&'a str>,
+    csrf_token: &'a str,
+) -> Html<String> {
+    let html = LogoutConfirmTemplate {
+        client_name,
+        client_id,
+        post_logout_redirect_uri,
+        state,
+        csrf_token,
+    };
+
+    Html(
+        html.render()
+            .unwrap_or("An unknown error occurred".to_string()),
+    )
+}
+
+#[derive(Template)]
+#[template(path = "logout.html")]
+struct LogoutTemplate<'a> {
+    frontchannel_logout_uris: &'a [String],
+    redirect_uri: Option<&'a str>,
+}
+
+pub fn get_logout_html<'a>(
+    frontchannel_logout_uris: &'a [String],
+    redirect_uri: Option<&'a str>,
+) -> Html<String> {
+    let html = LogoutTemplate {
+        frontchannel_logout_uris,
+        redirect_uri,
+    };
+
+    Html(
+        html.render()
+            .unwrap_or("An unknown error occurred".to_string()),
+    )
+}
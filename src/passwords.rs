@@ -0,0 +1,411 @@
+use crate::config::{
+    issuer_url, password_min_length, password_required_classes, password_reset_ttl,
+};
+use crate::mailer::Email;
+use crate::storage::User;
+use crate::{GLOBAL_CACHE, GLOBAL_DATABASE, GLOBAL_MAILER};
+use argon2::password_hash::rand_core::OsRng;
+use argon2::password_hash::{PasswordHash, PasswordHasher, PasswordVerifier, SaltString};
+use argon2::{Algorithm, Argon2, Params};
+use log::{debug, error, warn};
+use pbkdf2::Pbkdf2;
+use rand::distributions::Alphanumeric;
+use rand::Rng;
+use sha2::{Digest, Sha256};
+
+// Verified against when the email is unknown, so both cases take about the same time
+const DUMMY_PASSWORD_HASH: &str =
+    "$argon2id$v=19$m=19456,t=2,p=1$c29tZXNhbHRzb21lc2FsdA$7lLz9tdlCgtlhLt5MI1M/mEY34WWgFfTpX7SZxHGsVU";
+
+/// Stored for users without a password, e.g. provisioned by an upstream identity provider.
+/// No password verifies against it.
+pub const UNUSABLE_PASSWORD_HASH: &str = "!";
+
+#[derive(Debug, PartialEq)]
+pub enum PasswordVerification {
+    Invalid,
+    Valid,
+    /// The password matches a legacy or outdated hash that should be replaced.
+    ValidNeedsRehash,
+}
+
+/// Hashes a password into a PHC string using Argon2id with the default parameters.
+pub fn hash_password(password: &str) -> Option<String> {
+    let salt = SaltString::generate(&mut OsRng);
+
+    Argon2::default()
+        .hash_password(password.as_bytes(), &salt)
+        .map(|hash| hash.to_string())
+        .map_err(|err| error!("Failed to hash password: {}", err))
+        .ok()
+}
+
+/// Hashes a password like `hash_password` on the blocking thread pool. Hashing is CPU heavy,
+/// it would stall the async workers.
+pub async fn hash_password_blocking(password: &str) -> Option<String> {
+    let password = password.to_string();
+
+    tokio::task::spawn_blocking(move || hash_password(&password))
+        .await
+        .map_err(|err| error!("Password hashing task failed: {}", err))
+        .ok()
+        .flatten()
+}
+
+/// Verifies a password against a stored hash. Argon2id is current, bcrypt and PBKDF2
+/// hashes are accepted for imported users and reported as needing a rehash.
+pub fn verify_password(password: &str, password_hash: &str) -> PasswordVerification {
+    if password_hash == UNUSABLE_PASSWORD_HASH {
+        return PasswordVerification::Invalid;
+    }
+
+    // bcrypt uses its own modular crypt format instead of a PHC string
+    if ["$2a$", "$2b$", "$2y$"]
+        .iter()
+        .any(|prefix| password_hash.starts_with(prefix))
+    {
+        return match bcrypt::verify(password, password_hash) {
+            Ok(true) => PasswordVerification::ValidNeedsRehash,
+            Ok(false) => PasswordVerification::Invalid,
+            Err(err) => {
+                warn!("Failed to verify bcrypt hash: {}", err);
+                PasswordVerification::Invalid
+            }
+        };
+    }
+
+    let parsed_hash = match PasswordHash::new(password_hash) {
+        Ok(hash) => hash,
+        Err(err) => {
+            warn!("Stored password hash is not a PHC string: {}", err);
+            return PasswordVerification::Invalid;
+        }
+    };
+
+    match parsed_hash.algorithm.as_str() {
+        "argon2id" | "argon2i" | "argon2d" => {
+            if Argon2::default()
+                .verify_password(password.as_bytes(), &parsed_hash)
+                .is_err()
+            {
+                return PasswordVerification::Invalid;
+            }
+
+            // Upgrade hashes using another variant or outdated parameters
+            let current_params = Params::default();
+            let is_current = parsed_hash.algorithm == Algorithm::Argon2id.ident()
+                && Params::try_from(&parsed_hash)
+                    .map(|params| {
+                        params.m_cost() == current_params.m_cost()
+                            && params.t_cost() == current_params.t_cost()
+                            && params.p_cost() == current_params.p_cost()
+                    })
+                    .unwrap_or(false);
+
+            if is_current {
+                PasswordVerification::Valid
+            } else {
+                PasswordVerification::ValidNeedsRehash
+            }
+        }
+        "pbkdf2" | "pbkdf2-sha256" | "pbkdf2-sha512" => {
+            match Pbkdf2.verify_password(password.as_bytes(), &parsed_hash) {
+                Ok(_) => PasswordVerification::ValidNeedsRehash,
+                Err(_) => PasswordVerification::Invalid,
+            }
+        }
+        algorithm => {
+            warn!("Unsupported password hash algorithm: {}", algorithm);
+            PasswordVerification::Invalid
+        }
+    }
+}
+
+/// Finds a user by email and verifies their password. Legacy hashes are
+/// transparently re-hashed with Argon2id after a successful login.
+pub async fn authenticate_user(email: &str, password: &str) -> Option<User> {
+    let database = GLOBAL_DATABASE.get().unwrap();
+    let user = database.get_user(email).await;
+
+    // Hashing is CPU bound, keep it off the async runtime
+    let password = password.to_string();
+    let password_hash = user
+        .as_ref()
+        .map(|user| user.password_hash.clone())
+        .unwrap_or_else(|| DUMMY_PASSWORD_HASH.to_string());
+    let verification =
+        tokio::task::spawn_blocking(move || match verify_password(&password, &password_hash) {
+            PasswordVerification::ValidNeedsRehash => (true, hash_password(&password)),
+            verification => (verification == PasswordVerification::Valid, None),
+        })
+        .await;
+
+    let (is_valid, new_password_hash) = match verification {
+        Ok(result) => result,
+        Err(err) => {
+            error!("Password verification task failed: {}", err);
+            return None;
+        }
+    };
+
+    let user = user.filter(|_| is_valid)?;
+
+    if let Some(new_password_hash) = new_password_hash {
+        if database
+            .update_password_hash(&user.id, &new_password_hash)
+            .await
+        {
+            debug!("Upgraded password hash for user {}", user.id);
+        } else {
+            error!("Failed to upgrade password hash for user {}", user.id);
+        }
+    }
+
+    Some(user)
+}
+
+/// Whether the user can choose a password here with a reset link, see `CredentialSource`.
+pub async fn allows_password_reset(user: &User) -> bool {
+    GLOBAL_DATABASE
+        .get()
+        .unwrap()
+        .get_credential_source(&user.id)
+        .await
+        .is_some_and(|source| source.allows_password_reset())
+}
+
+/// Emails the user a link for choosing a new password. Returns whether the email was sent.
+pub async fn send_password_reset(user: &User) -> bool {
+    // Only the hash is stored, so the cache never holds a usable token
+    let token: String = rand::thread_rng()
+        .sample_iter(&Alphanumeric)
+        .take(48)
+        .map(char::from)
+        .collect();
+    let ttl = password_reset_ttl();
+    GLOBAL_CACHE
+        .get()
+        .unwrap()
+        .set_password_reset(&hash_reset_token(&token), user.id, ttl);
+
+    let email = Email {
+        to: user.email.clone(),
+        subject: "Reset your password".to_string(),
+        body: format!(
+            "Follow this link within {} minutes to choose a new password:\n\n\
+             {}/reset-password?token={}\n\n\
+             If you did not ask to reset your password, you can ignore this email.",
+            ttl / 60,
+            issuer_url().trim_end_matches('/'),
+            token
+        ),
+    };
+
+    GLOBAL_MAILER.get().unwrap().send(&email).await
+}
+
+/// The key a password reset token is stored under.
+pub fn hash_reset_token(token: &str) -> String {
+    format!("{:x}", Sha256::digest(token.as_bytes()))
+}
+
+/// The requirements for new passwords, from the configuration.
+pub struct PasswordPolicy {
+    pub min_length: usize,
+    pub required_classes: Vec<String>,
+}
+
+// Longer passwords only make hashing expensive
+const MAX_PASSWORD_LENGTH: usize = 128;
+
+impl PasswordPolicy {
+    pub fn from_config() -> Self {
+        PasswordPolicy {
+            min_length: password_min_length(),
+            required_classes: password_required_classes(),
+        }
+    }
+
+    /// Describes the requirements, for showing them next to password fields.
+    pub fn requirements(&self) -> Vec<String> {
+        let mut requirements = vec![format!(
+            "Between {} and {} characters",
+            self.min_length, MAX_PASSWORD_LENGTH
+        )];
+        requirements.extend(
+            self.required_classes
+                .iter()
+                .map(|class| format!("At least one {} character", class)),
+        );
+        requirements
+    }
+
+    /// Checks a new password and returns the requirements it violates.
+    pub fn check(&self, password: &str, email: &str) -> Vec<String> {
+        let mut violations = Vec::new();
+
+        let length = password.chars().count();
+        if length < self.min_length || length > MAX_PASSWORD_LENGTH {
+            violations.push(format!(
+                "The password must be between {} and {} characters long",
+                self.min_length, MAX_PASSWORD_LENGTH
+            ));
+        }
+
+        for class in &self.required_classes {
+            let matches = |c: char| match class.as_str() {
+                "lowercase" => c.is_lowercase(),
+                "uppercase" => c.is_uppercase(),
+                "digit" => c.is_ascii_digit(),
+                "symbol" => !c.is_alphanumeric() && !c.is_whitespace(),
+                _ => true,
+            };
+            if !password.chars().any(matches) {
+                violations.push(format!(
+                    "The password must contain at least one {} character",
+                    class
+                ));
+            }
+        }
+
+        if !email.is_empty() && password.to_lowercase().contains(&email.to_lowercase()) {
+            violations.push("The password must not contain the email address".to_string());
+        }
+
+        violations
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pbkdf2::password_hash::Ident;
+
+    const PASSWORD: &str = "correct horse battery staple";
+
+    #[test]
+    fn verifies_current_argon2id_hashes() {
+        let hash = hash_password(PASSWORD).unwrap();
+
+        assert_eq!(
+            verify_password(PASSWORD, &hash),
+            PasswordVerification::Valid
+        );
+        assert_eq!(
+            verify_password("wrong password", &hash),
+            PasswordVerification::Invalid
+        );
+    }
+
+    #[test]
+    fn rehashes_argon2_with_outdated_parameters_or_variant() {
+        let salt = SaltString::generate(&mut OsRng);
+        let outdated_params = Params::new(8192, 1, 1, None).unwrap();
+        let outdated = Argon2::new(Algorithm::Argon2id, Default::default(), outdated_params)
+            .hash_password(PASSWORD.as_bytes(), &salt)
+            .unwrap()
+            .to_string();
+        let argon2i = Argon2::new(Algorithm::Argon2i, Default::default(), Params::default())
+            .hash_password(PASSWORD.as_bytes(), &salt)
+            .unwrap()
+            .to_string();
+
+        for hash in [outdated, argon2i] {
+            assert_eq!(
+                verify_password(PASSWORD, &hash),
+                PasswordVerification::ValidNeedsRehash
+            );
+            assert_eq!(
+                verify_password("wrong password", &hash),
+                PasswordVerification::Invalid
+            );
+        }
+    }
+
+    #[test]
+    fn verifies_bcrypt_hashes_and_asks_for_a_rehash() {
+        for version in [
+            bcrypt::Version::TwoA,
+            bcrypt::Version::TwoB,
+            bcrypt::Version::TwoY,
+        ] {
+            let hash = bcrypt::hash_with_result(PASSWORD, 4)
+                .unwrap()
+                .format_for_version(version);
+
+            assert_eq!(
+                verify_password(PASSWORD, &hash),
+                PasswordVerification::ValidNeedsRehash
+            );
+            assert_eq!(
+                verify_password("wrong password", &hash),
+                PasswordVerification::Invalid
+            );
+        }
+    }
+
+    #[test]
+    fn verifies_pbkdf2_hashes_and_asks_for_a_rehash() {
+        let salt = SaltString::generate(&mut OsRng);
+        let params = pbkdf2::Params {
+            rounds: 1000,
+            output_length: 32,
+        };
+
+        for algorithm in [
+            pbkdf2::Algorithm::Pbkdf2Sha1,
+            pbkdf2::Algorithm::Pbkdf2Sha256,
+            pbkdf2::Algorithm::Pbkdf2Sha512,
+        ] {
+            let hash = Pbkdf2
+                .hash_password_customized(
+                    PASSWORD.as_bytes(),
+                    Some(Ident::new(algorithm.as_str()).unwrap()),
+                    None,
+                    params,
+                    &salt,
+                )
+                .unwrap()
+                .to_string();
+
+            assert_eq!(
+                verify_password(PASSWORD, &hash),
+                PasswordVerification::ValidNeedsRehash
+            );
+            assert_eq!(
+                verify_password("wrong password", &hash),
+                PasswordVerification::Invalid
+            );
+        }
+    }
+
+    #[test]
+    fn rejects_unusable_and_unknown_hashes() {
+        for hash in [
+            UNUSABLE_PASSWORD_HASH,
+            "",
+            PASSWORD,
+            "$scrypt$ln=16,r=8,p=1$c29tZXNhbHQ$aGFzaA",
+            "$2b$not-a-bcrypt-hash",
+        ] {
+            assert_eq!(
+                verify_password(PASSWORD, hash),
+                PasswordVerification::Invalid
+            );
+        }
+    }
+
+    #[test]
+    fn dummy_hash_uses_the_current_parameters() {
+        // Otherwise unknown emails would be verified faster than known ones
+        assert_eq!(
+            verify_password("wrong password", DUMMY_PASSWORD_HASH),
+            PasswordVerification::Invalid
+        );
+        let parsed_hash = PasswordHash::new(DUMMY_PASSWORD_HASH).unwrap();
+        let params = Params::try_from(&parsed_hash).unwrap();
+        assert_eq!(params.m_cost(), Params::default().m_cost());
+        assert_eq!(params.t_cost(), Params::default().t_cost());
+        assert_eq!(params.p_cost(), Params::default().p_cost());
+    }
+}
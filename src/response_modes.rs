@@ -0,0 +1,58 @@
+use crate::config::issuer_url;
+use crate::pages::get_form_post_html;
+use axum::response::{IntoResponse, Redirect, Response};
+use urlencoding::encode;
+
+/// How the authorization response parameters are returned to the client.
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub enum ResponseMode {
+    /// Parameters are added to the query component of the redirect URI.
+    #[default]
+    Query,
+    /// Parameters are added to the fragment component of the redirect URI.
+    Fragment,
+    /// Parameters are POSTed to the redirect URI by an auto-submitting HTML form.
+    FormPost,
+}
+
+impl ResponseMode {
+    pub fn parse(response_mode: &str) -> Option<Self> {
+        match response_mode {
+            "query" => Some(ResponseMode::Query),
+            "fragment" => Some(ResponseMode::Fragment),
+            "form_post" => Some(ResponseMode::FormPost),
+            _ => None,
+        }
+    }
+}
+
+/// Returns the authorization response parameters to the client using the given response mode.
+/// The issuer identifier is always included so clients can detect mix-up attacks (RFC 9207).
+pub fn authorization_response(
+    redirect_uri: &str,
+    response_mode: ResponseMode,
+    params: &[(&str, &str)],
+) -> Response {
+    let issuer = issuer_url();
+    let mut params = params.to_vec();
+    params.push(("iss", issuer.as_str()));
+
+    if response_mode == ResponseMode::FormPost {
+        return get_form_post_html(redirect_uri, &params).into_response();
+    }
+
+    let encoded_params = params
+        .iter()
+        .map(|(name, value)| format!("{}={}", name, encode(value)))
+        .collect::<Vec<String>>()
+        .join("&");
+
+    // Keep any query component already registered with the redirect URI
+    let separator = match response_mode {
+        ResponseMode::Query if redirect_uri.contains('?') => "&",
+        ResponseMode::Query => "?",
+        _ => "#",
+    };
+
+    Redirect::to(&format!("{}{}{}", redirect_uri, separator, encoded_params)).into_response()
+}
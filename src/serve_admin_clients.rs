@@ -0,0 +1,393 @@
+use crate::admin::{authorize_admin, record_admin_event, ListParams};
+use crate::errors::bearer_error;
+use crate::storage::{get_scope, Client, GRANT_TYPES};
+use crate::{GLOBAL_AUTHENTICATORS, GLOBAL_CACHE, GLOBAL_DATABASE};
+use axum::extract::{Json, Path, Query};
+use axum::http::{HeaderMap, StatusCode};
+use axum::response::{IntoResponse, Response};
+use log::{error, info};
+use rand::distributions::Alphanumeric;
+use rand::Rng;
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use validator::ValidateUrl;
+
+// Secrets set by admins must be at least this long, generated ones are longer
+const MIN_SECRET_LENGTH: usize = 32;
+
+// Request body for creating or updating a client, omitted fields are left unchanged
+#[derive(Deserialize)]
+pub struct ClientRequest {
+    name: Option<String>,
+    redirect_uris: Option<Vec<String>>,
+    allowed_scopes: Option<Vec<String>>,
+    default_scopes: Option<Vec<String>>,
+    grant_types: Option<Vec<String>>,
+    secret: Option<String>, // Generated when a client is created without one
+    mfa_required: Option<bool>,
+    authenticators: Option<Vec<String>>,
+    post_logout_redirect_uris: Option<Vec<String>>,
+    frontchannel_logout_uri: Option<String>, // Empty to remove it
+    backchannel_logout_uri: Option<String>,  // Empty to remove it
+}
+
+// A client as returned by the admin API, the secret only when it was just set
+#[derive(Serialize)]
+struct ClientResponse {
+    id: u32,
+    name: String,
+    redirect_uris: Vec<String>,
+    allowed_scopes: Vec<String>,
+    default_scopes: Vec<String>,
+    grant_types: Vec<String>,
+    mfa_required: bool,
+    authenticators: Vec<String>,
+    post_logout_redirect_uris: Vec<String>,
+    frontchannel_logout_uri: Option<String>,
+    backchannel_logout_uri: Option<String>,
+    enabled: bool,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    secret: Option<String>,
+}
+
+impl ClientResponse {
+    fn new(client: Client, with_secret: bool) -> Self {
+        ClientResponse {
+            id: client.id,
+            name: client.name,
+            redirect_uris: client.redirect_uris,
+            allowed_scopes: client.allowed_scopes,
+            default_scopes: client.default_scopes,
+            grant_types: client.grant_types,
+            mfa_required: client.mfa_required,
+            authenticators: client.authenticators,
+            post_logout_redirect_uris: client.post_logout_redirect_uris,
+            frontchannel_logout_uri: client.frontchannel_logout_uri,
+            backchannel_logout_uri: client.backchannel_logout_uri,
+            enabled: client.enabled,
+            secret: with_secret.then_some(client.secret),
+        }
+    }
+}
+
+/// Lists the clients, a page at a time.
+pub async fn serve_admin_clients(headers: HeaderMap, Query(params): Query<ListParams>) -> Response {
+    if let Err((status, error)) = authorize_admin(&headers).await {
+        return bearer_error(status, error);
+    }
+
+    let database = GLOBAL_DATABASE.get().unwrap();
+    let clients = database
+        .get_clients(params.search(), params.per_page(), params.offset())
+        .await
+        .into_iter()
+        .map(|client| ClientResponse::new(client, false))
+        .collect::<Vec<ClientResponse>>();
+
+    Json(json!({
+        "clients": clients,
+        "page": params.page(),
+        "per_page": params.per_page(),
+        "total": database.count_clients(params.search()).await,
+    }))
+    .into_response()
+}
+
+/// Registers a client. The response has the secret, it isn't returned again.
+pub async fn serve_admin_client_create(
+    headers: HeaderMap,
+    Json(payload): Json<ClientRequest>,
+) -> Response {
+    let admin_id = match authorize_admin(&headers).await {
+        Ok(admin_id) => admin_id,
+        Err((status, error)) => return bearer_error(status, error),
+    };
+
+    let mut client = Client {
+        id: 0,
+        name: String::new(),
+        allowed_scopes: Vec::new(),
+        redirect_uris: Vec::new(),
+        secret: generate_secret(),
+        mfa_required: false,
+        authenticators: vec!["database".to_string()],
+        default_scopes: Vec::new(),
+        post_logout_redirect_uris: Vec::new(),
+        frontchannel_logout_uri: None,
+        backchannel_logout_uri: None,
+        claim_mappings: Vec::new(),
+        enabled: true,
+        grant_types: GRANT_TYPES.iter().map(|g| g.to_string()).collect(),
+    };
+    if payload.has_short_secret() {
+        return (StatusCode::BAD_REQUEST, "The secret is too short").into_response();
+    }
+    payload.apply(&mut client);
+
+    if let Err(message) = validate_client(&client).await {
+        return (StatusCode::BAD_REQUEST, message).into_response();
+    }
+
+    client.id = match GLOBAL_DATABASE.get().unwrap().create_client(&client).await {
+        Some(id) => id,
+        None => {
+            error!("Failed to create client {}", client.name);
+            return (StatusCode::INTERNAL_SERVER_ERROR, "Failed to create client").into_response();
+        }
+    };
+    GLOBAL_CACHE
+        .get()
+        .unwrap()
+        .delete_client(&client.id.to_string());
+
+    record_admin_event("client_created", &admin_id, None, &client.id.to_string()).await;
+    info!("Admin {} created client {}", admin_id, client.id);
+
+    (StatusCode::CREATED, Json(ClientResponse::new(client, true))).into_response()
+}
+
+pub async fn serve_admin_client(headers: HeaderMap, Path(client_id): Path<u32>) -> Response {
+    if let Err((status, error)) = authorize_admin(&headers).await {
+        return bearer_error(status, error);
+    }
+
+    match GLOBAL_DATABASE.get().unwrap().get_client(&client_id).await {
+        Some(client) => Json(ClientResponse::new(client, false)).into_response(),
+        None => (StatusCode::NOT_FOUND, "Unknown client").into_response(),
+    }
+}
+
+/// Updates the fields of a client that are in the request.
+pub async fn serve_admin_client_update(
+    headers: HeaderMap,
+    Path(client_id): Path<u32>,
+    Json(payload): Json<ClientRequest>,
+) -> Response {
+    let admin_id = match authorize_admin(&headers).await {
+        Ok(admin_id) => admin_id,
+        Err((status, error)) => return bearer_error(status, error),
+    };
+
+    let database = GLOBAL_DATABASE.get().unwrap();
+    let mut client = match database.get_client(&client_id).await {
+        Some(client) => client,
+        None => return (StatusCode::NOT_FOUND, "Unknown client").into_response(),
+    };
+
+    if payload.has_short_secret() {
+        return (StatusCode::BAD_REQUEST, "The secret is too short").into_response();
+    }
+    let secret_changed = payload.secret.is_some();
+    payload.apply(&mut client);
+
+    if let Err(message) = validate_client(&client).await {
+        return (StatusCode::BAD_REQUEST, message).into_response();
+    }
+
+    if !update_client(&admin_id, &client, "client_updated").await {
+        return (StatusCode::INTERNAL_SERVER_ERROR, "Failed to update client").into_response();
+    }
+    Json(ClientResponse::new(client, secret_changed)).into_response()
+}
+
+/// Deletes a client with the consents users gave it. Its tokens can't be used anymore.
+pub async fn serve_admin_client_delete(headers: HeaderMap, Path(client_id): Path<u32>) -> Response {
+    let admin_id = match authorize_admin(&headers).await {
+        Ok(admin_id) => admin_id,
+        Err((status, error)) => return bearer_error(status, error),
+    };
+
+    if !GLOBAL_DATABASE
+        .get()
+        .unwrap()
+        .delete_client(&client_id)
+        .await
+    {
+        return (StatusCode::NOT_FOUND, "Unknown client").into_response();
+    }
+    GLOBAL_CACHE
+        .get()
+        .unwrap()
+        .delete_client(&client_id.to_string());
+
+    record_admin_event("client_deleted", &admin_id, None, &client_id.to_string()).await;
+    info!("Admin {} deleted client {}", admin_id, client_id);
+
+    StatusCode::NO_CONTENT.into_response()
+}
+
+/// Replaces the secret of a client with a generated one, which the response has.
+pub async fn serve_admin_client_secret(headers: HeaderMap, Path(client_id): Path<u32>) -> Response {
+    let admin_id = match authorize_admin(&headers).await {
+        Ok(admin_id) => admin_id,
+        Err((status, error)) => return bearer_error(status, error),
+    };
+
+    let mut client = match GLOBAL_DATABASE.get().unwrap().get_client(&client_id).await {
+        Some(client) => client,
+        None => return (StatusCode::NOT_FOUND, "Unknown client").into_response(),
+    };
+    client.secret = generate_secret();
+
+    if !update_client(&admin_id, &client, "client_secret_regenerated").await {
+        return (StatusCode::INTERNAL_SERVER_ERROR, "Failed to update client").into_response();
+    }
+    Json(ClientResponse::new(client, true)).into_response()
+}
+
+/// Enables a client again.
+pub async fn serve_admin_client_enable(headers: HeaderMap, Path(client_id): Path<u32>) -> Response {
+    set_client_enabled(&headers, client_id, true).await
+}
+
+/// Disables a client, users can't sign in to it and its tokens can't be refreshed.
+pub async fn serve_admin_client_disable(
+    headers: HeaderMap,
+    Path(client_id): Path<u32>,
+) -> Response {
+    set_client_enabled(&headers, client_id, false).await
+}
+
+async fn set_client_enabled(headers: &HeaderMap, client_id: u32, enabled: bool) -> Response {
+    let admin_id = match authorize_admin(headers).await {
+        Ok(admin_id) => admin_id,
+        Err((status, error)) => return bearer_error(status, error),
+    };
+
+    let mut client = match GLOBAL_DATABASE.get().unwrap().get_client(&client_id).await {
+        Some(client) => client,
+        None => return (StatusCode::NOT_FOUND, "Unknown client").into_response(),
+    };
+    client.enabled = enabled;
+
+    let event = if enabled {
+        "client_enabled"
+    } else {
+        "client_disabled"
+    };
+    if !update_client(&admin_id, &client, event).await {
+        return (StatusCode::INTERNAL_SERVER_ERROR, "Failed to update client").into_response();
+    }
+    Json(ClientResponse::new(client, false)).into_response()
+}
+
+impl ClientRequest {
+    fn has_short_secret(&self) -> bool {
+        self.secret
+            .as_ref()
+            .is_some_and(|secret| secret.len() < MIN_SECRET_LENGTH)
+    }
+
+    fn apply(self, client: &mut Client) {
+        if let Some(name) = self.name {
+            client.name = name.trim().to_string();
+        }
+        if let Some(redirect_uris) = self.redirect_uris {
+            client.redirect_uris = redirect_uris;
+        }
+        if let Some(allowed_scopes) = self.allowed_scopes {
+            client.allowed_scopes = allowed_scopes;
+        }
+        if let Some(default_scopes) = self.default_scopes {
+            client.default_scopes = default_scopes;
+        }
+        if let Some(grant_types) = self.grant_types {
+            client.grant_types = grant_types;
+        }
+        if let Some(secret) = self.secret {
+            client.secret = secret;
+        }
+        if let Some(mfa_required) = self.mfa_required {
+            client.mfa_required = mfa_required;
+        }
+        if let Some(authenticators) = self.authenticators {
+            client.authenticators = authenticators;
+        }
+        if let Some(post_logout_redirect_uris) = self.post_logout_redirect_uris {
+            client.post_logout_redirect_uris = post_logout_redirect_uris;
+        }
+        if let Some(uri) = self.frontchannel_logout_uri {
+            client.frontchannel_logout_uri = Some(uri).filter(|uri| !uri.is_empty());
+        }
+        if let Some(uri) = self.backchannel_logout_uri {
+            client.backchannel_logout_uri = Some(uri).filter(|uri| !uri.is_empty());
+        }
+    }
+}
+
+// Checks a client before it's saved, returns the reason it's invalid
+async fn validate_client(client: &Client) -> Result<(), &'static str> {
+    if client.name.is_empty() {
+        return Err("Missing name");
+    }
+
+    if !client
+        .redirect_uris
+        .iter()
+        .chain(&client.post_logout_redirect_uris)
+        .chain(&client.frontchannel_logout_uri)
+        .chain(&client.backchannel_logout_uri)
+        .all(|uri| uri.validate_url())
+    {
+        return Err("Invalid URI");
+    }
+
+    if client.grant_types.is_empty()
+        || !client
+            .grant_types
+            .iter()
+            .all(|grant_type| GRANT_TYPES.contains(&grant_type.as_str()))
+    {
+        return Err("Invalid grant_types");
+    }
+
+    // Only registered scopes can be requested
+    for scope in &client.allowed_scopes {
+        if get_scope(scope).await.is_none() {
+            return Err("Unregistered scope in allowed_scopes");
+        }
+    }
+    if !client
+        .default_scopes
+        .iter()
+        .all(|scope| client.allowed_scopes.contains(scope))
+    {
+        return Err("The default_scopes must be allowed_scopes");
+    }
+
+    let authenticators = GLOBAL_AUTHENTICATORS.get().unwrap();
+    if !client.authenticators.iter().all(|name| {
+        authenticators
+            .iter()
+            .any(|authenticator| authenticator.name() == name)
+    }) {
+        return Err("Unknown authenticator");
+    }
+
+    Ok(())
+}
+
+// Saves a changed client and removes the stale one from the cache
+async fn update_client(admin_id: &str, client: &Client, event: &str) -> bool {
+    if !GLOBAL_DATABASE.get().unwrap().update_client(client).await {
+        error!("Failed to update client {}", client.id);
+        return false;
+    }
+    GLOBAL_CACHE
+        .get()
+        .unwrap()
+        .delete_client(&client.id.to_string());
+
+    record_admin_event(event, admin_id, None, &client.id.to_string()).await;
+    info!("Admin {} updated client {}: {}", admin_id, client.id, event);
+
+    true
+}
+
+fn generate_secret() -> String {
+    rand::thread_rng()
+        .sample_iter(&Alphanumeric)
+        .take(48)
+        .map(char::from)
+        .collect()
+}
@@ -0,0 +1,362 @@
+use crate::admin::{authorize_admin, record_admin_event, ListParams};
+use crate::errors::bearer_error;
+use crate::flows::revoke_user_sessions;
+use crate::lockout::unlock_account;
+use crate::passwords::{
+    allows_password_reset, hash_password_blocking, send_password_reset, PasswordPolicy,
+    UNUSABLE_PASSWORD_HASH,
+};
+use crate::storage::{User, UserUpdate};
+use crate::GLOBAL_DATABASE;
+use axum::extract::{Json, Path, Query};
+use axum::http::{HeaderMap, StatusCode};
+use axum::response::{IntoResponse, Response};
+use log::{error, info};
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use validator::ValidateEmail;
+
+// Request body for creating a user
+#[derive(Deserialize)]
+pub struct CreateUserRequest {
+    email: String,
+    password: Option<String>, // Without one, the user has to reset it first
+    #[serde(default)]
+    groups: Vec<String>,
+    #[serde(default)]
+    roles: Vec<String>,
+    #[serde(default)]
+    entitlements: Vec<String>,
+    #[serde(default)]
+    mfa_required: bool,
+    name: Option<String>,
+    phone_number: Option<String>,
+}
+
+// Request body for updating a user, omitted fields are left unchanged
+#[derive(Deserialize)]
+pub struct UpdateUserRequest {
+    groups: Option<Vec<String>>,
+    roles: Option<Vec<String>>,
+    entitlements: Option<Vec<String>>,
+    mfa_required: Option<bool>,
+    email_verified: Option<bool>,
+    name: Option<String>, // An empty value removes it
+    phone_number: Option<String>,
+    phone_number_verified: Option<bool>,
+}
+
+// A user as returned by the admin API, without the password hash
+#[derive(Serialize)]
+struct UserResponse {
+    id: u32,
+    email: String,
+    groups: Vec<String>,
+    roles: Vec<String>,
+    entitlements: Vec<String>,
+    mfa_required: bool,
+    email_verified: bool,
+    enabled: bool,
+    has_password: bool,
+    name: Option<String>,
+    phone_number: Option<String>,
+    phone_number_verified: bool,
+}
+
+impl From<User> for UserResponse {
+    fn from(user: User) -> Self {
+        UserResponse {
+            id: user.id,
+            email: user.email,
+            groups: user.groups,
+            roles: user.roles,
+            entitlements: user.entitlements,
+            mfa_required: user.mfa_required,
+            email_verified: user.email_verified,
+            enabled: user.enabled,
+            has_password: user.password_hash != UNUSABLE_PASSWORD_HASH,
+            name: user.name,
+            phone_number: user.phone_number,
+            phone_number_verified: user.phone_number_verified,
+        }
+    }
+}
+
+/// Lists the users, a page at a time.
+pub async fn serve_admin_users(headers: HeaderMap, Query(params): Query<ListParams>) -> Response {
+    if let Err((status, error)) = authorize_admin(&headers).await {
+        return bearer_error(status, error);
+    }
+
+    let database = GLOBAL_DATABASE.get().unwrap();
+    let users = database
+        .get_users(params.search(), params.per_page(), params.offset())
+        .await
+        .into_iter()
+        .map(UserResponse::from)
+        .collect::<Vec<UserResponse>>();
+
+    Json(json!({
+        "users": users,
+        "page": params.page(),
+        "per_page": params.per_page(),
+        "total": database.count_users(params.search()).await,
+    }))
+    .into_response()
+}
+
+/// Creates a user, their email address is trusted.
+pub async fn serve_admin_user_create(
+    headers: HeaderMap,
+    Json(payload): Json<CreateUserRequest>,
+) -> Response {
+    let admin_id = match authorize_admin(&headers).await {
+        Ok(admin_id) => admin_id,
+        Err((status, error)) => return bearer_error(status, error),
+    };
+
+    let email = payload.email.trim().to_string();
+    if !email.validate_email() {
+        return (StatusCode::BAD_REQUEST, "Invalid email").into_response();
+    }
+
+    let password_hash = match payload.password {
+        Some(password) => {
+            let errors = PasswordPolicy::from_config().check(&password, &email);
+            if !errors.is_empty() {
+                return (StatusCode::BAD_REQUEST, Json(json!({ "errors": errors })))
+                    .into_response();
+            }
+
+            match hash_password_blocking(&password).await {
+                Some(hash) => hash,
+                None => {
+                    return (StatusCode::INTERNAL_SERVER_ERROR, "Failed to hash password")
+                        .into_response()
+                }
+            }
+        }
+        None => UNUSABLE_PASSWORD_HASH.to_string(),
+    };
+
+    let mut user = User {
+        id: 0,
+        email,
+        password_hash,
+        groups: payload.groups,
+        roles: payload.roles,
+        entitlements: payload.entitlements,
+        mfa_required: payload.mfa_required,
+        email_verified: true,
+        enabled: true,
+        name: payload.name.filter(|name| !name.trim().is_empty()),
+        phone_number: payload
+            .phone_number
+            .filter(|number| !number.trim().is_empty()),
+        phone_number_verified: false,
+    };
+
+    // Created with all its attributes at once, a user is never visible half set up
+    user.id = match GLOBAL_DATABASE.get().unwrap().insert_user(&user).await {
+        Some(user_id) => user_id,
+        None => return (StatusCode::CONFLICT, "Email already taken").into_response(),
+    };
+
+    record_admin_event("user_created", &admin_id, Some(user.id), &user.email).await;
+    info!("Admin {} created user {}", admin_id, user.id);
+
+    (StatusCode::CREATED, Json(UserResponse::from(user))).into_response()
+}
+
+pub async fn serve_admin_user(headers: HeaderMap, Path(user_id): Path<u32>) -> Response {
+    if let Err((status, error)) = authorize_admin(&headers).await {
+        return bearer_error(status, error);
+    }
+
+    match GLOBAL_DATABASE
+        .get()
+        .unwrap()
+        .get_user_by_id(&user_id)
+        .await
+    {
+        Some(user) => Json(UserResponse::from(user)).into_response(),
+        None => (StatusCode::NOT_FOUND, "Unknown user").into_response(),
+    }
+}
+
+/// Updates the roles and other attributes of a user that are in the request. Tokens issued
+/// from then on, also for refresh tokens, have the new attributes and only the scopes the new
+/// roles permit. Access tokens issued before keep theirs until they expire.
+pub async fn serve_admin_user_update(
+    headers: HeaderMap,
+    Path(user_id): Path<u32>,
+    Json(payload): Json<UpdateUserRequest>,
+) -> Response {
+    let admin_id = match authorize_admin(&headers).await {
+        Ok(admin_id) => admin_id,
+        Err((status, error)) => return bearer_error(status, error),
+    };
+
+    let database = GLOBAL_DATABASE.get().unwrap();
+    if database.get_user_by_id(&user_id).await.is_none() {
+        return (StatusCode::NOT_FOUND, "Unknown user").into_response();
+    }
+
+    // Only the attributes in the request are written, concurrent changes of others are kept
+    let update = UserUpdate {
+        groups: payload.groups,
+        roles: payload.roles,
+        entitlements: payload.entitlements,
+        mfa_required: payload.mfa_required,
+        email_verified: payload.email_verified,
+        name: payload.name,
+        phone_number: payload.phone_number,
+        phone_number_verified: payload.phone_number_verified,
+    };
+    if !database.update_user(&user_id, &update).await {
+        return (StatusCode::INTERNAL_SERVER_ERROR, "Failed to update user").into_response();
+    }
+
+    let user = match database.get_user_by_id(&user_id).await {
+        Some(user) => user,
+        None => return (StatusCode::NOT_FOUND, "Unknown user").into_response(),
+    };
+
+    record_admin_event("user_updated", &admin_id, Some(user.id), &user.email).await;
+    info!("Admin {} updated user {}", admin_id, user.id);
+
+    Json(UserResponse::from(user)).into_response()
+}
+
+/// Enables a user again.
+pub async fn serve_admin_user_enable(headers: HeaderMap, Path(user_id): Path<u32>) -> Response {
+    set_user_enabled(&headers, user_id, true).await
+}
+
+/// Disables a user, their sessions are ended and their refresh tokens revoked right away.
+pub async fn serve_admin_user_disable(headers: HeaderMap, Path(user_id): Path<u32>) -> Response {
+    set_user_enabled(&headers, user_id, false).await
+}
+
+async fn set_user_enabled(headers: &HeaderMap, user_id: u32, enabled: bool) -> Response {
+    let admin_id = match authorize_admin(headers).await {
+        Ok(admin_id) => admin_id,
+        Err((status, error)) => return bearer_error(status, error),
+    };
+
+    let database = GLOBAL_DATABASE.get().unwrap();
+    let mut user = match database.get_user_by_id(&user_id).await {
+        Some(user) => user,
+        None => return (StatusCode::NOT_FOUND, "Unknown user").into_response(),
+    };
+
+    if !database.set_user_enabled(&user.id, enabled).await {
+        return (StatusCode::INTERNAL_SERVER_ERROR, "Failed to update user").into_response();
+    }
+    user.enabled = enabled;
+
+    let event = if enabled {
+        "user_enabled"
+    } else {
+        revoke_user_sessions(&user.id.to_string());
+        "user_disabled"
+    };
+    record_admin_event(event, &admin_id, Some(user.id), &user.email).await;
+    info!("Admin {} updated user {}: {}", admin_id, user.id, event);
+
+    Json(UserResponse::from(user)).into_response()
+}
+
+/// Lifts the lockout after too many failed logins.
+pub async fn serve_admin_user_unlock(headers: HeaderMap, Path(user_id): Path<u32>) -> Response {
+    let admin_id = match authorize_admin(&headers).await {
+        Ok(admin_id) => admin_id,
+        Err((status, error)) => return bearer_error(status, error),
+    };
+
+    let user = match GLOBAL_DATABASE
+        .get()
+        .unwrap()
+        .get_user_by_id(&user_id)
+        .await
+    {
+        Some(user) => user,
+        None => return (StatusCode::NOT_FOUND, "Unknown user").into_response(),
+    };
+
+    // The unlock itself is recorded in the audit log
+    let was_locked = unlock_account(&user.email).await;
+    info!("Admin {} unlocked user {}", admin_id, user.id);
+
+    Json(json!({ "was_locked": was_locked })).into_response()
+}
+
+/// Emails the user a link for choosing a new password.
+pub async fn serve_admin_user_reset(headers: HeaderMap, Path(user_id): Path<u32>) -> Response {
+    let admin_id = match authorize_admin(&headers).await {
+        Ok(admin_id) => admin_id,
+        Err((status, error)) => return bearer_error(status, error),
+    };
+
+    let user = match GLOBAL_DATABASE
+        .get()
+        .unwrap()
+        .get_user_by_id(&user_id)
+        .await
+    {
+        Some(user) => user,
+        None => return (StatusCode::NOT_FOUND, "Unknown user").into_response(),
+    };
+
+    if !allows_password_reset(&user).await {
+        return (
+            StatusCode::CONFLICT,
+            "The password of the user is managed by their directory or identity provider",
+        )
+            .into_response();
+    }
+
+    if !send_password_reset(&user).await {
+        error!(
+            "Failed to send the password reset email to user {}",
+            user.id
+        );
+        return (
+            StatusCode::INTERNAL_SERVER_ERROR,
+            "Failed to send the password reset email",
+        )
+            .into_response();
+    }
+
+    record_admin_event("password_reset_sent", &admin_id, Some(user.id), &user.email).await;
+    info!(
+        "Admin {} sent a password reset to user {}",
+        admin_id, user.id
+    );
+
+    StatusCode::ACCEPTED.into_response()
+}
+
+/// Deletes a user after ending their sessions and revoking their refresh tokens.
+pub async fn serve_admin_user_delete(headers: HeaderMap, Path(user_id): Path<u32>) -> Response {
+    let admin_id = match authorize_admin(&headers).await {
+        Ok(admin_id) => admin_id,
+        Err((status, error)) => return bearer_error(status, error),
+    };
+
+    let database = GLOBAL_DATABASE.get().unwrap();
+    let user = match database.get_user_by_id(&user_id).await {
+        Some(user) => user,
+        None => return (StatusCode::NOT_FOUND, "Unknown user").into_response(),
+    };
+
+    revoke_user_sessions(&user.id.to_string());
+    if !database.delete_user(&user.id).await {
+        return (StatusCode::INTERNAL_SERVER_ERROR, "Failed to delete user").into_response();
+    }
+
+    record_admin_event("user_deleted", &admin_id, Some(user.id), &user.email).await;
+    info!("Admin {} deleted user {}", admin_id, user.id);
+
+    StatusCode::NO_CONTENT.into_response()
+}
@@ -0,0 +1,118 @@
+use crate::pages::{get_applications_html, get_error_html};
+use crate::storage::{get_session_user, User};
+use crate::{GLOBAL_CACHE, GLOBAL_DATABASE};
+use axum::extract::{Json, Path};
+use axum::http::{HeaderMap, StatusCode};
+use axum::response::{IntoResponse, Response};
+use log::info;
+use serde::Serialize;
+
+/// A client the user granted scopes to. Times are Unix timestamps.
+#[derive(Serialize)]
+pub struct AuthorizedApplication {
+    pub client_id: u32,
+    pub client_name: String,
+    pub scopes: Vec<String>,
+    pub granted_at: i64,
+    pub last_used_at: Option<i64>,
+    pub refresh_tokens: Vec<ActiveRefreshToken>,
+}
+
+/// A refresh token of the client that can still be used, the token itself is never shown.
+#[derive(Serialize)]
+pub struct ActiveRefreshToken {
+    pub scope: String,
+    pub auth_time: u64,
+    pub expires_in: i64,
+}
+
+/// Shows the clients the signed-in user authorized and lets them revoke their grants.
+pub async fn serve_applications(headers: HeaderMap) -> Response {
+    let user = match get_session_user(&headers).await {
+        Some(user) => user,
+        None => {
+            return get_error_html("Sign in to manage your authorized applications", "401")
+                .into_response()
+        }
+    };
+
+    get_applications_html(&user.email, &get_authorized_applications(&user).await).into_response()
+}
+
+/// Lists the clients the signed-in user authorized.
+pub async fn serve_applications_list(headers: HeaderMap) -> Response {
+    let user = match get_session_user(&headers).await {
+        Some(user) => user,
+        None => return (StatusCode::UNAUTHORIZED, "No active session").into_response(),
+    };
+
+    Json(get_authorized_applications(&user).await).into_response()
+}
+
+/// Revokes the grant of a client: the consent is deleted and its refresh tokens are revoked,
+/// so the client has to ask for consent again. Issued access tokens stay valid until they
+/// expire. Cross-site requests can't use DELETE without CORS, which isn't enabled.
+pub async fn serve_application_revoke(headers: HeaderMap, Path(client_id): Path<u32>) -> Response {
+    let user = match get_session_user(&headers).await {
+        Some(user) => user,
+        None => return (StatusCode::UNAUTHORIZED, "No active session").into_response(),
+    };
+
+    let database = GLOBAL_DATABASE.get().unwrap();
+    if !database.delete_consent(&user.id, &client_id).await {
+        return (StatusCode::NOT_FOUND, "Unknown application").into_response();
+    }
+
+    GLOBAL_CACHE
+        .get()
+        .unwrap()
+        .revoke_client_refresh_tokens(&user.id.to_string(), &client_id.to_string());
+
+    database
+        .record_audit_event(
+            "grant_revoked",
+            Some(user.id),
+            &user.email,
+            None,
+            Some(&format!("client_id={}", client_id)),
+        )
+        .await;
+    info!("User {} revoked the grant of client {}", user.id, client_id);
+
+    StatusCode::NO_CONTENT.into_response()
+}
+
+// Gets the consented clients of the user with their active refresh tokens
+async fn get_authorized_applications(user: &User) -> Vec<AuthorizedApplication> {
+    let refresh_tokens = GLOBAL_CACHE
+        .get()
+        .unwrap()
+        .get_user_refresh_tokens(&user.id.to_string());
+
+    GLOBAL_DATABASE
+        .get()
+        .unwrap()
+        .get_consents(&user.id)
+        .await
+        .into_iter()
+        .map(|consent| {
+            let client_id = consent.client_id.to_string();
+            AuthorizedApplication {
+                client_id: consent.client_id,
+                client_name: consent.client_name,
+                scopes: consent.scopes,
+                granted_at: consent.created_at,
+                last_used_at: consent.last_used_at,
+                refresh_tokens: refresh_tokens
+                    .iter()
+                    .filter(|(data, _)| data.client_id == client_id)
+                    .map(|(data, expires_in)| ActiveRefreshToken {
+                        scope: data.scope.clone(),
+                        auth_time: data.auth_time,
+                        expires_in: *expires_in,
+                    })
+                    .collect(),
+            }
+        })
+        .collect()
+}
@@ -1,15 +1,27 @@
-use crate::errors::{invalid_client_error, missing_scope_error, unsupported_response_type_error};
-use crate::flows::authorization_code_flow;
+use crate::cookies::{get_cookie, SESSION_COOKIE};
+use crate::errors::{
+    invalid_claims_error, invalid_max_age_error, invalid_prompt_error, invalid_target_error,
+    unsupported_response_mode_error, unsupported_response_type_error,
+};
+use crate::flows::{authorization_code_flow, registered_client};
 use crate::pages::get_error_html;
+use crate::response_modes::ResponseMode;
 use crate::storage::AuthorizeRequestData;
+use crate::GLOBAL_CACHE;
 use axum::extract::Query;
+use axum::http::HeaderMap;
 use axum::response::{IntoResponse, Response};
-use regex::Regex;
 use std::collections::HashMap;
-use validator::{ValidateRegex, ValidateUrl};
+use validator::ValidateUrl;
+
+// The prompt values of OpenID Connect Core section 3.1.2.1
+const PROMPT_VALUES: [&str; 4] = ["none", "login", "consent", "select_account"];
 
 #[axum::debug_handler]
-pub async fn serve_authorization(Query(params): Query<HashMap<String, String>>) -> Response {
+pub async fn serve_authorization(
+    headers: HeaderMap,
+    Query(params): Query<HashMap<String, String>>,
+) -> Response {
     // Create RequestData struct from query parameters
     let request_data = match AuthorizeRequestData::new(&params) {
         Some(data) => data,
@@ -21,24 +33,79 @@ pub async fn serve_authorization(Query(params): Query<HashMap<String, String>>)
         return get_error_html("Invalid redirect URI", "400").into_response();
     }
 
-    // Validate client ID
-    if !request_data
-        .client_id
-        .validate_regex(Regex::new("^\\d+$").unwrap())
-    {
-        return invalid_client_error(&request_data.redirect_uri, request_data.state.as_ref());
+    // Errors are only redirected to a redirect URI registered for the client
+    let client_data = match registered_client(&request_data).await {
+        Ok(client_data) => client_data,
+        Err(message) => return get_error_html(message, "400").into_response(),
+    };
+
+    // Validate the response mode
+    if let Some(response_mode) = &request_data.response_mode {
+        if ResponseMode::parse(response_mode).is_none() {
+            return unsupported_response_mode_error(
+                &request_data.redirect_uri,
+                request_data.response_mode(),
+                request_data.state.as_ref(),
+            );
+        }
+    }
+
+    // Validate the prompt, none can't be combined with other values
+    if let Some(prompt) = &request_data.prompt {
+        let prompts = prompt.split_whitespace().collect::<Vec<&str>>();
+        if !prompts.iter().all(|p| PROMPT_VALUES.contains(p))
+            || (prompts.contains(&"none") && prompts.len() > 1)
+        {
+            return invalid_prompt_error(
+                &request_data.redirect_uri,
+                request_data.response_mode(),
+                request_data.state.as_ref(),
+            );
+        }
     }
 
-    // Validate scope
-    if request_data.scope.is_empty() {
-        return missing_scope_error(&request_data.redirect_uri, request_data.state.as_ref());
+    // Validate the maximum authentication age
+    if request_data.max_age.is_some() && request_data.max_age().is_none() {
+        return invalid_max_age_error(
+            &request_data.redirect_uri,
+            request_data.response_mode(),
+            request_data.state.as_ref(),
+        );
+    }
+
+    // Validate the claims request
+    if request_data.claims.is_some() && request_data.claims_request().is_none() {
+        return invalid_claims_error(
+            &request_data.redirect_uri,
+            request_data.response_mode(),
+            request_data.state.as_ref(),
+        );
+    }
+
+    // Validate the resource indicator, it must be an absolute URI without a fragment
+    if let Some(resource) = &request_data.resource {
+        if !resource.validate_url() || resource.contains('#') {
+            return invalid_target_error(
+                &request_data.redirect_uri,
+                request_data.response_mode(),
+                request_data.state.as_ref(),
+            );
+        }
     }
 
     // Handle the flow based on the response_type
     match request_data.response_type.as_deref() {
-        Some("code") => authorization_code_flow(&request_data).await,
-        _ => {
-            unsupported_response_type_error(&request_data.redirect_uri, request_data.state.as_ref())
+        Some("code") => {
+            // Resume the browser SSO session, if any
+            let session = get_cookie(&headers, SESSION_COOKIE)
+                .and_then(|session_key| GLOBAL_CACHE.get().unwrap().get_session(&session_key));
+
+            authorization_code_flow(&headers, &request_data, client_data, session).await
         }
+        _ => unsupported_response_type_error(
+            &request_data.redirect_uri,
+            request_data.response_mode(),
+            request_data.state.as_ref(),
+        ),
     }
 }
@@ -0,0 +1,104 @@
+use crate::binding::require_csrf_token;
+use crate::claims::unscoped_claims;
+use crate::errors::failed_authorization_error;
+use crate::flows::{issue_authorization_code, permitted_scopes};
+use crate::pages::get_login_error_html;
+use crate::storage::ConsentRequestData;
+use crate::{GLOBAL_CACHE, GLOBAL_DATABASE};
+use axum::http::HeaderMap;
+use axum::response::{IntoResponse, Response};
+use axum::Form;
+use log::error;
+
+#[axum::debug_handler]
+pub async fn serve_consent(
+    headers: HeaderMap,
+    Form(params): Form<Vec<(String, String)>>,
+) -> Response {
+    let form_data = match ConsentRequestData::new(&params) {
+        Some(data) => data,
+        None => return get_login_error_html().into_response(),
+    };
+
+    let cache = GLOBAL_CACHE.get().unwrap();
+
+    let request_data = match cache.get_request(&form_data.request_id) {
+        Some(data) => data,
+        None => return get_login_error_html().into_response(),
+    };
+
+    if let Err(response) =
+        require_csrf_token(&headers, &form_data.request_id, &form_data.csrf_token)
+    {
+        return response;
+    }
+
+    // The user must have logged in for this request
+    let authentication = match cache.get_request_authentication(&form_data.request_id) {
+        Some(authentication) => authentication,
+        None => return get_login_error_html().into_response(),
+    };
+
+    // The request is finished either way, so it can't be replayed
+    cache.delete_request(&form_data.request_id);
+
+    // Only scopes that were requested and the user is permitted can be approved
+    let scope = permitted_scopes(&request_data.scope, &authentication.user_id).await;
+    let approved_scopes = scope
+        .split_whitespace()
+        .filter(|s| form_data.scopes.iter().any(|approved| approved == s))
+        .map(String::from)
+        .collect::<Vec<String>>();
+
+    // Likewise only the claims the consent page asked for
+    let approved_claims = request_data
+        .claims_request()
+        .map(|request| unscoped_claims(&scope, &request))
+        .unwrap_or_default()
+        .into_iter()
+        .filter(|claim| form_data.claims.contains(claim))
+        .collect::<Vec<String>>();
+
+    if form_data.action != "approve" || approved_scopes.is_empty() {
+        return failed_authorization_error(
+            &request_data.redirect_uri,
+            request_data.response_mode(),
+            request_data.state.as_ref(),
+        );
+    }
+
+    let (user_id, client_id) = match (
+        authentication.user_id.parse::<u32>(),
+        request_data.client_id.parse::<u32>(),
+    ) {
+        (Ok(user_id), Ok(client_id)) => (user_id, client_id),
+        _ => {
+            return failed_authorization_error(
+                &request_data.redirect_uri,
+                request_data.response_mode(),
+                request_data.state.as_ref(),
+            )
+        }
+    };
+
+    // Remember the grant so later requests for the same or fewer scopes and claims skip the
+    // prompt
+    if !GLOBAL_DATABASE
+        .get()
+        .unwrap()
+        .save_consent(&user_id, &client_id, &approved_scopes, &approved_claims)
+        .await
+    {
+        error!(
+            "Failed to save consent for user {} and client {}",
+            authentication.user_id, request_data.client_id
+        );
+    }
+
+    issue_authorization_code(
+        &request_data,
+        &authentication,
+        &approved_scopes.join(" "),
+        &approved_claims,
+    )
+}
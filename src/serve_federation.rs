@@ -0,0 +1,374 @@
+use crate::binding::{request_csrf_token, require_csrf_token, verify_request_binding};
+use crate::errors::failed_authorization_error;
+use crate::federation::{
+    authorization_url, exchange_code, generate_secret, resolve_endpoints, UpstreamIdentity,
+};
+use crate::flows::continue_login;
+use crate::pages::{get_error_html, get_federation_link_html, get_login_error_html};
+use crate::passwords::UNUSABLE_PASSWORD_HASH;
+use crate::storage::{
+    get_client_data, get_session_user, Authentication, FederationState, IdentityProvider,
+    PendingIdentityLink, User,
+};
+use crate::{GLOBAL_CACHE, GLOBAL_DATABASE};
+use axum::extract::{Path, Query};
+use axum::http::HeaderMap;
+use axum::response::{IntoResponse, Redirect, Response};
+use axum::Form;
+use log::{info, warn};
+use std::collections::HashMap;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// Sends the user to an upstream identity provider to sign in for an authorization request.
+pub async fn serve_federated_login(
+    Path(provider_id): Path<String>,
+    headers: HeaderMap,
+    Form(params): Form<HashMap<String, String>>,
+) -> Response {
+    let request_id = params.get("request_id").cloned().unwrap_or_default();
+    let csrf_token = params.get("csrf_token").cloned().unwrap_or_default();
+
+    let cache = GLOBAL_CACHE.get().unwrap();
+    let request_data = match cache.get_request(&request_id) {
+        Some(data) => data,
+        None => return get_login_error_html().into_response(),
+    };
+
+    if let Err(response) = require_csrf_token(&headers, &request_id, &csrf_token) {
+        return response;
+    }
+
+    let failed = || {
+        failed_authorization_error(
+            &request_data.redirect_uri,
+            request_data.response_mode(),
+            request_data.state.as_ref(),
+        )
+    };
+
+    let provider = match GLOBAL_DATABASE
+        .get()
+        .unwrap()
+        .get_identity_provider(&provider_id)
+        .await
+    {
+        Some(provider) => provider,
+        None => return get_error_html("Unknown identity provider", "404").into_response(),
+    };
+    let endpoints = match resolve_endpoints(&provider).await {
+        Some(endpoints) => endpoints,
+        None => return failed(),
+    };
+
+    let state = generate_secret();
+    let federation_state = FederationState {
+        provider_id: provider.id.clone(),
+        request_id,
+        nonce: generate_secret(),
+        code_verifier: generate_secret(),
+    };
+    cache.set_federation_state(&state, &federation_state);
+
+    Redirect::to(&authorization_url(
+        &provider,
+        &endpoints,
+        &state,
+        &federation_state.nonce,
+        &federation_state.code_verifier,
+    ))
+    .into_response()
+}
+
+/// Handles the redirect back from an upstream identity provider and signs the user in,
+/// linking or provisioning their account.
+pub async fn serve_federated_callback(
+    headers: HeaderMap,
+    Query(params): Query<HashMap<String, String>>,
+) -> Response {
+    let cache = GLOBAL_CACHE.get().unwrap();
+    let database = GLOBAL_DATABASE.get().unwrap();
+
+    let federation_state = match params
+        .get("state")
+        .and_then(|state| cache.take_federation_state(state))
+    {
+        Some(state) => state,
+        None => return get_login_error_html().into_response(),
+    };
+    let request_id = federation_state.request_id.as_str();
+
+    let request_data = match cache.get_request(request_id) {
+        Some(data) => data,
+        None => return get_login_error_html().into_response(),
+    };
+
+    // Only the browser that started the authorization request can continue it
+    if !verify_request_binding(&headers, request_id) {
+        return get_login_error_html().into_response();
+    }
+
+    let failed = || {
+        failed_authorization_error(
+            &request_data.redirect_uri,
+            request_data.response_mode(),
+            request_data.state.as_ref(),
+        )
+    };
+
+    if let Some(error) = params.get("error") {
+        warn!(
+            "Identity provider {} returned error {}",
+            federation_state.provider_id, error
+        );
+        return failed();
+    }
+
+    let provider = match database
+        .get_identity_provider(&federation_state.provider_id)
+        .await
+    {
+        Some(provider) => provider,
+        None => return failed(),
+    };
+    let endpoints = match resolve_endpoints(&provider).await {
+        Some(endpoints) => endpoints,
+        None => return failed(),
+    };
+    let identity = match exchange_code(
+        &provider,
+        &endpoints,
+        params.get("code").map(String::as_str).unwrap_or_default(),
+        &federation_state.code_verifier,
+        &federation_state.nonce,
+    )
+    .await
+    {
+        Some(identity) => identity,
+        None => return failed(),
+    };
+
+    let user = match resolve_user(&provider, &identity, &headers).await {
+        Ok(ResolvedIdentity::User(user)) => user,
+        Ok(ResolvedIdentity::ConfirmLink(user)) => {
+            cache.set_request_pending_link(
+                request_id,
+                &PendingIdentityLink {
+                    provider_id: provider.id.clone(),
+                    subject: identity.subject.clone(),
+                    email: identity.email.clone(),
+                    user_id: user.id,
+                },
+            );
+            return get_federation_link_html(
+                &provider.name,
+                identity.email.as_deref().unwrap_or_default(),
+                &user.email,
+                request_id,
+                &request_csrf_token(request_id),
+            )
+            .into_response();
+        }
+        Err(message) => return get_error_html(&message, "409").into_response(),
+    };
+
+    let client_data = match get_client_data(&request_data.client_id).await {
+        Some(data) => data,
+        None => return get_login_error_html().into_response(),
+    };
+
+    continue_login(
+        request_id,
+        &request_data,
+        &client_data,
+        &user,
+        &federated_authentication(&user),
+    )
+    .await
+}
+
+/// Links the upstream identity of a federated login to the signed-in user once they confirmed
+/// it, then continues the login.
+#[axum::debug_handler]
+pub async fn serve_federated_link(
+    headers: HeaderMap,
+    Form(params): Form<HashMap<String, String>>,
+) -> Response {
+    let request_id = params.get("request_id").cloned().unwrap_or_default();
+    let csrf_token = params.get("csrf_token").cloned().unwrap_or_default();
+
+    let cache = GLOBAL_CACHE.get().unwrap();
+    let database = GLOBAL_DATABASE.get().unwrap();
+
+    let request_data = match cache.get_request(&request_id) {
+        Some(data) => data,
+        None => return get_login_error_html().into_response(),
+    };
+
+    if let Err(response) = require_csrf_token(&headers, &request_id, &csrf_token) {
+        return response;
+    }
+
+    let link = match cache.take_request_pending_link(&request_id) {
+        Some(link) => link,
+        None => return get_login_error_html().into_response(),
+    };
+
+    if params.get("action").map(String::as_str) != Some("link") {
+        cache.delete_request(&request_id);
+        return failed_authorization_error(
+            &request_data.redirect_uri,
+            request_data.response_mode(),
+            request_data.state.as_ref(),
+        );
+    }
+
+    // The identity is only linked to the user who is still signed in
+    let user = match get_session_user(&headers).await {
+        Some(user) if user.id == link.user_id => user,
+        _ => return get_login_error_html().into_response(),
+    };
+
+    if !database
+        .link_user_identity(
+            &link.provider_id,
+            &link.subject,
+            &user.id,
+            link.email.as_deref(),
+        )
+        .await
+    {
+        return get_error_html("Failed to link your account", "500").into_response();
+    }
+    record_identity_link(&link.provider_id, &link.subject, &user).await;
+
+    let client_data = match get_client_data(&request_data.client_id).await {
+        Some(data) => data,
+        None => return get_login_error_html().into_response(),
+    };
+
+    continue_login(
+        &request_id,
+        &request_data,
+        &client_data,
+        &user,
+        &federated_authentication(&user),
+    )
+    .await
+}
+
+// How an upstream identity is signed in
+enum ResolvedIdentity {
+    User(User),
+    // Not linked yet, the signed-in user has to confirm linking it to their account
+    ConfirmLink(User),
+}
+
+// Finds the user of an upstream identity. Unknown identities are offered to the signed-in user
+// for linking, or provisioned just in time. They are never linked to an existing account
+// without its owner signing in first, whatever the provider asserts about the email address.
+// Errors are messages for the user.
+async fn resolve_user(
+    provider: &IdentityProvider,
+    identity: &UpstreamIdentity,
+    headers: &HeaderMap,
+) -> Result<ResolvedIdentity, String> {
+    let database = GLOBAL_DATABASE.get().unwrap();
+    let account_error = |message: &str| Err(message.to_string());
+
+    if let Some(user_id) = database
+        .get_user_identity(&provider.id, &identity.subject)
+        .await
+    {
+        return match database.get_user_by_id(&user_id).await {
+            Some(user) => Ok(ResolvedIdentity::User(user)),
+            None => account_error("The linked account no longer exists"),
+        };
+    }
+
+    if let Some(user) = get_session_user(headers).await {
+        return Ok(ResolvedIdentity::ConfirmLink(user));
+    }
+
+    let email = match &identity.email {
+        Some(email) => email,
+        None => {
+            return account_error(&format!(
+                "{} did not share an email address for your account",
+                provider.name
+            ))
+        }
+    };
+
+    if database.get_user(email).await.is_some() {
+        return account_error(&format!(
+            "An account with this email address already exists. Sign in with your password \
+             first, then sign in with {} again to link it.",
+            provider.name
+        ));
+    }
+
+    let email_verified = identity.email_verified || provider.trust_email;
+    let user = match database
+        .create_user(email, UNUSABLE_PASSWORD_HASH, email_verified)
+        .await
+    {
+        Some(user_id) => {
+            info!(
+                "Provisioned user {} from identity provider {}",
+                user_id, provider.id
+            );
+            database.get_user_by_id(&user_id).await
+        }
+        None => None,
+    };
+
+    let user = match user {
+        Some(user) => user,
+        None => return account_error("Failed to set up your account"),
+    };
+
+    if !database
+        .link_user_identity(
+            &provider.id,
+            &identity.subject,
+            &user.id,
+            identity.email.as_deref(),
+        )
+        .await
+    {
+        return account_error("Failed to link your account");
+    }
+    record_identity_link(&provider.id, &identity.subject, &user).await;
+
+    Ok(ResolvedIdentity::User(user))
+}
+
+async fn record_identity_link(provider_id: &str, subject: &str, user: &User) {
+    info!(
+        "Linked identity provider {} to user {}",
+        provider_id, user.id
+    );
+    GLOBAL_DATABASE
+        .get()
+        .unwrap()
+        .record_audit_event(
+            "identity_linked",
+            Some(user.id),
+            &user.email,
+            None,
+            Some(&format!("{} subject {}", provider_id, subject)),
+        )
+        .await;
+}
+
+fn federated_authentication(user: &User) -> Authentication {
+    Authentication {
+        user_id: user.id.to_string(),
+        auth_time: SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .expect("Time went backwards")
+            .as_secs(),
+        amr: vec!["fed".to_string()],
+        session_id: None,
+    }
+}
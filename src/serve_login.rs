@@ -1,15 +1,28 @@
-use crate::errors::failed_authorization_error;
-use crate::pages::get_login_error_html;
-use crate::storage::LoginRequestData;
-use crate::{GLOBAL_CACHE, GLOBAL_DATABASE};
-use axum::response::{IntoResponse, Redirect, Response};
+use crate::authenticators::authenticate;
+use crate::binding::require_csrf_token;
+use crate::errors::{failed_authorization_error, login_throttled_error};
+use crate::flows::continue_login;
+use crate::lockout::{
+    check_login_attempt, client_ip, record_login_failure, record_login_success, LoginThrottle,
+};
+use crate::pages::{get_login_error_html, get_notice_html};
+use crate::storage::{get_client_data, Authentication, LoginRequestData};
+use crate::GLOBAL_CACHE;
+use axum::extract::ConnectInfo;
+use axum::http::HeaderMap;
+use axum::response::{IntoResponse, Response};
 use axum::Form;
-use rand::distributions::Alphanumeric;
-use rand::Rng;
+use log::warn;
 use std::collections::HashMap;
+use std::net::SocketAddr;
+use std::time::{SystemTime, UNIX_EPOCH};
 
 #[axum::debug_handler]
-pub async fn serve_login(Form(params): Form<HashMap<String, String>>) -> Response {
+pub async fn serve_login(
+    ConnectInfo(address): ConnectInfo<SocketAddr>,
+    headers: HeaderMap,
+    Form(params): Form<HashMap<String, String>>,
+) -> Response {
     let form_data = match LoginRequestData::new(&params) {
         Some(data) => data,
         None => return get_login_error_html().into_response(),
@@ -24,44 +37,92 @@ pub async fn serve_login(Form(params): Form<HashMap<String, String>>) -> Respons
         None => return get_login_error_html().into_response(),
     };
 
-    let user = GLOBAL_DATABASE
-        .get()
-        .unwrap()
-        .get_user(&form_data.email, &form_data.password)
-        .await;
+    if let Err(response) =
+        require_csrf_token(&headers, &form_data.request_id, &form_data.csrf_token)
+    {
+        return response;
+    }
+
+    // Refuse to check the password while the account or the client is locked out or backing off
+    let ip = client_ip(&headers, &address);
+    match check_login_attempt(&form_data.email, &ip) {
+        LoginThrottle::Allowed => {}
+        LoginThrottle::Delayed(seconds) => {
+            warn!(
+                "Login for {} from {} delayed for {} seconds",
+                form_data.email, ip, seconds
+            );
+            return login_throttled_error(
+                &request_data.redirect_uri,
+                request_data.response_mode(),
+                request_data.state.as_ref(),
+            );
+        }
+        LoginThrottle::Locked => {
+            warn!(
+                "Login for {} from {} refused by lockout",
+                form_data.email, ip
+            );
+            return login_throttled_error(
+                &request_data.redirect_uri,
+                request_data.response_mode(),
+                request_data.state.as_ref(),
+            );
+        }
+    }
+
+    // The client decides which authentication backends its users sign in with
+    let client_data = match get_client_data(&request_data.client_id).await {
+        Some(data) => data,
+        None => return get_login_error_html().into_response(),
+    };
+
+    let user = authenticate(&client_data, &form_data.email, &form_data.password).await;
     if user.is_none() {
-        failed_authorization_error(&request_data.redirect_uri, request_data.state.as_ref());
+        record_login_failure(&form_data.email, &ip).await;
+        return failed_authorization_error(
+            &request_data.redirect_uri,
+            request_data.response_mode(),
+            request_data.state.as_ref(),
+        );
     }
     let user = user.unwrap();
+    record_login_success(&form_data.email);
 
-    // User is authorized, generate a code
-    if request_data.response_type.unwrap().eq("code") {
-        // Generate a code and cache the user details etc. for access and refresh tokens
-        let code: String = rand::thread_rng()
-            .sample_iter(&Alphanumeric)
-            .take(32)
-            .map(char::from)
-            .collect();
-
-        GLOBAL_CACHE
-            .get()
-            .unwrap()
-            .set_auth_code_for_user_and_scopes(
-                &request_data.client_id.to_string(),
-                &code,
-                &user.id.to_string(),
-                &request_data.scope,
-            );
+    // Self-registered users can only sign in once their email address is verified
+    if !user.email_verified {
+        return get_notice_html(
+            "Verify your email address",
+            "Follow the link we sent to your email address to activate your account, then sign in again.",
+        )
+        .into_response();
+    }
 
-        // Return the auth code and state
-        let redirect_uri = format!(
-            "{}?code={}&state={}",
-            request_data.redirect_uri,
-            code,
-            request_data.state.unwrap()
+    if request_data.response_type.as_deref() != Some("code") {
+        return failed_authorization_error(
+            &request_data.redirect_uri,
+            request_data.response_mode(),
+            request_data.state.as_ref(),
         );
-        return Redirect::permanent(redirect_uri.as_str()).into_response();
     }
 
-    failed_authorization_error(&request_data.redirect_uri, request_data.state.as_ref())
+    // User is authenticated
+    let authentication = Authentication {
+        user_id: user.id.to_string(),
+        auth_time: SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .expect("Time went backwards")
+            .as_secs(),
+        amr: vec!["pwd".to_string()],
+        session_id: None,
+    };
+
+    continue_login(
+        &form_data.request_id,
+        &request_data,
+        &client_data,
+        &user,
+        &authentication,
+    )
+    .await
 }
@@ -0,0 +1,177 @@
+use crate::binding::{logout_csrf_token, verify_logout_csrf_token};
+use crate::cookies::{build_cookie, get_cookie, SESSION_COOKIE};
+use crate::id_tokens::verify_id_token;
+use crate::logout::{frontchannel_logout_url, send_backchannel_logout};
+use crate::pages::{get_error_html, get_logout_confirm_html, get_logout_html};
+use crate::storage::get_client_data;
+use crate::GLOBAL_CACHE;
+use axum::extract::Query;
+use axum::http::header::SET_COOKIE;
+use axum::http::{HeaderMap, HeaderValue};
+use axum::response::{IntoResponse, Redirect, Response};
+use axum::Form;
+use log::info;
+use std::collections::HashMap;
+
+/// RP-initiated logout with the parameters in the query.
+pub async fn serve_logout(
+    headers: HeaderMap,
+    Query(params): Query<HashMap<String, String>>,
+) -> Response {
+    logout(&headers, &params).await
+}
+
+/// RP-initiated logout with the parameters posted as a form.
+pub async fn serve_logout_form(
+    headers: HeaderMap,
+    Form(params): Form<HashMap<String, String>>,
+) -> Response {
+    logout(&headers, &params).await
+}
+
+// Ends the browser SSO session, revokes the refresh tokens issued in it and logs the user out
+// of the clients signed in through it, then returns to the client if it asks to
+// (OpenID Connect RP-Initiated Logout 1.0)
+async fn logout(headers: &HeaderMap, params: &HashMap<String, String>) -> Response {
+    let cache = GLOBAL_CACHE.get().unwrap();
+
+    // An ID token identifies the client and the user logging out
+    let id_token_claims = match params.get("id_token_hint") {
+        Some(id_token_hint) => match verify_id_token(id_token_hint).await {
+            Some(claims) => Some(claims),
+            None => return get_error_html("Invalid id_token_hint", "400").into_response(),
+        },
+        None => None,
+    };
+
+    let client_id = match (
+        id_token_claims.as_ref().map(|claims| &claims.aud),
+        params.get("client_id"),
+    ) {
+        (Some(audience), Some(client_id)) if audience != client_id => {
+            return get_error_html("The client_id does not match the id_token_hint", "400")
+                .into_response()
+        }
+        (Some(client_id), _) | (None, Some(client_id)) => Some(client_id),
+        (None, None) => None,
+    };
+
+    let client_data = match client_id {
+        Some(client_id) if client_id.parse::<u32>().is_ok() => {
+            match get_client_data(client_id).await {
+                Some(data) => Some(data),
+                None => return get_error_html("Unknown client", "400").into_response(),
+            }
+        }
+        Some(_) => return get_error_html("Unknown client", "400").into_response(),
+        None => None,
+    };
+
+    // Only registered URIs of the client can be returned to
+    let redirect_uri = match params.get("post_logout_redirect_uri") {
+        Some(uri) => {
+            if !client_data
+                .as_ref()
+                .is_some_and(|data| data.post_logout_redirect_uris.contains(uri))
+            {
+                return get_error_html("Invalid post_logout_redirect_uri", "400").into_response();
+            }
+
+            match params.get("state") {
+                Some(state) => {
+                    let separator = if uri.contains('?') { '&' } else { '?' };
+                    Some(format!(
+                        "{}{}state={}",
+                        uri,
+                        separator,
+                        urlencoding::encode(state)
+                    ))
+                }
+                None => Some(uri.clone()),
+            }
+        }
+        None => None,
+    };
+
+    // The session isn't ended if the ID token is of another user than the one signed in
+    let session_key = get_cookie(headers, SESSION_COOKIE);
+    let session = session_key
+        .as_ref()
+        .and_then(|session_key| cache.get_session(session_key))
+        .filter(|session| {
+            id_token_claims
+                .as_ref()
+                .is_none_or(|claims| claims.sub == session.user_id)
+        });
+
+    // Without an ID token of the user, the logout could have been triggered by any site, so
+    // the user confirms it on a page posting back here (RP-Initiated Logout section 2)
+    if let (Some(session_key), Some(_)) = (&session_key, &session) {
+        let confirmed = params
+            .get("csrf_token")
+            .is_some_and(|token| verify_logout_csrf_token(session_key, token));
+        if id_token_claims.is_none() && !confirmed {
+            return get_logout_confirm_html(
+                client_data.as_ref().map(|data| data.name.as_str()),
+                client_id.map(String::as_str),
+                params.get("post_logout_redirect_uri").map(String::as_str),
+                params.get("state").map(String::as_str),
+                &logout_csrf_token(session_key),
+            )
+            .into_response();
+        }
+    }
+
+    let mut frontchannel_logout_uris = Vec::new();
+    if let (Some(session_key), Some(session)) = (&session_key, &session) {
+        let client_ids = cache.end_session(session_key, session);
+        if let Some(session_id) = &session.session_id {
+            cache.revoke_session_refresh_tokens(&session.user_id, session_id);
+        }
+        info!("User {} logged out", session.user_id);
+
+        for client_id in client_ids {
+            let client_data = match get_client_data(&client_id).await {
+                Some(data) => data,
+                None => continue,
+            };
+
+            if let (Some(logout_uri), Some(session_id)) =
+                (&client_data.frontchannel_logout_uri, &session.session_id)
+            {
+                frontchannel_logout_uris.push(frontchannel_logout_url(logout_uri, session_id));
+            }
+
+            // Clients are notified in the background, their endpoints can be slow
+            if let Some(logout_uri) = client_data.backchannel_logout_uri.clone() {
+                let user_id = session.user_id.clone();
+                let session_id = session.session_id.clone();
+                tokio::spawn(async move {
+                    send_backchannel_logout(
+                        &client_data,
+                        &logout_uri,
+                        &user_id,
+                        session_id.as_deref(),
+                    )
+                    .await;
+                });
+            }
+        }
+    }
+
+    let mut response = match redirect_uri {
+        Some(uri) if frontchannel_logout_uris.is_empty() => Redirect::to(&uri).into_response(),
+        redirect_uri => {
+            get_logout_html(&frontchannel_logout_uris, redirect_uri.as_deref()).into_response()
+        }
+    };
+
+    if session.is_some() {
+        response.headers_mut().append(
+            SET_COOKIE,
+            HeaderValue::from_str(&build_cookie(SESSION_COOKIE, "", 0)).unwrap(),
+        );
+    }
+
+    response
+}
@@ -0,0 +1,47 @@
+use crate::claims::SUPPORTED_CLAIMS;
+use crate::config::issuer_url;
+use crate::storage::GRANT_TYPES;
+use axum::extract::Json;
+use serde::Serialize;
+
+// Authorization server metadata (RFC 8414)
+#[derive(Serialize)]
+pub struct MetadataResponse {
+    issuer: String,
+    authorization_endpoint: String,
+    token_endpoint: String,
+    userinfo_endpoint: String,
+    response_types_supported: Vec<&'static str>,
+    response_modes_supported: Vec<&'static str>,
+    grant_types_supported: Vec<&'static str>,
+    authorization_response_iss_parameter_supported: bool,
+    end_session_endpoint: String,
+    claims_parameter_supported: bool,
+    claims_supported: Vec<&'static str>,
+    frontchannel_logout_supported: bool,
+    frontchannel_logout_session_supported: bool,
+    backchannel_logout_supported: bool,
+    backchannel_logout_session_supported: bool,
+}
+
+pub async fn serve_metadata() -> Json<MetadataResponse> {
+    let issuer = issuer_url();
+
+    Json(MetadataResponse {
+        authorization_endpoint: format!("{}/authorize", issuer),
+        token_endpoint: format!("{}/token", issuer),
+        userinfo_endpoint: format!("{}/userinfo", issuer),
+        response_types_supported: vec!["code"],
+        response_modes_supported: vec!["query", "fragment", "form_post"],
+        grant_types_supported: GRANT_TYPES.to_vec(),
+        authorization_response_iss_parameter_supported: true,
+        end_session_endpoint: format!("{}/logout", issuer),
+        claims_parameter_supported: true,
+        claims_supported: SUPPORTED_CLAIMS.to_vec(),
+        frontchannel_logout_supported: true,
+        frontchannel_logout_session_supported: true,
+        backchannel_logout_supported: true,
+        backchannel_logout_session_supported: true,
+        issuer,
+    })
+}
@@ -0,0 +1,391 @@
+use crate::binding::require_csrf_token;
+use crate::errors::{failed_authorization_error, login_throttled_error};
+use crate::flows::complete_authentication;
+use crate::lockout::{
+    check_mfa_attempt, client_ip, record_mfa_failure, record_mfa_success, LoginThrottle,
+};
+use crate::mfa::{
+    build_totp, generate_recovery_codes, generate_totp_secret, hash_recovery_code, verify_totp,
+};
+use crate::pages::{
+    get_account_mfa_html, get_error_html, get_login_error_html, get_mfa_enroll_html, get_mfa_html,
+    get_recovery_codes_html,
+};
+use crate::storage::{get_client_data, get_session_user, AuthorizeRequestData, MfaRequestData};
+use crate::{GLOBAL_CACHE, GLOBAL_DATABASE};
+use axum::extract::{ConnectInfo, Json};
+use axum::http::{HeaderMap, StatusCode};
+use axum::response::{IntoResponse, Response};
+use axum::Form;
+use log::{error, info, warn};
+use serde::Deserialize;
+use serde_json::json;
+use std::collections::HashMap;
+use std::net::SocketAddr;
+
+// Attempts at confirming an enrollment code, failed second factors are counted per user instead
+const MAX_ENROLLMENT_ATTEMPTS: u64 = 5;
+
+// Request body for confirming the enrollment of a signed-in user
+#[derive(Deserialize)]
+pub struct AccountEnrollmentRequest {
+    code: String,
+}
+
+/// Verifies the second factor of a login, either a TOTP code or a recovery code.
+#[axum::debug_handler]
+pub async fn serve_mfa(
+    ConnectInfo(address): ConnectInfo<SocketAddr>,
+    headers: HeaderMap,
+    Form(params): Form<HashMap<String, String>>,
+) -> Response {
+    let form_data = match MfaRequestData::new(&params) {
+        Some(data) => data,
+        None => return get_login_error_html().into_response(),
+    };
+
+    let cache = GLOBAL_CACHE.get().unwrap();
+    let database = GLOBAL_DATABASE.get().unwrap();
+
+    let request_data = match cache.get_request(&form_data.request_id) {
+        Some(data) => data,
+        None => return get_login_error_html().into_response(),
+    };
+
+    if let Err(response) =
+        require_csrf_token(&headers, &form_data.request_id, &form_data.csrf_token)
+    {
+        return response;
+    }
+
+    // The password must have been verified for this request
+    let mut authentication = match cache.get_request_pending_authentication(&form_data.request_id) {
+        Some(authentication) => authentication,
+        None => return get_login_error_html().into_response(),
+    };
+
+    let user_id = authentication.user_id.parse::<u32>().unwrap_or_default();
+    let user = match database.get_user_by_id(&user_id).await {
+        Some(user) => user,
+        None => return get_login_error_html().into_response(),
+    };
+    let secret = match database.get_totp_secret(&user_id).await {
+        Some(secret) => secret,
+        None => return get_login_error_html().into_response(),
+    };
+
+    // Refuse to check the code while the account is locked out or backing off
+    let ip = client_ip(&headers, &address);
+    match check_mfa_attempt(user.id, &user.email) {
+        LoginThrottle::Allowed => {}
+        LoginThrottle::Delayed(_) | LoginThrottle::Locked => {
+            warn!("Second factor for user {} refused by lockout", user.id);
+            cache.delete_request(&form_data.request_id);
+            return login_throttled_error(
+                &request_data.redirect_uri,
+                request_data.response_mode(),
+                request_data.state.as_ref(),
+            );
+        }
+    }
+
+    let is_valid = if verify_totp(&secret, &user.email, &form_data.code) {
+        cache.use_totp_code(&authentication.user_id, form_data.code.trim())
+    } else {
+        database
+            .use_recovery_code(&user_id, &hash_recovery_code(&form_data.code))
+            .await
+    };
+
+    if !is_valid {
+        warn!("Invalid second factor for user {}", authentication.user_id);
+        record_mfa_failure(user.id, &user.email, &ip).await;
+        let has_webauthn = !database.get_webauthn_credentials(&user_id).await.is_empty();
+        return get_mfa_html(
+            &form_data.request_id,
+            &form_data.csrf_token,
+            true,
+            has_webauthn,
+            true,
+        )
+        .into_response();
+    }
+    record_mfa_success(user.id);
+
+    let client_data = match get_client_data(&request_data.client_id).await {
+        Some(data) => data,
+        None => return get_login_error_html().into_response(),
+    };
+
+    authentication.amr.push("otp".to_string());
+    complete_authentication(
+        &form_data.request_id,
+        &request_data,
+        &client_data,
+        &authentication,
+    )
+    .await
+}
+
+/// Confirms a TOTP enrollment with a code from the authenticator app, then shows the recovery codes.
+#[axum::debug_handler]
+pub async fn serve_mfa_enroll(
+    headers: HeaderMap,
+    Form(params): Form<HashMap<String, String>>,
+) -> Response {
+    let form_data = match MfaRequestData::new(&params) {
+        Some(data) => data,
+        None => return get_login_error_html().into_response(),
+    };
+
+    let cache = GLOBAL_CACHE.get().unwrap();
+    let database = GLOBAL_DATABASE.get().unwrap();
+
+    let request_data = match cache.get_request(&form_data.request_id) {
+        Some(data) => data,
+        None => return get_login_error_html().into_response(),
+    };
+
+    if let Err(response) =
+        require_csrf_token(&headers, &form_data.request_id, &form_data.csrf_token)
+    {
+        return response;
+    }
+
+    let mut authentication = match cache.get_request_pending_authentication(&form_data.request_id) {
+        Some(authentication) => authentication,
+        None => return get_login_error_html().into_response(),
+    };
+    let secret = match cache.get_request_pending_totp(&form_data.request_id) {
+        Some(secret) => secret,
+        None => return get_login_error_html().into_response(),
+    };
+
+    let user_id = authentication.user_id.parse::<u32>().unwrap_or_default();
+    let user = match database.get_user_by_id(&user_id).await {
+        Some(user) => user,
+        None => return get_login_error_html().into_response(),
+    };
+
+    if !verify_totp(&secret, &user.email, &form_data.code) {
+        warn!("Invalid TOTP enrollment code for user {}", user.id);
+        return retry_or_deny(&form_data.request_id, &request_data, || {
+            match build_totp(&secret, &user.email) {
+                Some(totp) => get_mfa_enroll_html(
+                    &form_data.request_id,
+                    &form_data.csrf_token,
+                    &secret,
+                    &totp.get_url(),
+                    &totp.get_qr_base64().unwrap_or_default(),
+                    true,
+                )
+                .into_response(),
+                None => get_login_error_html().into_response(),
+            }
+        });
+    }
+
+    let recovery_codes = generate_recovery_codes();
+    let recovery_code_hashes = recovery_codes
+        .iter()
+        .map(|code| hash_recovery_code(code))
+        .collect::<Vec<String>>();
+
+    if !database
+        .save_totp(&user_id, &secret, &recovery_code_hashes)
+        .await
+    {
+        error!("Failed to save TOTP enrollment for user {}", user.id);
+        return get_login_error_html().into_response();
+    }
+    cache.use_totp_code(&authentication.user_id, form_data.code.trim());
+
+    // The enrollment code proves the second factor, the request continues after the codes are shown
+    authentication.amr.push("otp".to_string());
+    cache.set_request_pending_authentication(&form_data.request_id, &authentication);
+
+    get_recovery_codes_html(
+        &form_data.request_id,
+        &form_data.csrf_token,
+        &recovery_codes,
+    )
+    .into_response()
+}
+
+/// Continues the authorization request after the recovery codes have been shown.
+#[axum::debug_handler]
+pub async fn serve_mfa_continue(
+    headers: HeaderMap,
+    Form(params): Form<HashMap<String, String>>,
+) -> Response {
+    let request_id = match params.get("request_id") {
+        Some(request_id) => request_id,
+        None => return get_login_error_html().into_response(),
+    };
+
+    let cache = GLOBAL_CACHE.get().unwrap();
+
+    let request_data = match cache.get_request(request_id) {
+        Some(data) => data,
+        None => return get_login_error_html().into_response(),
+    };
+
+    let csrf_token = params
+        .get("csrf_token")
+        .map(String::as_str)
+        .unwrap_or_default();
+    if let Err(response) = require_csrf_token(&headers, request_id, csrf_token) {
+        return response;
+    }
+
+    // The second factor must have been enrolled for this request
+    let authentication = match cache.get_request_pending_authentication(request_id) {
+        Some(authentication) if authentication.amr.iter().any(|amr| amr == "otp") => authentication,
+        _ => return get_login_error_html().into_response(),
+    };
+
+    let client_data = match get_client_data(&request_data.client_id).await {
+        Some(data) => data,
+        None => return get_login_error_html().into_response(),
+    };
+
+    complete_authentication(request_id, &request_data, &client_data, &authentication).await
+}
+
+/// Lets the signed-in user set up an authenticator app, outside of an authorization request.
+pub async fn serve_account_mfa(headers: HeaderMap) -> Response {
+    let user = match get_session_user(&headers).await {
+        Some(user) => user,
+        None => {
+            return get_error_html("Sign in to set up an authenticator app", "401").into_response()
+        }
+    };
+
+    if GLOBAL_DATABASE
+        .get()
+        .unwrap()
+        .get_totp_secret(&user.id)
+        .await
+        .is_some()
+    {
+        return get_account_mfa_html(&user.email, true, "", "", "").into_response();
+    }
+
+    let secret = generate_totp_secret();
+    let totp = match build_totp(&secret, &user.email) {
+        Some(totp) => totp,
+        None => {
+            return get_error_html("Failed to set up an authenticator app", "500").into_response()
+        }
+    };
+    GLOBAL_CACHE
+        .get()
+        .unwrap()
+        .set_request_pending_totp(&account_enrollment_id(user.id), &secret);
+
+    get_account_mfa_html(
+        &user.email,
+        false,
+        &secret,
+        &totp.get_url(),
+        &totp.get_qr_base64().unwrap_or_default(),
+    )
+    .into_response()
+}
+
+/// Confirms the enrollment of the signed-in user with a code from the authenticator app and
+/// returns the recovery codes. Cross-site requests can't post JSON without CORS.
+pub async fn serve_account_mfa_enroll(
+    headers: HeaderMap,
+    Json(payload): Json<AccountEnrollmentRequest>,
+) -> Response {
+    let user = match get_session_user(&headers).await {
+        Some(user) => user,
+        None => return (StatusCode::UNAUTHORIZED, "No active session").into_response(),
+    };
+
+    let cache = GLOBAL_CACHE.get().unwrap();
+    let database = GLOBAL_DATABASE.get().unwrap();
+    let enrollment_id = account_enrollment_id(user.id);
+
+    if database.get_totp_secret(&user.id).await.is_some() {
+        return (
+            StatusCode::CONFLICT,
+            "An authenticator app is already set up",
+        )
+            .into_response();
+    }
+    let secret = match cache.get_request_pending_totp(&enrollment_id) {
+        Some(secret) => secret,
+        None => return (StatusCode::BAD_REQUEST, "Reload the page to start again").into_response(),
+    };
+
+    if !verify_totp(&secret, &user.email, &payload.code) {
+        warn!("Invalid TOTP enrollment code for user {}", user.id);
+        if cache.increment_mfa_attempts(&enrollment_id) >= MAX_ENROLLMENT_ATTEMPTS {
+            cache.delete_request(&enrollment_id);
+            return (
+                StatusCode::TOO_MANY_REQUESTS,
+                "Too many invalid codes, reload the page to start again",
+            )
+                .into_response();
+        }
+        return (
+            StatusCode::BAD_REQUEST,
+            "The code is invalid, please try again",
+        )
+            .into_response();
+    }
+
+    let recovery_codes = generate_recovery_codes();
+    let recovery_code_hashes = recovery_codes
+        .iter()
+        .map(|code| hash_recovery_code(code))
+        .collect::<Vec<String>>();
+
+    if !database
+        .save_totp(&user.id, &secret, &recovery_code_hashes)
+        .await
+    {
+        error!("Failed to save TOTP enrollment for user {}", user.id);
+        return (
+            StatusCode::INTERNAL_SERVER_ERROR,
+            "Failed to save enrollment",
+        )
+            .into_response();
+    }
+    cache.use_totp_code(&user.id.to_string(), payload.code.trim());
+    cache.delete_request(&enrollment_id);
+
+    database
+        .record_audit_event("mfa_enrolled", Some(user.id), &user.email, None, None)
+        .await;
+    info!("User {} set up an authenticator app", user.id);
+
+    Json(json!({ "recovery_codes": recovery_codes })).into_response()
+}
+
+// The pending enrollment of a signed-in user is cached like the one of a request
+fn account_enrollment_id(user_id: u32) -> String {
+    format!("USER_{}", user_id)
+}
+
+// Lets the user try again, until too many attempts were made for the request
+fn retry_or_deny(
+    request_id: &str,
+    request_data: &AuthorizeRequestData,
+    retry: impl FnOnce() -> Response,
+) -> Response {
+    let cache = GLOBAL_CACHE.get().unwrap();
+
+    if cache.increment_mfa_attempts(request_id) < MAX_ENROLLMENT_ATTEMPTS {
+        return retry();
+    }
+
+    cache.delete_request(request_id);
+    failed_authorization_error(
+        &request_data.redirect_uri,
+        request_data.response_mode(),
+        request_data.state.as_ref(),
+    )
+}
@@ -0,0 +1,153 @@
+use crate::flows::revoke_user_sessions;
+use crate::lockout::unlock_account;
+use crate::pages::{
+    get_error_html, get_forgot_password_html, get_notice_html, get_reset_password_html,
+};
+use crate::passwords::{
+    allows_password_reset, hash_password_blocking, hash_reset_token, send_password_reset,
+    PasswordPolicy,
+};
+use crate::storage::PasswordResetRequestData;
+use crate::{GLOBAL_CACHE, GLOBAL_DATABASE};
+use axum::extract::Query;
+use axum::response::{IntoResponse, Response};
+use axum::Form;
+use log::{error, info};
+use std::collections::HashMap;
+
+/// Shows the form asking for the email address of the account to reset.
+pub async fn serve_forgot_password_page() -> Response {
+    get_forgot_password_html().into_response()
+}
+
+/// Sends a password reset link to the email address, if it belongs to an account.
+pub async fn serve_forgot_password(Form(params): Form<HashMap<String, String>>) -> Response {
+    let email = params
+        .get("email")
+        .map(|email| email.trim().to_string())
+        .unwrap_or_default();
+
+    // The account is looked up and the email sent in the background, so neither the response
+    // nor its timing reveal whether the account exists
+    tokio::spawn(async move {
+        let user = match GLOBAL_DATABASE.get().unwrap().get_user(&email).await {
+            Some(user) => user,
+            None => return,
+        };
+
+        if !allows_password_reset(&user).await {
+            info!("Password reset of linked user {} refused", user.id);
+            return;
+        }
+
+        if !GLOBAL_CACHE
+            .get()
+            .unwrap()
+            .allow_password_reset_email(user.id)
+        {
+            info!("Password reset email for user {} throttled", user.id);
+            return;
+        }
+
+        if !send_password_reset(&user).await {
+            error!(
+                "Failed to send the password reset email to user {}",
+                user.id
+            );
+        }
+    });
+
+    get_notice_html(
+        "Check your email",
+        "If an account exists for this email address, we sent it a link to reset the password.",
+    )
+    .into_response()
+}
+
+/// Shows the form for choosing a new password with a reset link.
+pub async fn serve_reset_password_page(Query(params): Query<HashMap<String, String>>) -> Response {
+    let token = params.get("token").cloned().unwrap_or_default();
+
+    if GLOBAL_CACHE
+        .get()
+        .unwrap()
+        .get_password_reset(&hash_reset_token(&token))
+        .is_none()
+    {
+        return invalid_link();
+    }
+
+    let requirements = PasswordPolicy::from_config().requirements();
+    get_reset_password_html(&token, &[], &requirements).into_response()
+}
+
+/// Sets the new password, then invalidates the other reset links and ends all sessions and
+/// revokes all refresh tokens of the user.
+#[axum::debug_handler]
+pub async fn serve_reset_password(Form(params): Form<HashMap<String, String>>) -> Response {
+    let form_data = match PasswordResetRequestData::new(&params) {
+        Some(data) => data,
+        None => return invalid_link(),
+    };
+
+    let cache = GLOBAL_CACHE.get().unwrap();
+    let database = GLOBAL_DATABASE.get().unwrap();
+    let token_hash = hash_reset_token(&form_data.token);
+
+    // The user may have been linked since the link was sent
+    let user = match cache.get_password_reset(&token_hash) {
+        Some(user_id) => match database.get_user_by_id(&user_id).await {
+            Some(user) if allows_password_reset(&user).await => user,
+            _ => return invalid_link(),
+        },
+        None => return invalid_link(),
+    };
+
+    let policy = PasswordPolicy::from_config();
+    let mut errors = Vec::new();
+    if form_data.password != form_data.password_confirmation {
+        errors.push("The passwords do not match".to_string());
+    }
+    errors.extend(policy.check(&form_data.password, &user.email));
+
+    if !errors.is_empty() {
+        return get_reset_password_html(&form_data.token, &errors, &policy.requirements())
+            .into_response();
+    }
+
+    // The token is only used up once the new password is acceptable
+    if cache.take_password_reset(&token_hash) != Some(user.id) {
+        return invalid_link();
+    }
+
+    let password_hash = match hash_password_blocking(&form_data.password).await {
+        Some(hash) => hash,
+        None => return get_error_html("Failed to reset the password", "500").into_response(),
+    };
+
+    if !database
+        .update_password_hash(&user.id, &password_hash)
+        .await
+    {
+        return get_error_html("Failed to reset the password", "500").into_response();
+    }
+
+    // Other links sent to the user can't be used anymore
+    cache.delete_user_password_resets(user.id);
+    revoke_user_sessions(&user.id.to_string());
+    unlock_account(&user.email).await;
+    database
+        .record_audit_event("password_reset", Some(user.id), &user.email, None, None)
+        .await;
+    info!("Reset the password of user {}", user.id);
+
+    get_notice_html(
+        "Password reset",
+        "Your password has been changed and you have been signed out everywhere. You can now sign in with your new password.",
+    )
+    .into_response()
+}
+
+fn invalid_link() -> Response {
+    get_error_html("The password reset link is invalid or has expired", "400").into_response()
+}
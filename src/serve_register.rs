@@ -0,0 +1,196 @@
+use crate::config::{email_verification_ttl, issuer_url, jwt_secret};
+use crate::lockout::{allow_registration, client_ip};
+use crate::mailer::Email;
+use crate::pages::{get_error_html, get_notice_html, get_register_html};
+use crate::passwords::{hash_password_blocking, PasswordPolicy};
+use crate::storage::RegistrationRequestData;
+use crate::{GLOBAL_DATABASE, GLOBAL_MAILER};
+use axum::extract::{ConnectInfo, Query};
+use axum::http::HeaderMap;
+use axum::response::{IntoResponse, Response};
+use axum::Form;
+use jsonwebtoken::{decode, encode, Algorithm, DecodingKey, EncodingKey, Header, Validation};
+use log::{error, info, warn};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::net::SocketAddr;
+use std::time::{SystemTime, UNIX_EPOCH};
+use validator::ValidateEmail;
+
+// Tells verification tokens apart from other tokens signed with the same key
+const VERIFY_EMAIL_PURPOSE: &str = "verify_email";
+
+#[derive(Serialize, Deserialize)]
+struct VerificationClaims {
+    iss: String,
+    sub: String,
+    email: String,
+    purpose: String,
+    exp: u64,
+}
+
+/// Shows the registration form.
+pub async fn serve_register_page() -> Response {
+    let requirements = PasswordPolicy::from_config().requirements();
+    get_register_html("", &[], &requirements).into_response()
+}
+
+/// Creates an account with an unverified email address and sends the verification link.
+/// Registering an address again sends a new link while it is unverified. The emails are rate
+/// limited per address and per client IP address.
+#[axum::debug_handler]
+pub async fn serve_register(
+    ConnectInfo(address): ConnectInfo<SocketAddr>,
+    headers: HeaderMap,
+    Form(params): Form<HashMap<String, String>>,
+) -> Response {
+    let form_data = match RegistrationRequestData::new(&params) {
+        Some(data) => data,
+        None => return get_error_html("Invalid registration request", "400").into_response(),
+    };
+
+    let policy = PasswordPolicy::from_config();
+    let mut errors = Vec::new();
+    if !form_data.email.validate_email() {
+        errors.push("The email address is invalid".to_string());
+    }
+    if form_data.password != form_data.password_confirmation {
+        errors.push("The passwords do not match".to_string());
+    }
+    errors.extend(policy.check(&form_data.password, &form_data.email));
+
+    if !errors.is_empty() {
+        return get_register_html(&form_data.email, &errors, &policy.requirements())
+            .into_response();
+    }
+
+    if !allow_registration(&form_data.email, &client_ip(&headers, &address)) {
+        return get_error_html("Too many registrations, try again later", "429").into_response();
+    }
+
+    let password_hash = match hash_password_blocking(&form_data.password).await {
+        Some(hash) => hash,
+        None => return get_error_html("Failed to create the account", "500").into_response(),
+    };
+
+    let database = GLOBAL_DATABASE.get().unwrap();
+    let email = match database
+        .create_user(&form_data.email, &password_hash, false)
+        .await
+    {
+        Some(user_id) => {
+            info!("Registered user {}", user_id);
+            verification_email(user_id, &form_data.email)
+        }
+        // Don't reveal that the address is taken, tell its owner instead
+        None => match database.get_user(&form_data.email).await {
+            Some(user) if !user.email_verified => verification_email(user.id, &user.email),
+            Some(_) => Email {
+                to: form_data.email.clone(),
+                subject: "Account registration".to_string(),
+                body: "Someone tried to create an account with this email address, but you \
+                       already have one. If this was you, sign in with your existing account, \
+                       otherwise you can ignore this email."
+                    .to_string(),
+            },
+            None => {
+                warn!("Registration for {} failed", form_data.email);
+                return get_error_html("Failed to create the account", "500").into_response();
+            }
+        },
+    };
+
+    if !GLOBAL_MAILER.get().unwrap().send(&email).await {
+        error!(
+            "Failed to send the registration email to {}",
+            form_data.email
+        );
+    }
+
+    get_notice_html(
+        "Check your email",
+        "We sent a link to your email address, follow it to verify the address and activate your account.",
+    )
+    .into_response()
+}
+
+/// Verifies the email address of an account with the link sent at registration.
+pub async fn serve_verify_email(Query(params): Query<HashMap<String, String>>) -> Response {
+    let claims = match params.get("token").and_then(|token| decode_token(token)) {
+        Some(claims) => claims,
+        None => {
+            return get_error_html("The verification link is invalid or has expired", "400")
+                .into_response()
+        }
+    };
+
+    let user_id = claims.sub.parse::<u32>().unwrap_or_default();
+    if !GLOBAL_DATABASE
+        .get()
+        .unwrap()
+        .verify_user_email(&user_id, &claims.email)
+        .await
+    {
+        return get_error_html("The verification link is invalid or has expired", "400")
+            .into_response();
+    }
+
+    info!("Verified the email address of user {}", user_id);
+    get_notice_html(
+        "Email verified",
+        "Your email address has been verified, you can now sign in.",
+    )
+    .into_response()
+}
+
+fn verification_email(user_id: u32, email: &str) -> Email {
+    let claims = VerificationClaims {
+        iss: issuer_url(),
+        sub: user_id.to_string(),
+        email: email.to_string(),
+        purpose: VERIFY_EMAIL_PURPOSE.to_string(),
+        exp: SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .expect("Time went backwards")
+            .as_secs()
+            + email_verification_ttl(),
+    };
+
+    let token = encode(
+        &Header::default(),
+        &claims,
+        &EncodingKey::from_secret(jwt_secret().as_bytes()),
+    )
+    .unwrap_or_else(|err| {
+        error!("Failed to sign the verification token: {}", err);
+        String::new()
+    });
+
+    Email {
+        to: email.to_string(),
+        subject: "Verify your email address".to_string(),
+        body: format!(
+            "Follow this link to verify your email address and activate your account:\n\n\
+             {}/verify-email?token={}\n\n\
+             If you did not create an account, you can ignore this email.",
+            issuer_url().trim_end_matches('/'),
+            token
+        ),
+    }
+}
+
+fn decode_token(token: &str) -> Option<VerificationClaims> {
+    let mut validation = Validation::new(Algorithm::HS256);
+    validation.set_issuer(&[issuer_url()]);
+
+    let claims = decode::<VerificationClaims>(
+        token,
+        &DecodingKey::from_secret(jwt_secret().as_bytes()),
+        &validation,
+    )
+    .map_err(|err| warn!("Invalid verification token: {}", err))
+    .ok()?
+    .claims;
+
+    (claims.purpose == VERIFY_EMAIL_PURPOSE).then_some(claims)
+}
@@ -1,18 +1,32 @@
-use crate::storage::{check_client_id, get_client_data, Client};
-use crate::GLOBAL_CACHE;
+use crate::claim_mappers::{apply_claims_mappers, ClaimsTarget};
+use crate::claims::restrict_claims;
+use crate::config::{default_resource, issuer_url, jwt_secret, refresh_token_ttl};
+use crate::flows::permitted_scopes;
+use crate::id_tokens::issue_id_token;
+use crate::storage::{
+    check_client_id, get_client_data, get_resource_server, restrict_scopes, Client,
+    RedeemedAuthCode, RefreshTokenData,
+};
+use crate::{GLOBAL_CACHE, GLOBAL_DATABASE};
 use axum::{extract::Json, http::StatusCode, response::IntoResponse};
 use jsonwebtoken::{encode, EncodingKey, Header};
 use log::{debug, error};
+use rand::distributions::Alphanumeric;
+use rand::Rng;
 use serde::{Deserialize, Serialize};
-use std::env;
+use serde_json::{Map, Value};
 use std::time::{SystemTime, UNIX_EPOCH};
 
 // Request body for the token exchange
 #[derive(Deserialize)]
 pub struct TokenRequest {
+    grant_type: Option<String>, // Defaults to authorization_code
     client_id: String,
     client_secret: String,
-    auth_code: String,
+    auth_code: Option<String>,
+    refresh_token: Option<String>,
+    scope: Option<String>, // Narrows the granted scopes
+    resource: Option<String>,
 }
 
 // Response body containing the access token
@@ -21,23 +35,48 @@ struct TokenResponse {
     access_token: String,
     token_type: String,
     expires_in: u64,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    refresh_token: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    id_token: Option<String>,
+    scope: String,
 }
 
-// JWT Claims
+// JWT Claims following the access token profile (RFC 9068)
 #[derive(Serialize)]
 struct Claims {
-    sub: String, // Subject (user ID)
-    client_id: String,
-    scopes: String,
-    exp: usize, // Expiration timestamp
+    iss: String,       // Issuer identifier
+    sub: String,       // Subject (user ID)
+    aud: String,       // Audience (resource server URI)
+    exp: usize,        // Expiration timestamp
+    iat: usize,        // Issued at timestamp
+    nbf: usize,        // Not before timestamp
+    jti: String,       // Unique token identifier
+    auth_time: usize,  // Time the user authenticated
+    client_id: String, // Client the token was issued to
+    scope: String,     // Space-delimited granted scopes
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    amr: Vec<String>, // Authentication methods used
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    groups: Vec<String>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    roles: Vec<String>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    entitlements: Vec<String>,
+    #[serde(flatten)]
+    custom: Map<String, Value>, // Claims of the claims mappers
 }
 
 /// The main function that handles the token exchange.
 pub async fn serve_tokens(Json(payload): Json<TokenRequest>) -> impl IntoResponse {
     let TokenRequest {
+        grant_type,
         client_id,
         client_secret,
         auth_code,
+        refresh_token,
+        scope,
+        resource,
     } = payload;
 
     debug!("Token request received for client_id: {}", client_id);
@@ -48,58 +87,245 @@ pub async fn serve_tokens(Json(payload): Json<TokenRequest>) -> impl IntoRespons
         return (StatusCode::BAD_REQUEST, "Invalid client_id").into_response();
     }
 
-    let client_data: Option<Client> = get_client_data(client_id.as_str()).await;
-
-    if let Some(client) = client_data {
-        if client.secret != client_secret {
-            error!("Invalid client secret for client_id: {}", client_id);
-            return (StatusCode::UNAUTHORIZED, "Invalid client_secret").into_response();
+    let client: Client = match get_client_data(client_id.as_str()).await {
+        Some(client) => client,
+        None => {
+            error!(
+                "Client data not found in cache for client_id: {}",
+                client_id
+            );
+            return (StatusCode::BAD_REQUEST, "Client data not found").into_response();
         }
-    } else {
+    };
+
+    if client.secret != client_secret {
+        error!("Invalid client secret for client_id: {}", client_id);
+        return (StatusCode::UNAUTHORIZED, "Invalid client_secret").into_response();
+    }
+
+    // Clients can be limited to some grant types (RFC 6749 section 5.2)
+    let grant_type = grant_type.unwrap_or_else(|| "authorization_code".to_string());
+    if !client.grant_types.contains(&grant_type) {
         error!(
-            "Client data not found in cache for client_id: {}",
-            client_id
+            "Grant type {} not allowed for client_id: {}",
+            grant_type, client_id
         );
-        return (StatusCode::BAD_REQUEST, "Client data not found").into_response();
+        return (StatusCode::BAD_REQUEST, "Unauthorized grant_type").into_response();
     }
 
-    // Get the cached auth code and scopes
-    let (user_id, scopes) = match GLOBAL_CACHE
+    let cache = GLOBAL_CACHE.get().unwrap();
+
+    // Get the grant from the cached auth code or refresh token data, the nonce of the
+    // authorization request only goes into the first ID token
+    let mut nonce = None;
+    let grant = match grant_type.as_str() {
+        "authorization_code" => {
+            let auth_code = auth_code.unwrap_or_default();
+            let code_data = match cache.take_auth_code(client_id.as_str(), auth_code.as_str()) {
+                Some(data) => data,
+                None => {
+                    // A replayed code may have been stolen, so the tokens issued for it are
+                    // revoked (RFC 6749 section 4.1.2)
+                    if let Some(redeemed) =
+                        cache.get_redeemed_auth_code(client_id.as_str(), auth_code.as_str())
+                    {
+                        error!("Auth code replayed by client_id: {}", client_id);
+                        cache.revoke_grant_refresh_tokens(&redeemed.user_id, &redeemed.grant_id);
+                    }
+                    return (StatusCode::UNAUTHORIZED, "Unknown auth code").into_response();
+                }
+            };
+            nonce = code_data.nonce;
+
+            let grant_id = generate_token(32);
+            cache.set_redeemed_auth_code(
+                client_id.as_str(),
+                auth_code.as_str(),
+                &RedeemedAuthCode {
+                    user_id: code_data.user_id.clone(),
+                    grant_id: grant_id.clone(),
+                },
+            );
+
+            RefreshTokenData {
+                grant_id: Some(grant_id),
+                client_id: client_id.clone(),
+                user_id: code_data.user_id,
+                scope: code_data.scope,
+                resource: code_data.resource,
+                auth_time: code_data.auth_time,
+                amr: code_data.amr,
+                session_id: code_data.session_id,
+                claims: code_data.claims,
+                approved_claims: code_data.approved_claims,
+            }
+        }
+        "refresh_token" => {
+            // Refresh tokens are rotated, the used one is gone either way
+            let mut grant = match refresh_token
+                .and_then(|refresh_token| cache.take_refresh_token(&refresh_token))
+            {
+                Some(data) if data.client_id == client_id => data,
+                Some(_) => {
+                    error!("Refresh token used by another client: {}", client_id);
+                    return (StatusCode::BAD_REQUEST, "Invalid refresh_token").into_response();
+                }
+                None => return (StatusCode::BAD_REQUEST, "Unknown refresh_token").into_response(),
+            };
+
+            // The grant shrinks to the scopes the user's roles still permit and that are
+            // still consented to, and to the claims still consented to
+            let database = GLOBAL_DATABASE.get().unwrap();
+            let user_id = grant.user_id.parse::<u32>().unwrap_or_default();
+            let consented_scopes = database.get_consent(&user_id, &client.id).await;
+            let consented_claims = database.get_consented_claims(&user_id, &client.id).await;
+            grant
+                .approved_claims
+                .retain(|claim| consented_claims.contains(claim));
+            let scope = permitted_scopes(&grant.scope, &grant.user_id)
+                .await
+                .split_whitespace()
+                .filter(|scope| consented_scopes.iter().any(|consented| consented == scope))
+                .collect::<Vec<&str>>()
+                .join(" ");
+            if scope.is_empty() {
+                error!("Refresh token grant no longer permitted: {}", client_id);
+                return (StatusCode::BAD_REQUEST, "Invalid refresh_token").into_response();
+            }
+            grant.scope = scope;
+
+            grant
+        }
+        _ => return (StatusCode::BAD_REQUEST, "Unsupported grant_type").into_response(),
+    };
+
+    // The resource requested here must match the one bound to the grant, if any
+    let resource = match (resource, grant.resource.clone()) {
+        (Some(requested), Some(bound)) if requested != bound => {
+            error!("Resource {} does not match the grant", requested);
+            return (StatusCode::BAD_REQUEST, "Invalid resource").into_response();
+        }
+        (Some(resource), _) | (None, Some(resource)) => Some(resource),
+        (None, None) => None,
+    };
+
+    // The requested scopes can narrow, but not extend the grant (RFC 6749 section 6)
+    let granted_scope = match scope.filter(|scope| !scope.trim().is_empty()) {
+        Some(requested) => {
+            let granted = grant.scope.split_whitespace().collect::<Vec<&str>>();
+            if !requested
+                .split_whitespace()
+                .all(|scope| granted.contains(&scope))
+            {
+                error!("Scope {} exceeds the grant", requested);
+                return (StatusCode::BAD_REQUEST, "Invalid scope").into_response();
+            }
+            requested
+                .split_whitespace()
+                .collect::<Vec<&str>>()
+                .join(" ")
+        }
+        None => grant.scope.clone(),
+    };
+
+    // Restrict the audience and scopes to the requested resource server
+    let (audience, scopes) = match resource {
+        Some(uri) => {
+            let resource_server = match get_resource_server(uri.as_str()).await {
+                Some(server) => server,
+                None => {
+                    error!("Unknown resource: {}", uri);
+                    return (StatusCode::BAD_REQUEST, "Invalid resource").into_response();
+                }
+            };
+
+            let scopes = restrict_scopes(&granted_scope, &resource_server.scopes);
+            if scopes.is_empty() {
+                return (StatusCode::BAD_REQUEST, "No granted scopes for resource").into_response();
+            }
+
+            (resource_server.uri, scopes)
+        }
+        None => (default_resource(), granted_scope.clone()),
+    };
+
+    // Get the user for the authorization attributes
+    let user = match GLOBAL_DATABASE
         .get()
         .unwrap()
-        .get_auth_code_and_scopes(client_id.as_str(), auth_code.as_str())
+        .get_user_by_id(&grant.user_id.parse::<u32>().unwrap_or_default())
+        .await
     {
-        (Some(user_id), Some(scopes)) => (user_id, scopes),
-        (Some(_), None) => {
-            return (StatusCode::UNAUTHORIZED, "Unknown scopes for auth code").into_response()
+        Some(user) if user.enabled => user,
+        Some(user) => {
+            error!("Grant of disabled user: {}", user.id);
+            return (StatusCode::UNAUTHORIZED, "User is disabled").into_response();
         }
-        (None, Some(_)) => {
-            return (StatusCode::UNAUTHORIZED, "Unknown user id for auth code").into_response()
+        None => {
+            error!("Unknown user id for grant: {}", grant.user_id);
+            return (StatusCode::UNAUTHORIZED, "Unknown user id for grant").into_response();
         }
-        (None, None) => return (StatusCode::UNAUTHORIZED, "Unknown auth code").into_response(),
     };
 
     // Generate JWT
-    let expiration_time = SystemTime::now()
+    let issued_at = SystemTime::now()
         .duration_since(UNIX_EPOCH)
         .expect("Time went backwards")
-        .as_secs()
-        + 3600; // Expires in 1 hour
+        .as_secs();
+    let expiration_time = issued_at + 3600; // Expires in 1 hour
+
+    let jti = generate_token(32);
+
+    // Individually requested claims are returned from the userinfo endpoint for this token, the
+    // ones its scopes and the approved claims allow
+    let userinfo_claims = grant
+        .claims
+        .as_ref()
+        .map(|claims_request| {
+            restrict_claims(&claims_request.userinfo, &scopes, &grant.approved_claims)
+        })
+        .unwrap_or_default();
+    if !userinfo_claims.is_empty() {
+        cache.set_userinfo_claims(&jti, &userinfo_claims, expiration_time - issued_at);
+    }
+
+    let mut custom_claims = Map::new();
+    apply_claims_mappers(
+        ClaimsTarget::AccessToken,
+        &client,
+        &scopes,
+        &user,
+        &mut custom_claims,
+    )
+    .await;
 
     let claims = Claims {
-        sub: user_id.to_string(),
-        client_id: client_id.clone(),
-        scopes: scopes.clone(),
+        iss: issuer_url(),
+        sub: grant.user_id.clone(),
+        aud: audience,
         exp: expiration_time as usize,
+        iat: issued_at as usize,
+        nbf: issued_at as usize,
+        jti: jti.clone(),
+        auth_time: grant.auth_time as usize,
+        client_id: client_id.clone(),
+        scope: scopes.clone(),
+        amr: grant.amr.clone(),
+        groups: user.groups.clone(),
+        roles: user.roles.clone(),
+        entitlements: user.entitlements.clone(),
+        custom: custom_claims,
     };
 
-    let jwt_secret = env::var("JWT_SECRET").unwrap_or_else(|_| {
-        error!("JWT_SECRET environment variable is not set");
-        panic!("JWT_SECRET must be set");
-    });
+    let jwt_secret = jwt_secret();
 
     let encoding_key = EncodingKey::from_secret(jwt_secret.as_bytes());
-    let token = encode(&Header::default(), &claims, &encoding_key).unwrap_or_else(|err| {
+    let header = Header {
+        typ: Some("at+jwt".to_string()),
+        ..Default::default()
+    };
+
+    let token = encode(&header, &claims, &encoding_key).unwrap_or_else(|err| {
         error!("Failed to generate JWT: {}", err);
         String::new()
     });
@@ -112,12 +338,56 @@ pub async fn serve_tokens(Json(payload): Json<TokenRequest>) -> impl IntoRespons
             .into_response();
     }
 
+    // OpenID Connect clients also get an ID token
+    let id_token = if granted_scope.split_whitespace().any(|s| s == "openid") {
+        match issue_id_token(&client, &user, &grant, &granted_scope, nonce).await {
+            Some(id_token) => Some(id_token),
+            None => {
+                return (
+                    StatusCode::INTERNAL_SERVER_ERROR,
+                    "Failed to generate id_token",
+                )
+                    .into_response()
+            }
+        }
+    } else {
+        None
+    };
+
+    // Issue a new refresh token for the whole grant, if the client can use it
+    let refresh_token = if client.grant_types.iter().any(|g| g == "refresh_token") {
+        let refresh_token = generate_token(48);
+        cache.set_refresh_token(&refresh_token, &grant, refresh_token_ttl());
+        Some(refresh_token)
+    } else {
+        None
+    };
+
+    // Shown to the user with their authorized applications
+    GLOBAL_DATABASE
+        .get()
+        .unwrap()
+        .record_consent_use(&user.id, &client.id)
+        .await;
+
     // Return the response
     let response = TokenResponse {
         access_token: token,
         token_type: "Bearer".to_string(),
         expires_in: 3600,
+        refresh_token,
+        id_token,
+        // The granted scopes may be fewer than requested (RFC 6749 section 5.1)
+        scope: scopes,
     };
 
     (StatusCode::OK, Json(response)).into_response()
 }
+
+fn generate_token(length: usize) -> String {
+    rand::thread_rng()
+        .sample_iter(&Alphanumeric)
+        .take(length)
+        .map(char::from)
+        .collect()
+}
@@ -0,0 +1,97 @@
+use crate::claim_mappers::{apply_claims_mappers, ClaimsTarget};
+use crate::claims::select_claims;
+use crate::config::{issuer_url, jwt_secret};
+use crate::errors::bearer_error;
+use crate::storage::get_client_data;
+use crate::{GLOBAL_CACHE, GLOBAL_DATABASE};
+use axum::extract::Json;
+use axum::http::header::AUTHORIZATION;
+use axum::http::{HeaderMap, StatusCode};
+use axum::response::{IntoResponse, Response};
+use jsonwebtoken::{decode, Algorithm, DecodingKey, Validation};
+use log::warn;
+use serde::Deserialize;
+use serde_json::Value;
+
+// The access token claims the userinfo endpoint needs
+#[derive(Deserialize)]
+struct AccessTokenClaims {
+    sub: String,
+    jti: String,
+    client_id: String,
+    scope: String,
+}
+
+/// Returns the claims about the user of an access token that its scopes and the claims
+/// request ask for (OpenID Connect Core section 5.3).
+pub async fn serve_userinfo(headers: HeaderMap) -> Response {
+    let token = match headers
+        .get(AUTHORIZATION)
+        .and_then(|header| header.to_str().ok())
+        .and_then(|header| header.strip_prefix("Bearer "))
+    {
+        Some(token) => token,
+        None => return bearer_error(StatusCode::UNAUTHORIZED, "invalid_token"),
+    };
+
+    // Access tokens are issued for resource servers, any audience is accepted here
+    let mut validation = Validation::new(Algorithm::HS256);
+    validation.set_issuer(&[issuer_url()]);
+    validation.validate_aud = false;
+
+    let token_data = match decode::<AccessTokenClaims>(
+        token,
+        &DecodingKey::from_secret(jwt_secret().as_bytes()),
+        &validation,
+    ) {
+        Ok(token_data) => token_data,
+        Err(err) => {
+            warn!("Invalid access token for userinfo: {}", err);
+            return bearer_error(StatusCode::UNAUTHORIZED, "invalid_token");
+        }
+    };
+
+    // Only access tokens, other tokens signed with the same key aren't accepted
+    if token_data.header.typ.as_deref() != Some("at+jwt") {
+        return bearer_error(StatusCode::UNAUTHORIZED, "invalid_token");
+    }
+    let claims = token_data.claims;
+
+    if !claims.scope.split_whitespace().any(|s| s == "openid") {
+        return bearer_error(StatusCode::FORBIDDEN, "insufficient_scope");
+    }
+
+    let user = match GLOBAL_DATABASE
+        .get()
+        .unwrap()
+        .get_user_by_id(&claims.sub.parse::<u32>().unwrap_or_default())
+        .await
+    {
+        Some(user) if user.enabled => user,
+        _ => return bearer_error(StatusCode::UNAUTHORIZED, "invalid_token"),
+    };
+
+    let client_data = match get_client_data(&claims.client_id).await {
+        Some(data) if data.enabled => data,
+        _ => return bearer_error(StatusCode::UNAUTHORIZED, "invalid_token"),
+    };
+
+    let requested_claims = GLOBAL_CACHE.get().unwrap().get_userinfo_claims(&claims.jti);
+    // The claims were limited to the ones the token may return when it was issued
+    let approved = requested_claims
+        .iter()
+        .flat_map(|requested| requested.keys().cloned())
+        .collect::<Vec<String>>();
+    let mut userinfo = select_claims(&user, &claims.scope, requested_claims.as_ref(), &approved);
+    apply_claims_mappers(
+        ClaimsTarget::Userinfo,
+        &client_data,
+        &claims.scope,
+        &user,
+        &mut userinfo,
+    )
+    .await;
+    userinfo.insert("sub".to_string(), Value::from(user.id.to_string()));
+
+    (StatusCode::OK, Json(Value::Object(userinfo))).into_response()
+}
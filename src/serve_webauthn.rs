@@ -0,0 +1,330 @@
+use crate::binding::require_csrf_token;
+use crate::config::webauthn_rp_name;
+use crate::errors::failed_authorization_error;
+use crate::flows::complete_authentication;
+use crate::pages::{get_error_html, get_login_error_html, get_webauthn_html};
+use crate::storage::{get_client_data, get_session_user, Authentication, WebauthnCredential};
+use crate::webauthn::{
+    decode_base64url, encode_base64url, generate_challenge, sign_count_increased, verify_assertion,
+    verify_registration, RelyingParty, COSE_ALG_EDDSA, COSE_ALG_ES256, COSE_ALG_RS256,
+};
+use crate::{GLOBAL_CACHE, GLOBAL_DATABASE};
+use axum::extract::Json;
+use axum::http::{HeaderMap, StatusCode};
+use axum::response::{IntoResponse, Response};
+use axum::Form;
+use log::{error, warn};
+use serde::Deserialize;
+use serde_json::{json, Value};
+use std::collections::HashMap;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+// Request body for registering a new credential
+#[derive(Deserialize)]
+pub struct RegistrationRequest {
+    name: Option<String>,
+    client_data_json: String,
+    attestation_object: String,
+}
+
+// Request body for the authentication ceremony options
+#[derive(Deserialize)]
+pub struct LoginOptionsRequest {
+    request_id: String,
+}
+
+/// Shows the passkeys of the signed-in user and lets them register new ones.
+pub async fn serve_webauthn(headers: HeaderMap) -> Response {
+    let user = match get_session_user(&headers).await {
+        Some(user) => user,
+        None => return get_error_html("Sign in to manage your passkeys", "401").into_response(),
+    };
+
+    let credential_names = GLOBAL_DATABASE
+        .get()
+        .unwrap()
+        .get_webauthn_credentials(&user.id)
+        .await
+        .into_iter()
+        .map(|credential| credential.name)
+        .collect::<Vec<String>>();
+
+    get_webauthn_html(&user.email, &credential_names).into_response()
+}
+
+/// Returns the options for `navigator.credentials.create` for the signed-in user.
+pub async fn serve_webauthn_register_options(headers: HeaderMap) -> Response {
+    let user = match get_session_user(&headers).await {
+        Some(user) => user,
+        None => return (StatusCode::UNAUTHORIZED, "No active session").into_response(),
+    };
+
+    let challenge = generate_challenge();
+    GLOBAL_CACHE
+        .get()
+        .unwrap()
+        .set_webauthn_challenge(&format!("USER_{}", user.id), &challenge);
+
+    // Don't register the same authenticator twice
+    let exclude_credentials = GLOBAL_DATABASE
+        .get()
+        .unwrap()
+        .get_webauthn_credentials(&user.id)
+        .await
+        .into_iter()
+        .map(|credential| json!({ "type": "public-key", "id": credential.credential_id }))
+        .collect::<Vec<Value>>();
+
+    let relying_party = RelyingParty::from_config();
+    Json(json!({
+        "challenge": challenge,
+        "rp": { "id": relying_party.id, "name": webauthn_rp_name() },
+        "user": {
+            "id": encode_base64url(user.id.to_string().as_bytes()),
+            "name": user.email,
+            "displayName": user.email,
+        },
+        "pubKeyCredParams": [
+            { "type": "public-key", "alg": COSE_ALG_ES256 },
+            { "type": "public-key", "alg": COSE_ALG_EDDSA },
+            { "type": "public-key", "alg": COSE_ALG_RS256 },
+        ],
+        "timeout": 300000,
+        "attestation": "none",
+        "authenticatorSelection": {
+            "residentKey": "preferred",
+            "userVerification": "preferred",
+        },
+        "excludeCredentials": exclude_credentials,
+    }))
+    .into_response()
+}
+
+/// Verifies the registration ceremony and stores the new credential for the signed-in user.
+pub async fn serve_webauthn_register(
+    headers: HeaderMap,
+    Json(payload): Json<RegistrationRequest>,
+) -> Response {
+    let user = match get_session_user(&headers).await {
+        Some(user) => user,
+        None => return (StatusCode::UNAUTHORIZED, "No active session").into_response(),
+    };
+
+    let challenge = match GLOBAL_CACHE
+        .get()
+        .unwrap()
+        .take_webauthn_challenge(&format!("USER_{}", user.id))
+    {
+        Some(challenge) => challenge,
+        None => return (StatusCode::BAD_REQUEST, "Unknown challenge").into_response(),
+    };
+
+    let (client_data_json, attestation_object) = match (
+        decode_base64url(&payload.client_data_json),
+        decode_base64url(&payload.attestation_object),
+    ) {
+        (Some(client_data_json), Some(attestation_object)) => {
+            (client_data_json, attestation_object)
+        }
+        _ => return (StatusCode::BAD_REQUEST, "Invalid credential encoding").into_response(),
+    };
+
+    let registered_credential = match verify_registration(
+        &RelyingParty::from_config(),
+        &challenge,
+        &client_data_json,
+        &attestation_object,
+    ) {
+        Some(credential) => credential,
+        None => return (StatusCode::BAD_REQUEST, "Invalid credential").into_response(),
+    };
+
+    let credential = WebauthnCredential {
+        credential_id: encode_base64url(&registered_credential.credential_id),
+        user_id: user.id,
+        public_key: registered_credential.public_key,
+        sign_count: registered_credential.sign_count as i64,
+        name: payload
+            .name
+            .filter(|name| !name.trim().is_empty())
+            .unwrap_or_else(|| "Passkey".to_string()),
+    };
+
+    if !GLOBAL_DATABASE
+        .get()
+        .unwrap()
+        .save_webauthn_credential(&credential)
+        .await
+    {
+        error!("Failed to save WebAuthn credential for user {}", user.id);
+        return (
+            StatusCode::INTERNAL_SERVER_ERROR,
+            "Failed to save credential",
+        )
+            .into_response();
+    }
+
+    (
+        StatusCode::CREATED,
+        Json(json!({ "name": credential.name })),
+    )
+        .into_response()
+}
+
+/// Returns the options for `navigator.credentials.get` for an authorization request. After a
+/// password login the user's credentials are used as a second factor, otherwise any passkey
+/// with user verification can sign in.
+pub async fn serve_webauthn_login_options(Json(payload): Json<LoginOptionsRequest>) -> Response {
+    let cache = GLOBAL_CACHE.get().unwrap();
+
+    if cache.get_request(&payload.request_id).is_none() {
+        return (StatusCode::BAD_REQUEST, "Unknown request").into_response();
+    }
+
+    let challenge = generate_challenge();
+    cache.set_webauthn_challenge(&format!("REQUEST_{}", payload.request_id), &challenge);
+
+    let (allow_credentials, user_verification) =
+        match cache.get_request_pending_authentication(&payload.request_id) {
+            Some(authentication) => {
+                let credentials = GLOBAL_DATABASE
+                .get()
+                .unwrap()
+                .get_webauthn_credentials(
+                    &authentication.user_id.parse::<u32>().unwrap_or_default(),
+                )
+                .await
+                .into_iter()
+                .map(|credential| json!({ "type": "public-key", "id": credential.credential_id }))
+                .collect::<Vec<Value>>();
+                (credentials, "discouraged")
+            }
+            None => (Vec::new(), "required"),
+        };
+
+    Json(json!({
+        "challenge": challenge,
+        "rpId": RelyingParty::from_config().id,
+        "timeout": 300000,
+        "userVerification": user_verification,
+        "allowCredentials": allow_credentials,
+    }))
+    .into_response()
+}
+
+/// Verifies the authentication ceremony and continues the authorization request.
+#[axum::debug_handler]
+pub async fn serve_webauthn_login(
+    headers: HeaderMap,
+    Form(params): Form<HashMap<String, String>>,
+) -> Response {
+    let field = |name: &str| params.get(name).cloned().unwrap_or_default();
+    let request_id = field("request_id");
+
+    let cache = GLOBAL_CACHE.get().unwrap();
+    let database = GLOBAL_DATABASE.get().unwrap();
+
+    let request_data = match cache.get_request(&request_id) {
+        Some(data) => data,
+        None => return get_login_error_html().into_response(),
+    };
+    if let Err(response) = require_csrf_token(&headers, &request_id, &field("csrf_token")) {
+        return response;
+    }
+    let failed = || {
+        failed_authorization_error(
+            &request_data.redirect_uri,
+            request_data.response_mode(),
+            request_data.state.as_ref(),
+        )
+    };
+
+    let challenge = match cache.take_webauthn_challenge(&format!("REQUEST_{}", request_id)) {
+        Some(challenge) => challenge,
+        None => return get_login_error_html().into_response(),
+    };
+
+    let credential = match database
+        .get_webauthn_credential(&field("credential_id"))
+        .await
+    {
+        Some(credential) => credential,
+        None => {
+            warn!("Unknown WebAuthn credential");
+            return failed();
+        }
+    };
+
+    let (Some(client_data_json), Some(authenticator_data), Some(signature)) = (
+        decode_base64url(&field("client_data_json")),
+        decode_base64url(&field("authenticator_data")),
+        decode_base64url(&field("signature")),
+    ) else {
+        return failed();
+    };
+
+    let verified_data = match verify_assertion(
+        &RelyingParty::from_config(),
+        &challenge,
+        &client_data_json,
+        &authenticator_data,
+        &signature,
+        &credential.public_key,
+    ) {
+        Some(data) => data,
+        None => return failed(),
+    };
+
+    if !sign_count_increased(credential.sign_count, verified_data.sign_count) {
+        warn!(
+            "WebAuthn signature counter did not increase for credential {}",
+            credential.credential_id
+        );
+        return failed();
+    }
+    database
+        .update_webauthn_sign_count(&credential.credential_id, verified_data.sign_count as i64)
+        .await;
+
+    let authentication = match cache.get_request_pending_authentication(&request_id) {
+        // Second factor after a password login, it must be the same user
+        Some(mut authentication) => {
+            if authentication.user_id != credential.user_id.to_string() {
+                warn!("WebAuthn credential belongs to another user");
+                return failed();
+            }
+
+            authentication.amr.push("hwk".to_string());
+            authentication
+        }
+        // Passwordless login, the authenticator must have verified the user
+        None => {
+            let user_handle = field("user_handle");
+            if !verified_data.user_verified()
+                || (!user_handle.is_empty()
+                    && decode_base64url(&user_handle)
+                        != Some(credential.user_id.to_string().into_bytes()))
+            {
+                warn!("WebAuthn passwordless login without user verification");
+                return failed();
+            }
+
+            Authentication {
+                user_id: credential.user_id.to_string(),
+                auth_time: SystemTime::now()
+                    .duration_since(UNIX_EPOCH)
+                    .expect("Time went backwards")
+                    .as_secs(),
+                amr: vec!["hwk".to_string(), "mfa".to_string()],
+                session_id: None,
+            }
+        }
+    };
+
+    let client_data = match get_client_data(&request_data.client_id).await {
+        Some(data) => data,
+        None => return get_login_error_html().into_response(),
+    };
+
+    complete_authentication(&request_id, &request_data, &client_data, &authentication).await
+}
@@ -64,6 +64,79 @@ pub async fn get_client_data(client_id: &str) -> Option<Client> {
     }
 }
 
+/// Gets the user signed in with the browser SSO session of the request.
+pub async fn get_session_user(headers: &HeaderMap) -> Option<User> {
+    let session_key = get_cookie(headers, SESSION_COOKIE)?;
+    let authentication = GLOBAL_CACHE.get().unwrap().get_session(&session_key)?;
+
+    GLOBAL_DATABASE
+        .get()
+        .unwrap()
+        .get_user_by_id(&authentication.user_id.parse::<u32>().ok()?)
+        .await
+        .filter(|user| user.enabled)
+}
+
+pub async fn get_resource_server(uri: &str) -> Option<ResourceServer> {
+    let data_from_cache = GLOBAL_CACHE.get().unwrap().get_resource_server(uri);
+    if let Some(data) = data_from_cache {
+        Some(data)
+    } else {
+        let data = GLOBAL_DATABASE
+            .get()
+            .unwrap()
+            .get_resource_server(uri)
+            .await;
+        if let Some(data) = data {
+            GLOBAL_CACHE.get().unwrap().set_resource_server(&data);
+            Some(data)
+        } else {
+            None
+        }
+    }
+}
+
+pub async fn get_scope(name: &str) -> Option<Scope> {
+    let data_from_cache = GLOBAL_CACHE.get().unwrap().get_scope(name);
+    if let Some(data) = data_from_cache {
+        Some(data)
+    } else {
+        let data = GLOBAL_DATABASE.get().unwrap().get_scope(name).await;
+        if let Some(data) = data {
+            GLOBAL_CACHE.get().unwrap().set_scope(&data);
+            Some(data)
+        } else {
+            None
+        }
+    }
+}
+
+/// Looks up the scopes of a space-delimited scope string in the registry,
+/// returns `None` if any of them is not registered.
+pub async fn get_scopes(scope: &str) -> Option<Vec<Scope>> {
+    let mut scopes = Vec::new();
+    for name in scope.split_whitespace() {
+        match get_scope(name).await {
+            Some(data) => scopes.push(data),
+            None => {
+                debug!("Scope {} is not registered", name);
+                return None;
+            }
+        }
+    }
+
+    Some(scopes)
+}
+
+/// Restricts a space-delimited scope string to the scopes owned by a resource server.
+pub fn restrict_scopes(scope: &str, allowed_scopes: &[String]) -> String {
+    scope
+        .split_whitespace()
+        .filter(|s| allowed_scopes.iter().any(|allowed| allowed == s))
+        .collect::<Vec<&str>>()
+        .join(" ")
+}
+
 #[derive(Debug, Serialize, Deserialize)]
 pub struct Client {
     pub id: u32,
@@ -71,28 +144,132 @@ pub struct Client {
     pub allowed_scopes: Vec<String>,
     pub redirect_uris: Vec<String>,
     pub secret: String,
+    #[serde(default)]
+    pub mfa_required: bool,
+    /// The names of the user authentication backends, tried in order.
+    #[serde(default)]
+    pub authenticators: Vec<String>,
+    /// The scopes granted when a request omits the scope.
+    #[serde(default)]
+    pub default_scopes: Vec<String>,
+    #[serde(default)]
+    pub post_logout_redirect_uris: Vec<String>,
+    #[serde(default)]
+    pub frontchannel_logout_uri: Option<String>,
+    #[serde(default)]
+    pub backchannel_logout_uri: Option<String>,
+    /// The custom claims of the client, including those of every client.
+    #[serde(default)]
+    pub claim_mappings: Vec<ClaimMapping>,
+    #[serde(default = "default_client_enabled")]
+    pub enabled: bool,
+    /// The grant types the client can use at the token endpoint.
+    #[serde(default = "default_grant_types")]
+    pub grant_types: Vec<String>,
+}
+
+// Clients cached before they could be disabled are enabled
+fn default_client_enabled() -> bool {
+    true
+}
+
+// Clients cached before grant types were restricted can use all of them
+fn default_grant_types() -> Vec<String> {
+    GRANT_TYPES
+        .iter()
+        .map(|grant_type| grant_type.to_string())
+        .collect()
+}
+
+/// The grant types supported by the token endpoint.
+pub const GRANT_TYPES: [&str; 2] = ["authorization_code", "refresh_token"];
+
+/// A custom claim added to tokens, see the `claim_mappings` table.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct ClaimMapping {
+    pub claim: String,
+    pub source: String,
+    pub value: String,
+    pub scope: Option<String>,
+    pub targets: Vec<String>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct ResourceServer {
+    pub uri: String,
+    pub name: String,
+    pub scopes: Vec<String>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct Scope {
+    pub name: String,
+    pub description: String,
+    /// One of `low`, `medium` or `high`.
+    pub sensitivity: String,
+    /// The URI of the resource server owning the scope.
+    pub resource_server: Option<String>,
+    /// Whether the scope is granted when a request omits the scope.
+    pub is_default: bool,
+    /// The roles or groups a user needs one of to be granted the scope, empty for everyone.
+    #[serde(default)]
+    pub roles: Vec<String>,
+}
+
+impl Scope {
+    pub fn is_sensitive(&self) -> bool {
+        self.sensitivity == "high"
+    }
+
+    pub fn is_permitted(&self, user: &User) -> bool {
+        self.roles.is_empty()
+            || self
+                .roles
+                .iter()
+                .any(|role| user.roles.contains(role) || user.groups.contains(role))
+    }
 }
 
+use crate::claims::ClaimsRequest;
+use crate::cookies::{get_cookie, SESSION_COOKIE};
+use crate::response_modes::ResponseMode;
 use crate::{GLOBAL_CACHE, GLOBAL_DATABASE};
+use axum::http::HeaderMap;
 use log::{debug, error};
 use std::collections::HashMap;
 
-#[derive(Debug, Serialize, Deserialize)]
+#[derive(Clone, Debug, Serialize, Deserialize)]
 pub struct AuthorizeRequestData {
     pub client_id: String,
     pub redirect_uri: String,
     pub scope: String,
     pub state: Option<String>,
     pub response_type: Option<String>,
+    pub resource: Option<String>,
+    pub response_mode: Option<String>,
+    pub nonce: Option<String>,
+    pub prompt: Option<String>,
+    pub max_age: Option<String>,
+    pub login_hint: Option<String>,
+    pub id_token_hint: Option<String>,
+    pub claims: Option<String>,
 }
 
 impl<'a> AuthorizeRequestData {
     pub fn new(params: &'a HashMap<String, String>) -> Option<Self> {
         let client_id = params.get("client_id")?.clone();
         let redirect_uri = params.get("redirect_uri")?.clone();
-        let scope = params.get("scope")?.clone();
+        let scope = params.get("scope").cloned().unwrap_or_default();
         let state = params.get("state").cloned();
         let response_type = params.get("response_type").cloned();
+        let resource = params.get("resource").cloned();
+        let response_mode = params.get("response_mode").cloned();
+        let nonce = params.get("nonce").cloned();
+        let prompt = params.get("prompt").cloned();
+        let max_age = params.get("max_age").cloned();
+        let login_hint = params.get("login_hint").cloned();
+        let id_token_hint = params.get("id_token_hint").cloned();
+        let claims = params.get("claims").cloned();
 
         Some(AuthorizeRequestData {
             client_id,
@@ -100,8 +277,103 @@ impl<'a> AuthorizeRequestData {
             scope,
             state,
             response_type,
+            resource,
+            response_mode,
+            nonce,
+            prompt,
+            max_age,
+            login_hint,
+            id_token_hint,
+            claims,
         })
     }
+
+    /// Whether the space-delimited `prompt` parameter contains the value.
+    pub fn has_prompt(&self, value: &str) -> bool {
+        self.prompt
+            .as_deref()
+            .is_some_and(|prompt| prompt.split_whitespace().any(|p| p == value))
+    }
+
+    /// The parsed `claims` parameter, if the client requests individual claims.
+    pub fn claims_request(&self) -> Option<ClaimsRequest> {
+        self.claims.as_deref().and_then(ClaimsRequest::parse)
+    }
+
+    /// The maximum age of the authentication in seconds, if the client limits it.
+    pub fn max_age(&self) -> Option<u64> {
+        self.max_age
+            .as_deref()
+            .and_then(|max_age| max_age.parse().ok())
+    }
+
+    /// The response mode requested by the client, defaulting to query for the code flow.
+    /// Unsupported response modes also fall back to the default so errors can be returned.
+    pub fn response_mode(&self) -> ResponseMode {
+        self.response_mode
+            .as_deref()
+            .and_then(ResponseMode::parse)
+            .unwrap_or_default()
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct AuthCodeData {
+    pub user_id: String,
+    pub scope: String,
+    pub resource: Option<String>,
+    pub auth_time: u64,
+    #[serde(default)]
+    pub amr: Vec<String>,
+    #[serde(default)]
+    pub nonce: Option<String>,
+    #[serde(default)]
+    pub session_id: Option<String>,
+    #[serde(default)]
+    pub claims: Option<ClaimsRequest>,
+    #[serde(default)]
+    pub approved_claims: Vec<String>,
+}
+
+/// The grant an auth code was redeemed for, kept to revoke it when the code is replayed.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct RedeemedAuthCode {
+    pub user_id: String,
+    pub grant_id: String,
+}
+
+/// A refresh token grant, the scope is the one originally granted, narrowed to what the user
+/// still permits at every refresh.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct RefreshTokenData {
+    /// Identifies the grant across refresh token rotations.
+    #[serde(default)]
+    pub grant_id: Option<String>,
+    pub client_id: String,
+    pub user_id: String,
+    pub scope: String,
+    pub resource: Option<String>,
+    pub auth_time: u64,
+    pub amr: Vec<String>,
+    /// The browser SSO session the grant was issued in.
+    #[serde(default)]
+    pub session_id: Option<String>,
+    /// The individual claims requested for the ID token and userinfo.
+    #[serde(default)]
+    pub claims: Option<ClaimsRequest>,
+    /// The claims the user consented to on their own, besides the claims of the scopes.
+    #[serde(default)]
+    pub approved_claims: Vec<String>,
+}
+
+/// The scopes a user granted to a client. Times are Unix timestamps.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct Consent {
+    pub client_id: u32,
+    pub client_name: String,
+    pub scopes: Vec<String>,
+    pub created_at: i64,
+    pub last_used_at: Option<i64>,
 }
 
 #[derive(Debug, Serialize, Deserialize)]
@@ -109,6 +381,7 @@ pub struct LoginRequestData {
     pub request_id: String,
     pub email: String,
     pub password: String,
+    pub csrf_token: String,
 }
 
 impl<'a> LoginRequestData {
@@ -116,18 +389,274 @@ impl<'a> LoginRequestData {
         let request_id = params.get("request_id")?.clone();
         let email = params.get("email")?.clone();
         let password = params.get("password")?.clone();
+        let csrf_token = params.get("csrf_token")?.clone();
 
         Some(LoginRequestData {
             request_id,
             email,
             password,
+            csrf_token,
         })
     }
 }
 
+#[derive(Debug, Serialize, Deserialize)]
+pub struct ConsentRequestData {
+    pub request_id: String,
+    pub action: String,
+    pub scopes: Vec<String>,
+    pub claims: Vec<String>,
+    pub csrf_token: String,
+}
+
+impl<'a> ConsentRequestData {
+    pub fn new(params: &'a [(String, String)]) -> Option<Self> {
+        let find = |name: &str| {
+            params
+                .iter()
+                .find(|(key, _)| key == name)
+                .map(|(_, value)| value.clone())
+        };
+
+        let request_id = find("request_id")?;
+        let action = find("action")?;
+        let csrf_token = find("csrf_token")?;
+        let scopes = params
+            .iter()
+            .filter(|(key, _)| key == "scope")
+            .map(|(_, value)| value.clone())
+            .collect();
+        let claims = params
+            .iter()
+            .filter(|(key, _)| key == "claim")
+            .map(|(_, value)| value.clone())
+            .collect();
+
+        Some(ConsentRequestData {
+            request_id,
+            action,
+            scopes,
+            claims,
+            csrf_token,
+        })
+    }
+}
+
+/// A completed user authentication, kept until the authorization request is finished
+/// and as the state of the browser SSO session.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct Authentication {
+    pub user_id: String,
+    pub auth_time: u64,
+    pub amr: Vec<String>, // Authentication methods used
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    // The public `sid` of the browser SSO session once it is started, never the cookie value
+    pub session_id: Option<String>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct MfaRequestData {
+    pub request_id: String,
+    pub code: String,
+    pub csrf_token: String,
+}
+
+impl<'a> MfaRequestData {
+    pub fn new(params: &'a HashMap<String, String>) -> Option<Self> {
+        let request_id = params.get("request_id")?.clone();
+        let code = params.get("code")?.clone();
+        let csrf_token = params.get("csrf_token")?.clone();
+
+        Some(MfaRequestData {
+            request_id,
+            code,
+            csrf_token,
+        })
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct RegistrationRequestData {
+    pub email: String,
+    pub password: String,
+    pub password_confirmation: String,
+}
+
+impl<'a> RegistrationRequestData {
+    pub fn new(params: &'a HashMap<String, String>) -> Option<Self> {
+        let email = params.get("email")?.trim().to_string();
+        let password = params.get("password")?.clone();
+        let password_confirmation = params.get("password_confirmation")?.clone();
+
+        Some(RegistrationRequestData {
+            email,
+            password,
+            password_confirmation,
+        })
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct IdentityProvider {
+    pub id: String,
+    pub name: String,
+    pub issuer: String,
+    pub client_id: String,
+    pub client_secret: String,
+    pub scopes: String,
+    pub authorization_endpoint: Option<String>,
+    pub token_endpoint: Option<String>,
+    pub userinfo_endpoint: Option<String>,
+    pub trust_email: bool,
+}
+
+/// A login through an upstream identity provider, stored under its `state` parameter.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct FederationState {
+    pub provider_id: String,
+    pub request_id: String,
+    pub nonce: String,
+    pub code_verifier: String,
+}
+
+/// An upstream identity that the signed-in user has to confirm linking to their account.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct PendingIdentityLink {
+    pub provider_id: String,
+    pub subject: String,
+    pub email: Option<String>,
+    pub user_id: u32,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct PasswordResetRequestData {
+    pub token: String,
+    pub password: String,
+    pub password_confirmation: String,
+}
+
+impl<'a> PasswordResetRequestData {
+    pub fn new(params: &'a HashMap<String, String>) -> Option<Self> {
+        let token = params.get("token")?.clone();
+        let password = params.get("password")?.clone();
+        let password_confirmation = params.get("password_confirmation")?.clone();
+
+        Some(PasswordResetRequestData {
+            token,
+            password,
+            password_confirmation,
+        })
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct WebauthnCredential {
+    pub credential_id: String,
+    pub user_id: u32,
+    pub public_key: Vec<u8>,
+    pub sign_count: i64,
+    pub name: String,
+}
+
+/// Where the credentials of a user are managed.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum CredentialSource {
+    /// A password stored here.
+    Local,
+    /// The LDAP directory the user is linked to.
+    Ldap,
+    /// The upstream identity providers the user is linked to.
+    Federated,
+}
+
+impl CredentialSource {
+    /// Whether a password stored here signs the user in. Directory users would otherwise keep
+    /// access after the directory disabled or removed them.
+    pub fn allows_local_login(&self) -> bool {
+        *self != CredentialSource::Ldap
+    }
+
+    /// Whether the user can set a password here by email. Linked users could otherwise give
+    /// themselves a local password that outlives their upstream account.
+    pub fn allows_password_reset(&self) -> bool {
+        *self == CredentialSource::Local
+    }
+}
+
 #[derive(Debug, Serialize, Deserialize)]
 pub struct User {
     pub id: u32,
     pub email: String,
-    pub password: String,
+    pub password_hash: String,
+    pub groups: Vec<String>,
+    pub roles: Vec<String>,
+    pub entitlements: Vec<String>,
+    pub mfa_required: bool,
+    pub email_verified: bool,
+    pub enabled: bool,
+    pub name: Option<String>,
+    pub phone_number: Option<String>,
+    pub phone_number_verified: bool,
+}
+
+/// The attributes of a user to change, the others are left as they are. An empty name or phone
+/// number removes it.
+#[derive(Debug, Default)]
+pub struct UserUpdate {
+    pub groups: Option<Vec<String>>,
+    pub roles: Option<Vec<String>>,
+    pub entitlements: Option<Vec<String>>,
+    pub mfa_required: Option<bool>,
+    pub email_verified: Option<bool>,
+    pub name: Option<String>,
+    pub phone_number: Option<String>,
+    pub phone_number_verified: Option<bool>,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn owned(scopes: &[&str]) -> Vec<String> {
+        scopes.iter().map(|s| s.to_string()).collect()
+    }
+
+    #[test]
+    fn restrict_scopes_keeps_owned_scopes_in_order() {
+        let allowed_scopes = owned(&["write", "read"]);
+
+        assert_eq!(
+            restrict_scopes("read openid write", &allowed_scopes),
+            "read write"
+        );
+        assert_eq!(restrict_scopes("write read", &allowed_scopes), "write read");
+    }
+
+    #[test]
+    fn restrict_scopes_ignores_extra_whitespace_and_partial_names() {
+        let allowed_scopes = owned(&["read"]);
+
+        assert_eq!(restrict_scopes("  read   reader ", &allowed_scopes), "read");
+        assert_eq!(restrict_scopes("re ad", &allowed_scopes), "");
+    }
+
+    #[test]
+    fn restrict_scopes_without_a_match_is_empty() {
+        assert_eq!(restrict_scopes("openid profile", &owned(&["read"])), "");
+        assert_eq!(restrict_scopes("read", &[]), "");
+        assert_eq!(restrict_scopes("", &owned(&["read"])), "");
+    }
+
+    #[test]
+    fn only_local_users_reset_passwords_and_directory_users_never_use_them() {
+        assert!(CredentialSource::Local.allows_local_login());
+        assert!(CredentialSource::Local.allows_password_reset());
+
+        assert!(!CredentialSource::Ldap.allows_local_login());
+        assert!(!CredentialSource::Ldap.allows_password_reset());
+
+        // Local users who linked an upstream identity keep their password, but can't reset it
+        assert!(CredentialSource::Federated.allows_local_login());
+        assert!(!CredentialSource::Federated.allows_password_reset());
+    }
 }
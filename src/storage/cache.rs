@@ -1,7 +1,11 @@
-use crate::storage::{AuthorizeRequestData, Client};
+use crate::claims::ClaimRequest;
+use crate::storage::{
+    AuthCodeData, Authentication, AuthorizeRequestData, Client, FederationState,
+    PendingIdentityLink, RedeemedAuthCode, RefreshTokenData, ResourceServer, Scope,
+};
 use log::{debug, error, warn};
 use redis::{Client as RedisClient, Commands};
-use serde_json;
+use std::collections::HashMap;
 
 #[derive(Debug)]
 pub struct Cache {
@@ -44,6 +48,25 @@ impl Cache {
             });
     }
 
+    /// Removes a client from the cache after it changed, so it's read from the database again.
+    pub fn delete_client(&self, client_id: &str) {
+        let mut con = self.get_connection();
+
+        con.del(self.get_prefixed_key(&format!("CLIENT_{}_DATA", client_id)))
+            .unwrap_or_else(|err| {
+                error!(
+                    "Failed to delete client {} data from cache: {}",
+                    client_id, err
+                );
+            });
+        con.srem(self.get_prefixed_key("CLIENT_IDS"), client_id)
+            .unwrap_or_else(|err| {
+                error!("Failed to remove client ID from cache: {}", err);
+            });
+
+        debug!("Deleted client {} from cache", client_id);
+    }
+
     pub fn set_request(&self, request_id: &str, request_data: &AuthorizeRequestData) {
         let mut con = self.get_connection();
 
@@ -94,110 +117,1138 @@ impl Cache {
         }
     }
 
-    pub(super) fn set_client(self: &Self, client: &Client) {
+    pub fn delete_request(&self, request_id: &str) {
         let mut con = self.get_connection();
 
-        let client_json = serde_json::to_string(client).unwrap_or_else(|err| {
-            error!("Failed to serialize client data: {}", err);
+        con.del(&[
+            self.get_prefixed_key(&format!("REQUEST_ID_{}_REQUEST_DATA", request_id)),
+            self.get_prefixed_key(&format!("REQUEST_ID_{}_AUTHENTICATION", request_id)),
+            self.get_prefixed_key(&format!("REQUEST_ID_{}_PENDING_AUTHENTICATION", request_id)),
+            self.get_prefixed_key(&format!("REQUEST_ID_{}_PENDING_TOTP", request_id)),
+            self.get_prefixed_key(&format!("REQUEST_ID_{}_MFA_ATTEMPTS", request_id)),
+            self.get_prefixed_key(&format!("REQUEST_ID_{}_PENDING_LINK", request_id)),
+            self.get_prefixed_key(&format!("REQUEST_ID_{}_BROWSER", request_id)),
+        ])
+        .unwrap_or_else(|err| {
+            warn!("Failed to delete request data from cache: {}", err);
+        });
+
+        debug!("Deleted request data for request ID {}", request_id);
+    }
+
+    pub fn set_request_authentication(&self, request_id: &str, authentication: &Authentication) {
+        let mut con = self.get_connection();
+
+        let authentication_json = serde_json::to_string(authentication).unwrap_or_else(|err| {
+            error!("Failed to serialize authentication: {}", err);
             String::new()
         });
 
-        if !client_json.is_empty() {
-            warn!("Failed to save client {}", client.id);
+        if authentication_json.is_empty() {
+            return;
         }
 
         con.set_ex(
-            self.get_prefixed_key(&format!("CLIENT_{}_DATA", client.id)),
-            client_json,
+            self.get_prefixed_key(&format!("REQUEST_ID_{}_AUTHENTICATION", request_id)),
+            authentication_json,
             600,
         )
         .unwrap_or_else(|err| {
-            warn!(
-                "Failed to store client {} data in cache: {}",
-                client.id, err
-            );
+            error!("Failed to store authentication in cache: {}", err);
         });
 
-        debug!("Stored client {} data in cache", client.id);
+        debug!("Saved authentication for request ID {}", request_id);
     }
 
-    pub(super) fn get_client(self: &Self, client_id: &str) -> Option<Client> {
+    pub fn get_request_authentication(&self, request_id: &str) -> Option<Authentication> {
         let mut con = self.get_connection();
 
-        let client_data: Option<String> = con
-            .get(self.get_prefixed_key(&format!("CLIENT_{}_DATA", client_id)))
+        let authentication: Option<String> = con
+            .get(self.get_prefixed_key(&format!("REQUEST_ID_{}_AUTHENTICATION", request_id)))
             .unwrap_or_else(|err| {
-                warn!("Failed to retrieve client data from cache: {}", err);
+                warn!("Failed to retrieve authentication from cache: {}", err);
                 None
             });
 
-        if let Some(data) = client_data {
-            let client: Client = serde_json::from_str(&data).unwrap_or_else(|err| {
-                error!("Failed to deserialize client data: {}", err);
+        authentication.map(|data| {
+            serde_json::from_str(&data).unwrap_or_else(|err| {
+                error!("Failed to deserialize authentication: {}", err);
                 panic!("Corrupted cache data");
-            });
-
-            Some(client)
-        } else {
-            warn!("Failed to retrieve data for client ID {}", client_id);
-            None
-        }
+            })
+        })
     }
 
-    pub fn set_auth_code_for_user_and_scopes(
+    /// Stores an authentication that is still waiting for a second factor.
+    pub fn set_request_pending_authentication(
         &self,
-        client_id: &str,
-        code: &str,
-        user_id: &str,
-        scopes: &str,
+        request_id: &str,
+        authentication: &Authentication,
     ) {
         let mut con = self.get_connection();
 
+        let authentication_json = serde_json::to_string(authentication).unwrap_or_else(|err| {
+            error!("Failed to serialize authentication: {}", err);
+            String::new()
+        });
+
+        if authentication_json.is_empty() {
+            return;
+        }
+
+        con.set_ex(
+            self.get_prefixed_key(&format!("REQUEST_ID_{}_PENDING_AUTHENTICATION", request_id)),
+            authentication_json,
+            600,
+        )
+        .unwrap_or_else(|err| {
+            error!("Failed to store pending authentication in cache: {}", err);
+        });
+
+        debug!("Saved pending authentication for request ID {}", request_id);
+    }
+
+    pub fn get_request_pending_authentication(&self, request_id: &str) -> Option<Authentication> {
+        let mut con = self.get_connection();
+
+        let authentication: Option<String> = con
+            .get(
+                self.get_prefixed_key(&format!("REQUEST_ID_{}_PENDING_AUTHENTICATION", request_id)),
+            )
+            .unwrap_or_else(|err| {
+                warn!(
+                    "Failed to retrieve pending authentication from cache: {}",
+                    err
+                );
+                None
+            });
+
+        authentication.map(|data| {
+            serde_json::from_str(&data).unwrap_or_else(|err| {
+                error!("Failed to deserialize authentication: {}", err);
+                panic!("Corrupted cache data");
+            })
+        })
+    }
+
+    /// Stores a TOTP secret that is being enrolled until the user confirms it with a code.
+    pub fn set_request_browser(&self, request_id: &str, browser_id: &str) {
+        let mut con = self.get_connection();
+
         con.set_ex(
-            self.get_prefixed_key(&format!("AUTH_CLIENT_{}_CODE_{}_SCOPES", client_id, code)),
-            scopes.to_string(),
+            self.get_prefixed_key(&format!("REQUEST_ID_{}_BROWSER", request_id)),
+            browser_id,
             600,
         )
         .unwrap_or_else(|err| {
-            error!("Failed to store scopes in cache: {}", err);
+            error!("Failed to store request browser binding in cache: {}", err);
         });
+    }
+
+    pub fn get_request_browser(&self, request_id: &str) -> Option<String> {
+        let mut con = self.get_connection();
+
+        con.get(self.get_prefixed_key(&format!("REQUEST_ID_{}_BROWSER", request_id)))
+            .unwrap_or_else(|err| {
+                warn!(
+                    "Failed to retrieve request browser binding from cache: {}",
+                    err
+                );
+                None
+            })
+    }
+
+    pub fn set_request_pending_totp(&self, request_id: &str, secret: &str) {
+        let mut con = self.get_connection();
 
         con.set_ex(
-            self.get_prefixed_key(&format!("AUTH_CLIENT_{}_CODE_{}_USER_ID", client_id, code)),
-            user_id.to_string(),
+            self.get_prefixed_key(&format!("REQUEST_ID_{}_PENDING_TOTP", request_id)),
+            secret,
             600,
         )
         .unwrap_or_else(|err| {
-            error!("Failed to store user id in cache: {}", err);
+            error!("Failed to store pending TOTP secret in cache: {}", err);
         });
+    }
 
-        debug!(
-            "Saved auth client {} code {} user id and scopes",
-            client_id, code
-        );
+    pub fn get_request_pending_totp(&self, request_id: &str) -> Option<String> {
+        let mut con = self.get_connection();
+
+        con.get(self.get_prefixed_key(&format!("REQUEST_ID_{}_PENDING_TOTP", request_id)))
+            .unwrap_or_else(|err| {
+                warn!("Failed to retrieve pending TOTP secret from cache: {}", err);
+                None
+            })
     }
 
-    pub fn get_auth_code_and_scopes(
-        &self,
-        client_id: &str,
-        code: &str,
-    ) -> (Option<String>, Option<String>) {
+    /// Counts second factor attempts for a request and returns the number so far.
+    pub fn increment_mfa_attempts(&self, request_id: &str) -> u64 {
         let mut con = self.get_connection();
+        let key = self.get_prefixed_key(&format!("REQUEST_ID_{}_MFA_ATTEMPTS", request_id));
 
-        let user_id: Option<String> = con
-            .get(self.get_prefixed_key(&format!("AUTH_CLIENT_{}_CODE_{}_USER_ID", client_id, code)))
-            .unwrap_or_else(|_| {
-                warn!("Failed to retrieve user_id from cache: {}", code);
+        let attempts: u64 = con.incr(&key, 1).unwrap_or_else(|err| {
+            error!("Failed to count MFA attempts: {}", err);
+            u64::MAX
+        });
+
+        con.expire(&key, 600).unwrap_or_else(|err| {
+            warn!("Failed to set MFA attempts expiry: {}", err);
+        });
+
+        attempts
+    }
+
+    /// Marks a TOTP code as used by a user. Returns false if it was already used,
+    /// so a code can't be replayed while it is still valid.
+    pub fn use_totp_code(&self, user_id: &str, code: &str) -> bool {
+        let mut con = self.get_connection();
+
+        redis::cmd("SET")
+            .arg(self.get_prefixed_key(&format!("USER_{}_TOTP_{}_USED", user_id, code)))
+            .arg(1)
+            .arg("NX")
+            .arg("EX")
+            .arg(90)
+            .query::<Option<String>>(&mut con)
+            .unwrap_or_else(|err| {
+                error!("Failed to mark TOTP code as used: {}", err);
+                None
+            })
+            .is_some()
+    }
+
+    /// Stores the challenge of a WebAuthn ceremony for a user or an authorization request.
+    pub fn set_webauthn_challenge(&self, owner: &str, challenge: &str) {
+        let mut con = self.get_connection();
+
+        con.set_ex(
+            self.get_prefixed_key(&format!("WEBAUTHN_{}_CHALLENGE", owner)),
+            challenge,
+            300,
+        )
+        .unwrap_or_else(|err| {
+            error!("Failed to store WebAuthn challenge in cache: {}", err);
+        });
+    }
+
+    /// Removes and returns a WebAuthn challenge, so each one can only be used once.
+    pub fn take_webauthn_challenge(&self, owner: &str) -> Option<String> {
+        let mut con = self.get_connection();
+
+        con.get_del(self.get_prefixed_key(&format!("WEBAUTHN_{}_CHALLENGE", owner)))
+            .unwrap_or_else(|err| {
+                warn!("Failed to retrieve WebAuthn challenge from cache: {}", err);
+                None
+            })
+    }
+
+    /// Records a failed login attempt in a sliding window and returns the number of failures
+    /// within the window, including this one.
+    pub fn add_login_failure(&self, key: &str, now: u64, window: u64) -> u64 {
+        self.add_to_sliding_window(&format!("LOGIN_FAILURES_{}", key), now, window)
+            .unwrap_or_else(|err| {
+                error!("Failed to record login failure in cache: {}", err);
+                0
+            })
+    }
+
+    /// Records a rate limited action in a sliding window and returns the number of actions
+    /// within the window, including this one. Fails closed when the cache is unavailable.
+    pub fn add_rate_limited_action(&self, key: &str, now: u64, window: u64) -> u64 {
+        self.add_to_sliding_window(&format!("RATE_LIMIT_{}", key), now, window)
+            .unwrap_or_else(|err| {
+                error!("Failed to record rate limited action in cache: {}", err);
+                u64::MAX
+            })
+    }
+
+    fn add_to_sliding_window(&self, key: &str, now: u64, window: u64) -> redis::RedisResult<u64> {
+        let mut con = self.get_connection();
+        let window_key = self.get_prefixed_key(key);
+
+        // Members must be unique, attempts in the same second are told apart by a random suffix
+        let member = format!("{}-{}", now, rand::random::<u32>());
+
+        redis::pipe()
+            .atomic()
+            .zrembyscore(&window_key, "-inf", now.saturating_sub(window))
+            .ignore()
+            .zadd(&window_key, member, now)
+            .ignore()
+            .expire(&window_key, window as i64)
+            .ignore()
+            .zcard(&window_key)
+            .query::<(u64,)>(&mut con)
+            .map(|(count,)| count)
+    }
+
+    /// Gets the number of failed login attempts within the sliding window and the time of the
+    /// most recent one.
+    pub fn get_login_failures(&self, key: &str, now: u64, window: u64) -> (u64, u64) {
+        let mut con = self.get_connection();
+        let failures_key = self.get_prefixed_key(&format!("LOGIN_FAILURES_{}", key));
+
+        redis::pipe()
+            .atomic()
+            .zrembyscore(&failures_key, "-inf", now.saturating_sub(window))
+            .ignore()
+            .zcard(&failures_key)
+            .zrange_withscores(&failures_key, -1, -1)
+            .query::<(u64, Vec<(String, f64)>)>(&mut con)
+            .map(|(count, latest)| (count, latest.first().map_or(0, |(_, time)| *time as u64)))
+            .unwrap_or_else(|err| {
+                error!("Failed to retrieve login failures from cache: {}", err);
+                (0, 0)
+            })
+    }
+
+    pub fn clear_login_failures(&self, key: &str) {
+        let mut con = self.get_connection();
+
+        con.del(self.get_prefixed_key(&format!("LOGIN_FAILURES_{}", key)))
+            .unwrap_or_else(|err| {
+                error!("Failed to clear login failures in cache: {}", err);
+            });
+    }
+
+    pub fn set_lockout(&self, key: &str, ttl: u64) {
+        let mut con = self.get_connection();
+
+        con.set_ex(self.get_prefixed_key(&format!("LOCKOUT_{}", key)), 1, ttl)
+            .unwrap_or_else(|err| {
+                error!("Failed to store lockout in cache: {}", err);
+            });
+    }
+
+    pub fn is_locked_out(&self, key: &str) -> bool {
+        let mut con = self.get_connection();
+
+        con.exists(self.get_prefixed_key(&format!("LOCKOUT_{}", key)))
+            .unwrap_or_else(|err| {
+                error!("Failed to check lockout in cache: {}", err);
+                false
+            })
+    }
+
+    /// Removes a lockout and returns whether there was one.
+    pub fn clear_lockout(&self, key: &str) -> bool {
+        let mut con = self.get_connection();
+
+        con.del::<_, u64>(self.get_prefixed_key(&format!("LOCKOUT_{}", key)))
+            .unwrap_or_else(|err| {
+                error!("Failed to clear lockout in cache: {}", err);
+                0
+            })
+            > 0
+    }
+
+    /// Stores a browser SSO session under the key in its cookie.
+    pub fn set_session(&self, session_key: &str, authentication: &Authentication, ttl: u64) {
+        let mut con = self.get_connection();
+
+        let session_json = serde_json::to_string(authentication).unwrap_or_else(|err| {
+            error!("Failed to serialize session: {}", err);
+            String::new()
+        });
+
+        if session_json.is_empty() {
+            return;
+        }
+
+        con.set_ex(
+            self.get_prefixed_key(&format!("SESSION_{}_DATA", session_key)),
+            session_json,
+            ttl,
+        )
+        .unwrap_or_else(|err| {
+            error!("Failed to store session in cache: {}", err);
+        });
+
+        // Track the sessions of each user so they can all be ended at once
+        con.sadd(
+            self.get_prefixed_key(&format!("USER_{}_SESSIONS", authentication.user_id)),
+            session_key,
+        )
+        .unwrap_or_else(|err| {
+            error!("Failed to track session for user: {}", err);
+        });
+
+        debug!("Saved session for user {}", authentication.user_id);
+    }
+
+    pub fn get_session(&self, session_key: &str) -> Option<Authentication> {
+        let mut con = self.get_connection();
+
+        let session: Option<String> = con
+            .get(self.get_prefixed_key(&format!("SESSION_{}_DATA", session_key)))
+            .unwrap_or_else(|err| {
+                warn!("Failed to retrieve session from cache: {}", err);
                 None
             });
 
-        let scopes: Option<String> = con
-            .get(self.get_prefixed_key(&format!("AUTH_CLIENT_{}_CODE_{}_SCOPES", client_id, code)))
-            .unwrap_or_else(|_| {
-                warn!("Failed to retrieve scope from cache: {}", code);
+        session.map(|data| {
+            serde_json::from_str(&data).unwrap_or_else(|err| {
+                error!("Failed to deserialize session: {}", err);
+                panic!("Corrupted cache data");
+            })
+        })
+    }
+
+    pub fn set_federation_state(&self, state: &str, federation_state: &FederationState) {
+        let mut con = self.get_connection();
+
+        let state_json = serde_json::to_string(federation_state).unwrap_or_else(|err| {
+            error!("Failed to serialize federation state: {}", err);
+            String::new()
+        });
+
+        if state_json.is_empty() {
+            return;
+        }
+
+        con.set_ex(
+            self.get_prefixed_key(&format!("FEDERATION_{}_STATE", state)),
+            state_json,
+            600,
+        )
+        .unwrap_or_else(|err| {
+            error!("Failed to store federation state in cache: {}", err);
+        });
+    }
+
+    /// Removes the state of an upstream login and returns it, so a callback can't be replayed.
+    pub fn take_federation_state(&self, state: &str) -> Option<FederationState> {
+        let mut con = self.get_connection();
+
+        let state_json: Option<String> = con
+            .get_del(self.get_prefixed_key(&format!("FEDERATION_{}_STATE", state)))
+            .unwrap_or_else(|err| {
+                warn!("Failed to retrieve federation state from cache: {}", err);
+                None
+            });
+
+        state_json.map(|data| {
+            serde_json::from_str(&data).unwrap_or_else(|err| {
+                error!("Failed to deserialize federation state: {}", err);
+                panic!("Corrupted cache data");
+            })
+        })
+    }
+
+    pub fn set_request_pending_link(&self, request_id: &str, link: &PendingIdentityLink) {
+        let mut con = self.get_connection();
+
+        let link_json = serde_json::to_string(link).unwrap_or_else(|err| {
+            error!("Failed to serialize identity link: {}", err);
+            String::new()
+        });
+
+        if link_json.is_empty() {
+            return;
+        }
+
+        con.set_ex(
+            self.get_prefixed_key(&format!("REQUEST_ID_{}_PENDING_LINK", request_id)),
+            link_json,
+            600,
+        )
+        .unwrap_or_else(|err| {
+            error!("Failed to store identity link in cache: {}", err);
+        });
+    }
+
+    /// Removes the identity link waiting for confirmation and returns it, so it is used once.
+    pub fn take_request_pending_link(&self, request_id: &str) -> Option<PendingIdentityLink> {
+        let mut con = self.get_connection();
+
+        let link_json: Option<String> = con
+            .get_del(self.get_prefixed_key(&format!("REQUEST_ID_{}_PENDING_LINK", request_id)))
+            .unwrap_or_else(|err| {
+                warn!("Failed to retrieve identity link from cache: {}", err);
                 None
             });
 
-        (user_id, scopes)
+        link_json.map(|data| {
+            serde_json::from_str(&data).unwrap_or_else(|err| {
+                error!("Failed to deserialize identity link: {}", err);
+                panic!("Corrupted cache data");
+            })
+        })
+    }
+
+    /// Records that a client was signed in through a browser SSO session.
+    pub fn add_session_client(&self, session_id: &str, client_id: &str, ttl: u64) {
+        let mut con = self.get_connection();
+        let clients_key = self.get_prefixed_key(&format!("SESSION_{}_CLIENTS", session_id));
+
+        con.sadd(&clients_key, client_id).unwrap_or_else(|err| {
+            error!("Failed to track client for session: {}", err);
+        });
+        con.expire(&clients_key, ttl as i64).unwrap_or_else(|err| {
+            error!("Failed to set expiry of session clients: {}", err);
+        });
+    }
+
+    /// Ends a browser SSO session, by the key in its cookie, and returns the IDs of the clients
+    /// signed in through it.
+    pub fn end_session(&self, session_key: &str, session: &Authentication) -> Vec<String> {
+        let mut con = self.get_connection();
+        let mut keys = vec![self.get_prefixed_key(&format!("SESSION_{}_DATA", session_key))];
+
+        let client_ids: Vec<String> = match &session.session_id {
+            Some(session_id) => {
+                let clients_key = self.get_prefixed_key(&format!("SESSION_{}_CLIENTS", session_id));
+                let client_ids = con.smembers(&clients_key).unwrap_or_else(|err| {
+                    error!("Failed to retrieve clients of session from cache: {}", err);
+                    Vec::new()
+                });
+                keys.push(clients_key);
+                client_ids
+            }
+            None => Vec::new(),
+        };
+
+        con.del(keys).unwrap_or_else(|err| {
+            error!("Failed to delete session from cache: {}", err);
+        });
+        con.srem(
+            self.get_prefixed_key(&format!("USER_{}_SESSIONS", session.user_id)),
+            session_key,
+        )
+        .unwrap_or_else(|err| {
+            warn!("Failed to untrack session for user: {}", err);
+        });
+
+        debug!("Ended session of user {}", session.user_id);
+        client_ids
+    }
+
+    /// Ends all browser SSO sessions of a user.
+    pub fn delete_user_sessions(&self, user_id: &str) {
+        let mut con = self.get_connection();
+        let sessions_key = self.get_prefixed_key(&format!("USER_{}_SESSIONS", user_id));
+
+        let session_keys: Vec<String> = con.smembers(&sessions_key).unwrap_or_else(|err| {
+            error!("Failed to retrieve sessions of user from cache: {}", err);
+            Vec::new()
+        });
+
+        let mut keys = Vec::new();
+        for session_key in &session_keys {
+            // The clients are tracked under the public ID of the session
+            if let Some(session_id) = self
+                .get_session(session_key)
+                .and_then(|session| session.session_id)
+            {
+                keys.push(self.get_prefixed_key(&format!("SESSION_{}_CLIENTS", session_id)));
+            }
+            keys.push(self.get_prefixed_key(&format!("SESSION_{}_DATA", session_key)));
+        }
+        keys.push(sessions_key);
+
+        con.del(keys).unwrap_or_else(|err| {
+            error!("Failed to delete sessions of user from cache: {}", err);
+        });
+
+        debug!(
+            "Deleted {} sessions of user {}",
+            session_keys.len(),
+            user_id
+        );
+    }
+
+    /// Stores the claims individually requested from the userinfo endpoint with an access token.
+    pub fn set_userinfo_claims(
+        &self,
+        jti: &str,
+        claims: &HashMap<String, Option<ClaimRequest>>,
+        ttl: u64,
+    ) {
+        let mut con = self.get_connection();
+
+        let claims_json = serde_json::to_string(claims).unwrap_or_else(|err| {
+            error!("Failed to serialize userinfo claims: {}", err);
+            String::new()
+        });
+
+        if claims_json.is_empty() {
+            return;
+        }
+
+        con.set_ex(
+            self.get_prefixed_key(&format!("ACCESS_TOKEN_{}_CLAIMS", jti)),
+            claims_json,
+            ttl,
+        )
+        .unwrap_or_else(|err| {
+            error!("Failed to store userinfo claims in cache: {}", err);
+        });
+    }
+
+    pub fn get_userinfo_claims(&self, jti: &str) -> Option<HashMap<String, Option<ClaimRequest>>> {
+        let mut con = self.get_connection();
+
+        let claims_json: Option<String> = con
+            .get(self.get_prefixed_key(&format!("ACCESS_TOKEN_{}_CLAIMS", jti)))
+            .unwrap_or_else(|err| {
+                warn!("Failed to retrieve userinfo claims from cache: {}", err);
+                None
+            });
+
+        claims_json.map(|data| {
+            serde_json::from_str(&data).unwrap_or_else(|err| {
+                error!("Failed to deserialize userinfo claims: {}", err);
+                panic!("Corrupted cache data");
+            })
+        })
+    }
+
+    pub fn set_refresh_token(&self, token: &str, token_data: &RefreshTokenData, ttl: u64) {
+        let mut con = self.get_connection();
+
+        let token_json = serde_json::to_string(token_data).unwrap_or_else(|err| {
+            error!("Failed to serialize refresh token data: {}", err);
+            String::new()
+        });
+
+        if token_json.is_empty() {
+            return;
+        }
+
+        con.set_ex(
+            self.get_prefixed_key(&format!("REFRESH_TOKEN_{}_DATA", token)),
+            token_json,
+            ttl,
+        )
+        .unwrap_or_else(|err| {
+            error!("Failed to store refresh token in cache: {}", err);
+        });
+
+        // Track the refresh tokens of each user so they can all be revoked at once
+        con.sadd(
+            self.get_prefixed_key(&format!("USER_{}_REFRESH_TOKENS", token_data.user_id)),
+            token,
+        )
+        .unwrap_or_else(|err| {
+            error!("Failed to track refresh token for user: {}", err);
+        });
+
+        debug!("Saved refresh token for user {}", token_data.user_id);
+    }
+
+    /// Removes a refresh token and returns its grant, so every refresh token is used once.
+    pub fn take_refresh_token(&self, token: &str) -> Option<RefreshTokenData> {
+        let mut con = self.get_connection();
+
+        let token_json: Option<String> = con
+            .get_del(self.get_prefixed_key(&format!("REFRESH_TOKEN_{}_DATA", token)))
+            .unwrap_or_else(|err| {
+                warn!("Failed to retrieve refresh token from cache: {}", err);
+                None
+            });
+
+        let token_data: RefreshTokenData =
+            serde_json::from_str(&token_json?).unwrap_or_else(|err| {
+                error!("Failed to deserialize refresh token data: {}", err);
+                panic!("Corrupted cache data");
+            });
+
+        con.srem(
+            self.get_prefixed_key(&format!("USER_{}_REFRESH_TOKENS", token_data.user_id)),
+            token,
+        )
+        .unwrap_or_else(|err| {
+            warn!("Failed to untrack refresh token for user: {}", err);
+        });
+
+        Some(token_data)
+    }
+
+    /// Gets the active refresh token grants of a user, with the seconds until they expire.
+    pub fn get_user_refresh_tokens(&self, user_id: &str) -> Vec<(RefreshTokenData, i64)> {
+        let mut con = self.get_connection();
+
+        let tokens: Vec<String> = con
+            .smembers(self.get_prefixed_key(&format!("USER_{}_REFRESH_TOKENS", user_id)))
+            .unwrap_or_else(|err| {
+                error!(
+                    "Failed to retrieve refresh tokens of user from cache: {}",
+                    err
+                );
+                Vec::new()
+            });
+
+        let mut grants = Vec::new();
+        for token in tokens {
+            let token_key = self.get_prefixed_key(&format!("REFRESH_TOKEN_{}_DATA", token));
+            let token_json: Option<String> = con.get(&token_key).unwrap_or_else(|err| {
+                warn!("Failed to retrieve refresh token from cache: {}", err);
+                None
+            });
+
+            // Expired tokens are only left in the set
+            let token_data: RefreshTokenData = match token_json {
+                Some(data) => serde_json::from_str(&data).unwrap_or_else(|err| {
+                    error!("Failed to deserialize refresh token data: {}", err);
+                    panic!("Corrupted cache data");
+                }),
+                None => continue,
+            };
+            let expires_in: i64 = con.ttl(&token_key).unwrap_or_else(|err| {
+                warn!("Failed to retrieve refresh token TTL from cache: {}", err);
+                0
+            });
+
+            grants.push((token_data, expires_in));
+        }
+
+        grants
+    }
+
+    /// Revokes the refresh tokens of a user that were issued in a browser SSO session.
+    pub fn revoke_session_refresh_tokens(&self, user_id: &str, session_id: &str) {
+        self.revoke_matching_refresh_tokens(user_id, |data| {
+            data.session_id.as_deref() == Some(session_id)
+        });
+
+        debug!("Revoked refresh tokens of session of user {}", user_id);
+    }
+
+    /// Revokes the refresh tokens of a user that were rotated from the same grant.
+    pub fn revoke_grant_refresh_tokens(&self, user_id: &str, grant_id: &str) {
+        self.revoke_matching_refresh_tokens(user_id, |data| {
+            data.grant_id.as_deref() == Some(grant_id)
+        });
+
+        debug!("Revoked refresh tokens of grant of user {}", user_id);
+    }
+
+    /// Revokes the refresh tokens of a user that were issued to a client.
+    pub fn revoke_client_refresh_tokens(&self, user_id: &str, client_id: &str) {
+        self.revoke_matching_refresh_tokens(user_id, |data| data.client_id == client_id);
+
+        debug!(
+            "Revoked refresh tokens of client {} of user {}",
+            client_id, user_id
+        );
+    }
+
+    // Revokes the refresh tokens of a user whose grant matches, and untracks expired ones
+    fn revoke_matching_refresh_tokens(
+        &self,
+        user_id: &str,
+        matches: impl Fn(&RefreshTokenData) -> bool,
+    ) {
+        let mut con = self.get_connection();
+        let tokens_key = self.get_prefixed_key(&format!("USER_{}_REFRESH_TOKENS", user_id));
+
+        let tokens: Vec<String> = con.smembers(&tokens_key).unwrap_or_else(|err| {
+            error!(
+                "Failed to retrieve refresh tokens of user from cache: {}",
+                err
+            );
+            Vec::new()
+        });
+
+        for token in tokens {
+            let token_key = self.get_prefixed_key(&format!("REFRESH_TOKEN_{}_DATA", token));
+            let token_json: Option<String> = con.get(&token_key).unwrap_or_else(|err| {
+                warn!("Failed to retrieve refresh token from cache: {}", err);
+                None
+            });
+
+            // Expired tokens are only left in the set
+            let token_data: Option<RefreshTokenData> = token_json.map(|data| {
+                serde_json::from_str(&data).unwrap_or_else(|err| {
+                    error!("Failed to deserialize refresh token data: {}", err);
+                    panic!("Corrupted cache data");
+                })
+            });
+            if token_data.is_some_and(|data| !matches(&data)) {
+                continue;
+            }
+
+            con.del(&token_key).unwrap_or_else(|err| {
+                error!("Failed to revoke refresh token in cache: {}", err);
+            });
+            con.srem(&tokens_key, &token).unwrap_or_else(|err| {
+                warn!("Failed to untrack refresh token for user: {}", err);
+            });
+        }
+    }
+
+    /// Revokes all refresh tokens of a user. Refresh tokens are stored as
+    /// `REFRESH_TOKEN_{token}_DATA` and tracked in the `USER_{id}_REFRESH_TOKENS` set.
+    pub fn revoke_user_refresh_tokens(&self, user_id: &str) {
+        let mut con = self.get_connection();
+        let tokens_key = self.get_prefixed_key(&format!("USER_{}_REFRESH_TOKENS", user_id));
+
+        let tokens: Vec<String> = con.smembers(&tokens_key).unwrap_or_else(|err| {
+            error!(
+                "Failed to retrieve refresh tokens of user from cache: {}",
+                err
+            );
+            Vec::new()
+        });
+
+        let mut keys = tokens
+            .iter()
+            .map(|token| self.get_prefixed_key(&format!("REFRESH_TOKEN_{}_DATA", token)))
+            .collect::<Vec<String>>();
+        keys.push(tokens_key);
+
+        con.del(keys).unwrap_or_else(|err| {
+            error!("Failed to revoke refresh tokens of user in cache: {}", err);
+        });
+
+        debug!(
+            "Revoked {} refresh tokens of user {}",
+            tokens.len(),
+            user_id
+        );
+    }
+
+    /// Stores a password reset token, by its hash, for the user it resets. The user's tokens
+    /// are tracked so they can all be invalidated at once.
+    pub fn set_password_reset(&self, token_hash: &str, user_id: u32, ttl: u64) {
+        let mut con = self.get_connection();
+        let user_resets_key = self.get_prefixed_key(&format!("USER_{}_PASSWORD_RESETS", user_id));
+
+        redis::pipe()
+            .atomic()
+            .set_ex(
+                self.get_prefixed_key(&format!("PASSWORD_RESET_{}", token_hash)),
+                user_id,
+                ttl,
+            )
+            .ignore()
+            .sadd(&user_resets_key, token_hash)
+            .ignore()
+            .expire(&user_resets_key, ttl as i64)
+            .ignore()
+            .query::<()>(&mut con)
+            .unwrap_or_else(|err| {
+                error!("Failed to store password reset token in cache: {}", err);
+            });
+    }
+
+    /// Removes every outstanding password reset token of a user.
+    pub fn delete_user_password_resets(&self, user_id: u32) {
+        let mut con = self.get_connection();
+        let user_resets_key = self.get_prefixed_key(&format!("USER_{}_PASSWORD_RESETS", user_id));
+
+        let token_hashes: Vec<String> = con.smembers(&user_resets_key).unwrap_or_else(|err| {
+            warn!(
+                "Failed to retrieve password reset tokens from cache: {}",
+                err
+            );
+            Vec::new()
+        });
+
+        let mut keys = token_hashes
+            .iter()
+            .map(|token_hash| self.get_prefixed_key(&format!("PASSWORD_RESET_{}", token_hash)))
+            .collect::<Vec<String>>();
+        keys.push(user_resets_key);
+
+        con.del(&keys).unwrap_or_else(|err| {
+            error!("Failed to delete password reset tokens from cache: {}", err);
+        });
+
+        debug!(
+            "Deleted {} password reset tokens of user {}",
+            token_hashes.len(),
+            user_id
+        );
+    }
+
+    pub fn get_password_reset(&self, token_hash: &str) -> Option<u32> {
+        let mut con = self.get_connection();
+
+        con.get(self.get_prefixed_key(&format!("PASSWORD_RESET_{}", token_hash)))
+            .unwrap_or_else(|err| {
+                warn!(
+                    "Failed to retrieve password reset token from cache: {}",
+                    err
+                );
+                None
+            })
+    }
+
+    /// Removes a password reset token and returns its user, so it can only be used once.
+    pub fn take_password_reset(&self, token_hash: &str) -> Option<u32> {
+        let mut con = self.get_connection();
+
+        con.get_del(self.get_prefixed_key(&format!("PASSWORD_RESET_{}", token_hash)))
+            .unwrap_or_else(|err| {
+                warn!(
+                    "Failed to retrieve password reset token from cache: {}",
+                    err
+                );
+                None
+            })
+    }
+
+    /// Returns whether a password reset email may be sent to the user, at most one per minute.
+    pub fn allow_password_reset_email(&self, user_id: u32) -> bool {
+        let mut con = self.get_connection();
+
+        redis::cmd("SET")
+            .arg(self.get_prefixed_key(&format!("USER_{}_PASSWORD_RESET_SENT", user_id)))
+            .arg(1)
+            .arg("NX")
+            .arg("EX")
+            .arg(60)
+            .query::<Option<String>>(&mut con)
+            .unwrap_or_else(|err| {
+                error!("Failed to throttle password reset emails: {}", err);
+                None
+            })
+            .is_some()
+    }
+
+    pub(super) fn set_client(&self, client: &Client) {
+        let mut con = self.get_connection();
+
+        let client_json = serde_json::to_string(client).unwrap_or_else(|err| {
+            error!("Failed to serialize client data: {}", err);
+            String::new()
+        });
+
+        if !client_json.is_empty() {
+            warn!("Failed to save client {}", client.id);
+        }
+
+        con.set_ex(
+            self.get_prefixed_key(&format!("CLIENT_{}_DATA", client.id)),
+            client_json,
+            600,
+        )
+        .unwrap_or_else(|err| {
+            warn!(
+                "Failed to store client {} data in cache: {}",
+                client.id, err
+            );
+        });
+
+        debug!("Stored client {} data in cache", client.id);
+    }
+
+    pub(super) fn get_client(&self, client_id: &str) -> Option<Client> {
+        let mut con = self.get_connection();
+
+        let client_data: Option<String> = con
+            .get(self.get_prefixed_key(&format!("CLIENT_{}_DATA", client_id)))
+            .unwrap_or_else(|err| {
+                warn!("Failed to retrieve client data from cache: {}", err);
+                None
+            });
+
+        if let Some(data) = client_data {
+            let client: Client = serde_json::from_str(&data).unwrap_or_else(|err| {
+                error!("Failed to deserialize client data: {}", err);
+                panic!("Corrupted cache data");
+            });
+
+            Some(client)
+        } else {
+            warn!("Failed to retrieve data for client ID {}", client_id);
+            None
+        }
+    }
+
+    pub(super) fn set_resource_server(&self, resource_server: &ResourceServer) {
+        let mut con = self.get_connection();
+
+        let resource_server_json = serde_json::to_string(resource_server).unwrap_or_else(|err| {
+            error!("Failed to serialize resource server data: {}", err);
+            String::new()
+        });
+
+        if resource_server_json.is_empty() {
+            return;
+        }
+
+        con.set_ex(
+            self.get_prefixed_key(&format!("RESOURCE_{}_DATA", resource_server.uri)),
+            resource_server_json,
+            600,
+        )
+        .unwrap_or_else(|err| {
+            warn!(
+                "Failed to store resource server {} data in cache: {}",
+                resource_server.uri, err
+            );
+        });
+
+        debug!(
+            "Stored resource server {} data in cache",
+            resource_server.uri
+        );
+    }
+
+    pub(super) fn get_resource_server(&self, uri: &str) -> Option<ResourceServer> {
+        let mut con = self.get_connection();
+
+        let resource_server_data: Option<String> = con
+            .get(self.get_prefixed_key(&format!("RESOURCE_{}_DATA", uri)))
+            .unwrap_or_else(|err| {
+                warn!(
+                    "Failed to retrieve resource server data from cache: {}",
+                    err
+                );
+                None
+            });
+
+        if let Some(data) = resource_server_data {
+            let resource_server: ResourceServer =
+                serde_json::from_str(&data).unwrap_or_else(|err| {
+                    error!("Failed to deserialize resource server data: {}", err);
+                    panic!("Corrupted cache data");
+                });
+
+            Some(resource_server)
+        } else {
+            debug!("No cached data for resource server {}", uri);
+            None
+        }
+    }
+
+    pub(super) fn set_scope(&self, scope: &Scope) {
+        let mut con = self.get_connection();
+
+        let scope_json = serde_json::to_string(scope).unwrap_or_else(|err| {
+            error!("Failed to serialize scope data: {}", err);
+            String::new()
+        });
+
+        if scope_json.is_empty() {
+            return;
+        }
+
+        con.set_ex(
+            self.get_prefixed_key(&format!("SCOPE_{}_DATA", scope.name)),
+            scope_json,
+            600,
+        )
+        .unwrap_or_else(|err| {
+            warn!(
+                "Failed to store scope {} data in cache: {}",
+                scope.name, err
+            );
+        });
+
+        debug!("Stored scope {} data in cache", scope.name);
+    }
+
+    pub(super) fn get_scope(&self, name: &str) -> Option<Scope> {
+        let mut con = self.get_connection();
+
+        let scope_data: Option<String> = con
+            .get(self.get_prefixed_key(&format!("SCOPE_{}_DATA", name)))
+            .unwrap_or_else(|err| {
+                warn!("Failed to retrieve scope data from cache: {}", err);
+                None
+            });
+
+        if let Some(data) = scope_data {
+            let scope: Scope = serde_json::from_str(&data).unwrap_or_else(|err| {
+                error!("Failed to deserialize scope data: {}", err);
+                panic!("Corrupted cache data");
+            });
+
+            Some(scope)
+        } else {
+            debug!("No cached data for scope {}", name);
+            None
+        }
+    }
+
+    pub fn set_auth_code(&self, client_id: &str, code: &str, code_data: &AuthCodeData) {
+        let mut con = self.get_connection();
+
+        let code_data_json = serde_json::to_string(code_data).unwrap_or_else(|err| {
+            error!("Failed to serialize auth code data: {}", err);
+            String::new()
+        });
+
+        if code_data_json.is_empty() {
+            return;
+        }
+
+        con.set_ex(
+            self.get_prefixed_key(&format!("AUTH_CLIENT_{}_CODE_{}_DATA", client_id, code)),
+            code_data_json,
+            600,
+        )
+        .unwrap_or_else(|err| {
+            error!("Failed to store auth code data in cache: {}", err);
+        });
+
+        debug!("Saved auth client {} code {} data", client_id, code);
+    }
+
+    /// Removes an auth code and returns its data, so every code is redeemed once.
+    pub fn take_auth_code(&self, client_id: &str, code: &str) -> Option<AuthCodeData> {
+        let mut con = self.get_connection();
+
+        let code_data: Option<String> = con
+            .get_del(
+                self.get_prefixed_key(&format!("AUTH_CLIENT_{}_CODE_{}_DATA", client_id, code)),
+            )
+            .unwrap_or_else(|_| {
+                warn!("Failed to retrieve auth code data from cache: {}", code);
+                None
+            });
+
+        code_data.map(|data| {
+            serde_json::from_str(&data).unwrap_or_else(|err| {
+                error!("Failed to deserialize auth code data: {}", err);
+                panic!("Corrupted cache data");
+            })
+        })
+    }
+
+    /// Remembers the grant a code was redeemed for, for as long as the code would have been
+    /// valid.
+    pub fn set_redeemed_auth_code(&self, client_id: &str, code: &str, redeemed: &RedeemedAuthCode) {
+        let mut con = self.get_connection();
+
+        let redeemed_json = serde_json::to_string(redeemed).unwrap_or_else(|err| {
+            error!("Failed to serialize redeemed auth code: {}", err);
+            String::new()
+        });
+
+        if redeemed_json.is_empty() {
+            return;
+        }
+
+        con.set_ex(
+            self.get_prefixed_key(&format!("AUTH_CLIENT_{}_CODE_{}_REDEEMED", client_id, code)),
+            redeemed_json,
+            600,
+        )
+        .unwrap_or_else(|err| {
+            error!("Failed to store redeemed auth code in cache: {}", err);
+        });
+    }
+
+    pub fn get_redeemed_auth_code(&self, client_id: &str, code: &str) -> Option<RedeemedAuthCode> {
+        let mut con = self.get_connection();
+
+        let redeemed: Option<String> = con
+            .get(
+                self.get_prefixed_key(&format!("AUTH_CLIENT_{}_CODE_{}_REDEEMED", client_id, code)),
+            )
+            .unwrap_or_else(|_| {
+                warn!("Failed to retrieve redeemed auth code from cache: {}", code);
+                None
+            });
+
+        redeemed.map(|data| {
+            serde_json::from_str(&data).unwrap_or_else(|err| {
+                error!("Failed to deserialize redeemed auth code: {}", err);
+                panic!("Corrupted cache data");
+            })
+        })
     }
 }
@@ -1,6 +1,55 @@
-use crate::storage::{Client, User};
+use crate::storage::{
+    ClaimMapping, Client, Consent, CredentialSource, IdentityProvider, ResourceServer, Scope, User,
+    UserUpdate, WebauthnCredential,
+};
 use log::error;
-use tokio_postgres::Client as PgClient;
+use serde_json::{Map, Value};
+use tokio_postgres::{Client as PgClient, Row};
+
+// The columns read by `client_from_row`
+const CLIENT_COLUMNS: &str = "id, name, allowed_scopes, redirect_uris, secret, mfa_required, \
+     authenticators, default_scopes, post_logout_redirect_uris, frontchannel_logout_uri, \
+     backchannel_logout_uri, enabled, grant_types";
+
+fn client_from_row(row: &Row) -> Client {
+    Client {
+        id: row.get(0),
+        name: row.get(1),
+        allowed_scopes: row.get(2),
+        redirect_uris: row.get(3),
+        secret: row.get(4),
+        mfa_required: row.get(5),
+        authenticators: row.get(6),
+        default_scopes: row.get(7),
+        post_logout_redirect_uris: row.get(8),
+        frontchannel_logout_uri: row.get(9),
+        backchannel_logout_uri: row.get(10),
+        claim_mappings: Vec::new(),
+        enabled: row.get(11),
+        grant_types: row.get(12),
+    }
+}
+
+// The columns read by `user_from_row`
+const USER_COLUMNS: &str = "id, email, password_hash, groups, roles, entitlements, mfa_required, \
+     email_verified, enabled, name, phone_number, phone_number_verified";
+
+fn user_from_row(row: &Row) -> User {
+    User {
+        id: row.get(0),
+        email: row.get(1),
+        password_hash: row.get(2),
+        groups: row.get(3),
+        roles: row.get(4),
+        entitlements: row.get(5),
+        mfa_required: row.get(6),
+        email_verified: row.get(7),
+        enabled: row.get(8),
+        name: row.get(9),
+        phone_number: row.get(10),
+        phone_number_verified: row.get(11),
+    }
+}
 
 #[derive(Debug)]
 pub struct Database {
@@ -12,11 +61,12 @@ impl Database {
         Self { client }
     }
 
-    pub async fn find_client(self: &Self, client_id: &u32) -> bool {
+    /// Whether an enabled client with the ID exists.
+    pub async fn find_client(&self, client_id: &u32) -> bool {
         let query = self
             .client
             .query(
-                "SELECT id FROM clients WHERE id = $1::OID LIMIT 1;",
+                "SELECT id FROM clients WHERE id = $1::OID AND enabled LIMIT 1;",
                 &[client_id],
             )
             .await;
@@ -29,9 +79,17 @@ impl Database {
         !query.unwrap().is_empty()
     }
 
-    pub async fn get_client(self: &Self, client_id: &u32) -> Option<Client> {
-        let query = self.client.query(
-            "SELECT id, name, allowed_scopes, redirect_uris, secret FROM public.clients WHERE id = $1::OID LIMIT 1;", &[client_id]).await;
+    pub async fn get_client(&self, client_id: &u32) -> Option<Client> {
+        let query = self
+            .client
+            .query(
+                &format!(
+                    "SELECT {} FROM public.clients WHERE id = $1::OID LIMIT 1;",
+                    CLIENT_COLUMNS
+                ),
+                &[client_id],
+            )
+            .await;
 
         if query.is_err() {
             error!("{}", query.err().unwrap());
@@ -39,22 +97,256 @@ impl Database {
         }
 
         if let Some(row) = query.unwrap().into_iter().next() {
-            return Option::from(Client {
-                id: row.get(0),
+            let mut client = client_from_row(&row);
+            client.claim_mappings = self.get_claim_mappings(client_id).await;
+            return Option::from(client);
+        }
+
+        None
+    }
+
+    /// Gets a page of the clients whose name contains the search term, or whose ID it is,
+    /// without their claim mappings.
+    pub async fn get_clients(&self, search: &str, limit: i64, offset: i64) -> Vec<Client> {
+        let query = self
+            .client
+            .query(
+                &format!(
+                    "SELECT {} FROM public.clients \
+                     WHERE name ILIKE '%' || $1::VARCHAR || '%' OR id::TEXT = $1::VARCHAR \
+                     ORDER BY id LIMIT $2::BIGINT OFFSET $3::BIGINT;",
+                    CLIENT_COLUMNS
+                ),
+                &[&search, &limit, &offset],
+            )
+            .await;
+
+        if query.is_err() {
+            error!("{}", query.err().unwrap());
+            return Vec::new();
+        }
+
+        query.unwrap().iter().map(client_from_row).collect()
+    }
+
+    pub async fn count_clients(&self, search: &str) -> i64 {
+        let query = self
+            .client
+            .query(
+                "SELECT COUNT(*) FROM public.clients \
+                 WHERE name ILIKE '%' || $1::VARCHAR || '%' OR id::TEXT = $1::VARCHAR;",
+                &[&search],
+            )
+            .await;
+
+        if query.is_err() {
+            error!("{}", query.err().unwrap());
+            return 0;
+        }
+
+        match query.unwrap().into_iter().next() {
+            Some(row) => row.get(0),
+            None => 0,
+        }
+    }
+
+    /// Creates a client and returns its generated ID.
+    pub async fn create_client(&self, client: &Client) -> Option<u32> {
+        let query = self
+            .client
+            .query(
+                "INSERT INTO public.clients (name, allowed_scopes, redirect_uris, secret, mfa_required, \
+                 authenticators, default_scopes, post_logout_redirect_uris, frontchannel_logout_uri, \
+                 backchannel_logout_uri, enabled, grant_types) \
+                 VALUES ($1::VARCHAR, $2::VARCHAR[], $3::VARCHAR[], $4::VARCHAR, $5::BOOLEAN, $6::VARCHAR[], \
+                 $7::VARCHAR[], $8::VARCHAR[], $9::VARCHAR, $10::VARCHAR, $11::BOOLEAN, $12::VARCHAR[]) \
+                 RETURNING id;",
+                &[
+                    &client.name,
+                    &client.allowed_scopes,
+                    &client.redirect_uris,
+                    &client.secret,
+                    &client.mfa_required,
+                    &client.authenticators,
+                    &client.default_scopes,
+                    &client.post_logout_redirect_uris,
+                    &client.frontchannel_logout_uri,
+                    &client.backchannel_logout_uri,
+                    &client.enabled,
+                    &client.grant_types,
+                ],
+            )
+            .await;
+
+        if query.is_err() {
+            error!("{}", query.err().unwrap());
+            return None;
+        }
+
+        query.unwrap().into_iter().next().map(|row| row.get(0))
+    }
+
+    pub async fn update_client(&self, client: &Client) -> bool {
+        let query = self
+            .client
+            .execute(
+                "UPDATE public.clients SET name = $2::VARCHAR, allowed_scopes = $3::VARCHAR[], \
+                 redirect_uris = $4::VARCHAR[], secret = $5::VARCHAR, mfa_required = $6::BOOLEAN, \
+                 authenticators = $7::VARCHAR[], default_scopes = $8::VARCHAR[], \
+                 post_logout_redirect_uris = $9::VARCHAR[], frontchannel_logout_uri = $10::VARCHAR, \
+                 backchannel_logout_uri = $11::VARCHAR, enabled = $12::BOOLEAN, \
+                 grant_types = $13::VARCHAR[] WHERE id = $1::OID;",
+                &[
+                    &client.id,
+                    &client.name,
+                    &client.allowed_scopes,
+                    &client.redirect_uris,
+                    &client.secret,
+                    &client.mfa_required,
+                    &client.authenticators,
+                    &client.default_scopes,
+                    &client.post_logout_redirect_uris,
+                    &client.frontchannel_logout_uri,
+                    &client.backchannel_logout_uri,
+                    &client.enabled,
+                    &client.grant_types,
+                ],
+            )
+            .await;
+
+        if query.is_err() {
+            error!("{}", query.err().unwrap());
+            return false;
+        }
+
+        true
+    }
+
+    /// Deletes a client with its consents and claim mappings, returns whether it existed.
+    pub async fn delete_client(&self, client_id: &u32) -> bool {
+        for statement in [
+            "DELETE FROM public.consents WHERE client_id = $1::OID;",
+            "DELETE FROM public.claim_mappings WHERE client_id = $1::OID;",
+        ] {
+            let query = self.client.execute(statement, &[client_id]).await;
+            if query.is_err() {
+                error!("{}", query.err().unwrap());
+                return false;
+            }
+        }
+
+        let query = self
+            .client
+            .execute(
+                "DELETE FROM public.clients WHERE id = $1::OID;",
+                &[client_id],
+            )
+            .await;
+
+        if query.is_err() {
+            error!("{}", query.err().unwrap());
+            return false;
+        }
+
+        query.unwrap() > 0
+    }
+
+    async fn get_claim_mappings(&self, client_id: &u32) -> Vec<ClaimMapping> {
+        let query = self
+            .client
+            .query(
+                "SELECT claim, source, value, scope, targets FROM public.claim_mappings \
+                 WHERE client_id = $1::OID OR client_id IS NULL ORDER BY id;",
+                &[client_id],
+            )
+            .await;
+
+        if query.is_err() {
+            error!("{}", query.err().unwrap());
+            return Vec::new();
+        }
+
+        query
+            .unwrap()
+            .into_iter()
+            .map(|row| ClaimMapping {
+                claim: row.get(0),
+                source: row.get(1),
+                value: row.get(2),
+                scope: row.get(3),
+                targets: row.get(4),
+            })
+            .collect()
+    }
+
+    /// Gets the allowed columns of a user as JSON. The password hash is never included.
+    pub async fn get_user_attributes(
+        &self,
+        user_id: &u32,
+        attributes: &[String],
+    ) -> Map<String, Value> {
+        let attributes = attributes
+            .iter()
+            .filter(|attribute| *attribute != "password_hash")
+            .collect::<Vec<&String>>();
+        let query = self
+            .client
+            .query(
+                "SELECT COALESCE((SELECT jsonb_object_agg(key, value) FROM jsonb_each(to_jsonb(u)) \
+                 WHERE key = ANY($2::VARCHAR[])), '{}'::JSONB)::TEXT FROM public.users u \
+                 WHERE id = $1::OID LIMIT 1;",
+                &[user_id, &attributes],
+            )
+            .await;
+
+        if query.is_err() {
+            error!("{}", query.err().unwrap());
+            return Map::new();
+        }
+
+        match query.unwrap().into_iter().next() {
+            Some(row) => serde_json::from_str(row.get(0)).unwrap_or_else(|err| {
+                error!("Invalid attributes of user {}: {}", user_id, err);
+                Map::new()
+            }),
+            None => Map::new(),
+        }
+    }
+
+    pub async fn get_resource_server(&self, uri: &str) -> Option<ResourceServer> {
+        let query = self
+            .client
+            .query(
+                "SELECT uri, name, scopes FROM public.resource_servers WHERE uri = $1::VARCHAR LIMIT 1;",
+                &[&uri],
+            )
+            .await;
+
+        if query.is_err() {
+            error!("{}", query.err().unwrap());
+            return None;
+        }
+
+        if let Some(row) = query.unwrap().into_iter().next() {
+            return Option::from(ResourceServer {
+                uri: row.get(0),
                 name: row.get(1),
-                allowed_scopes: row.get(2),
-                redirect_uris: row.get(3),
-                secret: row.get(4),
+                scopes: row.get(2),
             });
         }
 
         None
     }
 
-    pub async fn get_user(self: &Self, email: &str, password: &str) -> Option<User> {
-        let query = self.client.query(
-            "SELECT id, email, password FROM users WHERE email = $1::VARCHAR AND password = $2::VARCHAR LIMIT 1", &[&email, &password],
-        ).await;
+    pub async fn get_scope(&self, name: &str) -> Option<Scope> {
+        let query = self
+            .client
+            .query(
+                "SELECT name, description, sensitivity, resource_server, is_default, roles \
+                 FROM public.scopes WHERE name = $1::VARCHAR LIMIT 1;",
+                &[&name],
+            )
+            .await;
 
         if query.is_err() {
             error!("{}", query.err().unwrap());
@@ -62,13 +354,814 @@ impl Database {
         }
 
         if let Some(row) = query.unwrap().into_iter().next() {
-            return Option::from(User {
-                id: row.get(0),
-                email: row.get(1),
-                password: row.get(2),
+            return Option::from(Scope {
+                name: row.get(0),
+                description: row.get(1),
+                sensitivity: row.get(2),
+                resource_server: row.get(3),
+                is_default: row.get(4),
+                roles: row.get(5),
             });
         }
 
         None
     }
+
+    /// Gets the names of the scopes granted when a request omits the scope.
+    pub async fn get_default_scopes(&self) -> Vec<String> {
+        let query = self
+            .client
+            .query(
+                "SELECT name FROM public.scopes WHERE is_default ORDER BY name;",
+                &[],
+            )
+            .await;
+
+        if query.is_err() {
+            error!("{}", query.err().unwrap());
+            return Vec::new();
+        }
+
+        query.unwrap().into_iter().map(|row| row.get(0)).collect()
+    }
+
+    pub async fn get_consent(&self, user_id: &u32, client_id: &u32) -> Vec<String> {
+        let query = self
+            .client
+            .query(
+                "SELECT scopes FROM public.consents WHERE user_id = $1::OID AND client_id = $2::OID LIMIT 1;",
+                &[user_id, client_id],
+            )
+            .await;
+
+        if query.is_err() {
+            error!("{}", query.err().unwrap());
+            return Vec::new();
+        }
+
+        match query.unwrap().into_iter().next() {
+            Some(row) => row.get(0),
+            None => Vec::new(),
+        }
+    }
+
+    /// Gets the claims a user granted to a client on their own, besides the claims of the scopes.
+    pub async fn get_consented_claims(&self, user_id: &u32, client_id: &u32) -> Vec<String> {
+        let query = self
+            .client
+            .query(
+                "SELECT claims FROM public.consents WHERE user_id = $1::OID AND client_id = $2::OID LIMIT 1;",
+                &[user_id, client_id],
+            )
+            .await;
+
+        if query.is_err() {
+            error!("{}", query.err().unwrap());
+            return Vec::new();
+        }
+
+        match query.unwrap().into_iter().next() {
+            Some(row) => row.get(0),
+            None => Vec::new(),
+        }
+    }
+
+    pub async fn save_consent(
+        &self,
+        user_id: &u32,
+        client_id: &u32,
+        scopes: &[String],
+        claims: &[String],
+    ) -> bool {
+        // Merge with the scopes and claims the user granted previously
+        let query = self
+            .client
+            .execute(
+                "INSERT INTO public.consents (user_id, client_id, scopes, claims) \
+                 VALUES ($1::OID, $2::OID, $3::VARCHAR[], $4::VARCHAR[]) \
+                 ON CONFLICT (user_id, client_id) DO UPDATE \
+                 SET scopes = ARRAY(SELECT DISTINCT UNNEST(consents.scopes || EXCLUDED.scopes)), \
+                 claims = ARRAY(SELECT DISTINCT UNNEST(consents.claims || EXCLUDED.claims)), \
+                 updated_at = NOW();",
+                &[user_id, client_id, &scopes, &claims],
+            )
+            .await;
+
+        if query.is_err() {
+            error!("{}", query.err().unwrap());
+            return false;
+        }
+
+        true
+    }
+
+    /// Gets the clients a user granted scopes to, the most recently used first.
+    pub async fn get_consents(&self, user_id: &u32) -> Vec<Consent> {
+        let query = self
+            .client
+            .query(
+                "SELECT c.id, c.name, co.scopes, EXTRACT(EPOCH FROM co.created_at)::BIGINT, \
+                 EXTRACT(EPOCH FROM co.last_used_at)::BIGINT FROM public.consents co \
+                 JOIN public.clients c ON c.id = co.client_id WHERE co.user_id = $1::OID \
+                 ORDER BY co.last_used_at DESC NULLS LAST, co.created_at DESC;",
+                &[user_id],
+            )
+            .await;
+
+        if query.is_err() {
+            error!("{}", query.err().unwrap());
+            return Vec::new();
+        }
+
+        query
+            .unwrap()
+            .into_iter()
+            .map(|row| Consent {
+                client_id: row.get(0),
+                client_name: row.get(1),
+                scopes: row.get(2),
+                created_at: row.get(3),
+                last_used_at: row.get(4),
+            })
+            .collect()
+    }
+
+    /// Records that a client obtained tokens with the grant of a user.
+    pub async fn record_consent_use(&self, user_id: &u32, client_id: &u32) -> bool {
+        let query = self
+            .client
+            .execute(
+                "UPDATE public.consents SET last_used_at = NOW() \
+                 WHERE user_id = $1::OID AND client_id = $2::OID;",
+                &[user_id, client_id],
+            )
+            .await;
+
+        if query.is_err() {
+            error!("{}", query.err().unwrap());
+            return false;
+        }
+
+        true
+    }
+
+    /// Deletes the scopes a user granted to a client, returns whether there were any.
+    pub async fn delete_consent(&self, user_id: &u32, client_id: &u32) -> bool {
+        let query = self
+            .client
+            .execute(
+                "DELETE FROM public.consents WHERE user_id = $1::OID AND client_id = $2::OID;",
+                &[user_id, client_id],
+            )
+            .await;
+
+        if query.is_err() {
+            error!("{}", query.err().unwrap());
+            return false;
+        }
+
+        query.unwrap() > 0
+    }
+
+    pub async fn get_user(&self, email: &str) -> Option<User> {
+        let query = self
+            .client
+            .query(
+                &format!(
+                    "SELECT {} FROM users WHERE email = $1::VARCHAR LIMIT 1",
+                    USER_COLUMNS
+                ),
+                &[&email],
+            )
+            .await;
+
+        if query.is_err() {
+            error!("{}", query.err().unwrap());
+            return None;
+        }
+
+        query.unwrap().first().map(user_from_row)
+    }
+
+    pub async fn get_user_by_id(&self, user_id: &u32) -> Option<User> {
+        let query = self
+            .client
+            .query(
+                &format!(
+                    "SELECT {} FROM users WHERE id = $1::OID LIMIT 1",
+                    USER_COLUMNS
+                ),
+                &[user_id],
+            )
+            .await;
+
+        if query.is_err() {
+            error!("{}", query.err().unwrap());
+            return None;
+        }
+
+        query.unwrap().first().map(user_from_row)
+    }
+
+    /// Gets a page of the users whose email address contains the search term, or whose ID it is.
+    pub async fn get_users(&self, search: &str, limit: i64, offset: i64) -> Vec<User> {
+        let query = self
+            .client
+            .query(
+                &format!(
+                    "SELECT {} FROM public.users \
+                     WHERE email ILIKE '%' || $1::VARCHAR || '%' OR id::TEXT = $1::VARCHAR \
+                     ORDER BY id LIMIT $2::BIGINT OFFSET $3::BIGINT;",
+                    USER_COLUMNS
+                ),
+                &[&search, &limit, &offset],
+            )
+            .await;
+
+        if query.is_err() {
+            error!("{}", query.err().unwrap());
+            return Vec::new();
+        }
+
+        query.unwrap().iter().map(user_from_row).collect()
+    }
+
+    pub async fn count_users(&self, search: &str) -> i64 {
+        let query = self
+            .client
+            .query(
+                "SELECT COUNT(*) FROM public.users \
+                 WHERE email ILIKE '%' || $1::VARCHAR || '%' OR id::TEXT = $1::VARCHAR;",
+                &[&search],
+            )
+            .await;
+
+        if query.is_err() {
+            error!("{}", query.err().unwrap());
+            return 0;
+        }
+
+        match query.unwrap().into_iter().next() {
+            Some(row) => row.get(0),
+            None => 0,
+        }
+    }
+
+    /// Updates the attributes of a user that are in the update. A changed phone number isn't
+    /// verified anymore and only a number can be verified. The status is changed on its own, so
+    /// an update never re-enables a user that was disabled meanwhile.
+    pub async fn update_user(&self, user_id: &u32, update: &UserUpdate) -> bool {
+        let query = self
+            .client
+            .execute(
+                "UPDATE public.users SET \
+                 groups = COALESCE($2::VARCHAR[], groups), \
+                 roles = COALESCE($3::VARCHAR[], roles), \
+                 entitlements = COALESCE($4::VARCHAR[], entitlements), \
+                 mfa_required = COALESCE($5::BOOLEAN, mfa_required), \
+                 email_verified = COALESCE($6::BOOLEAN, email_verified), \
+                 name = CASE WHEN $7::VARCHAR IS NULL THEN name \
+                 WHEN BTRIM($7::VARCHAR) = '' THEN NULL ELSE $7::VARCHAR END, \
+                 phone_number = CASE WHEN $8::VARCHAR IS NULL THEN phone_number \
+                 WHEN BTRIM($8::VARCHAR) = '' THEN NULL ELSE $8::VARCHAR END, \
+                 phone_number_verified = CASE \
+                 WHEN $9::BOOLEAN IS NOT NULL THEN $9::BOOLEAN AND CASE \
+                 WHEN $8::VARCHAR IS NULL THEN phone_number IS NOT NULL \
+                 ELSE BTRIM($8::VARCHAR) <> '' END \
+                 WHEN $8::VARCHAR IS NOT NULL \
+                 AND $8::VARCHAR IS DISTINCT FROM phone_number THEN FALSE \
+                 ELSE phone_number_verified END \
+                 WHERE id = $1::OID;",
+                &[
+                    user_id,
+                    &update.groups,
+                    &update.roles,
+                    &update.entitlements,
+                    &update.mfa_required,
+                    &update.email_verified,
+                    &update.name,
+                    &update.phone_number,
+                    &update.phone_number_verified,
+                ],
+            )
+            .await;
+
+        if query.is_err() {
+            error!("{}", query.err().unwrap());
+            return false;
+        }
+
+        true
+    }
+
+    /// Enables or disables a user.
+    pub async fn set_user_enabled(&self, user_id: &u32, enabled: bool) -> bool {
+        let query = self
+            .client
+            .execute(
+                "UPDATE public.users SET enabled = $2::BOOLEAN WHERE id = $1::OID;",
+                &[user_id, &enabled],
+            )
+            .await;
+
+        if query.is_err() {
+            error!("{}", query.err().unwrap());
+            return false;
+        }
+
+        true
+    }
+
+    /// Deletes a user with their consents, second factors and linked identities and directory
+    /// entry, returns whether they existed. Audit events are kept.
+    pub async fn delete_user(&self, user_id: &u32) -> bool {
+        // One statement, so nothing is left half deleted when a part fails
+        let query = self
+            .client
+            .execute(
+                "WITH consents AS (DELETE FROM public.consents WHERE user_id = $1::OID), \
+                 totp AS (DELETE FROM public.user_totp WHERE user_id = $1::OID), \
+                 recovery_codes AS (DELETE FROM public.user_recovery_codes \
+                 WHERE user_id = $1::OID), \
+                 webauthn AS (DELETE FROM public.webauthn_credentials WHERE user_id = $1::OID), \
+                 identities AS (DELETE FROM public.user_identities WHERE user_id = $1::OID), \
+                 ldap AS (DELETE FROM public.ldap_users WHERE user_id = $1::OID) \
+                 DELETE FROM public.users WHERE id = $1::OID;",
+                &[user_id],
+            )
+            .await;
+
+        if query.is_err() {
+            error!("{}", query.err().unwrap());
+            return false;
+        }
+
+        query.unwrap() > 0
+    }
+
+    /// Creates a user and returns its ID, or `None` if the email address is already taken.
+    pub async fn create_user(
+        &self,
+        email: &str,
+        password_hash: &str,
+        email_verified: bool,
+    ) -> Option<u32> {
+        let query = self
+            .client
+            .query(
+                "INSERT INTO public.users (email, password_hash, email_verified) \
+                 VALUES ($1::VARCHAR, $2::VARCHAR, $3::BOOLEAN) \
+                 ON CONFLICT ((LOWER(email))) DO NOTHING RETURNING id;",
+                &[&email, &password_hash, &email_verified],
+            )
+            .await;
+
+        if query.is_err() {
+            error!("{}", query.err().unwrap());
+            return None;
+        }
+
+        query.unwrap().into_iter().next().map(|row| row.get(0))
+    }
+
+    /// Creates a user with all its attributes at once and returns its ID, or `None` if the
+    /// email address is already taken. The ID of the given user is ignored.
+    pub async fn insert_user(&self, user: &User) -> Option<u32> {
+        let query = self
+            .client
+            .query(
+                "INSERT INTO public.users (email, password_hash, groups, roles, entitlements, \
+                 mfa_required, email_verified, enabled, name, phone_number, phone_number_verified) \
+                 VALUES ($1::VARCHAR, $2::VARCHAR, $3::VARCHAR[], $4::VARCHAR[], $5::VARCHAR[], \
+                 $6::BOOLEAN, $7::BOOLEAN, $8::BOOLEAN, $9::VARCHAR, $10::VARCHAR, $11::BOOLEAN) \
+                 ON CONFLICT ((LOWER(email))) DO NOTHING RETURNING id;",
+                &[
+                    &user.email,
+                    &user.password_hash,
+                    &user.groups,
+                    &user.roles,
+                    &user.entitlements,
+                    &user.mfa_required,
+                    &user.email_verified,
+                    &user.enabled,
+                    &user.name,
+                    &user.phone_number,
+                    &user.phone_number_verified,
+                ],
+            )
+            .await;
+
+        if query.is_err() {
+            error!("{}", query.err().unwrap());
+            return None;
+        }
+
+        query.unwrap().into_iter().next().map(|row| row.get(0))
+    }
+
+    pub async fn verify_user_email(&self, user_id: &u32, email: &str) -> bool {
+        let query = self
+            .client
+            .execute(
+                "UPDATE public.users SET email_verified = TRUE WHERE id = $1::OID AND email = $2::VARCHAR;",
+                &[user_id, &email],
+            )
+            .await;
+
+        if query.is_err() {
+            error!("{}", query.err().unwrap());
+            return false;
+        }
+
+        query.unwrap() > 0
+    }
+
+    pub async fn update_password_hash(&self, user_id: &u32, password_hash: &str) -> bool {
+        let query = self
+            .client
+            .execute(
+                "UPDATE public.users SET password_hash = $2::VARCHAR WHERE id = $1::OID;",
+                &[user_id, &password_hash],
+            )
+            .await;
+
+        if query.is_err() {
+            error!("{}", query.err().unwrap());
+            return false;
+        }
+
+        true
+    }
+
+    /// Replaces the authorization attributes of a user, e.g. with those of a directory.
+    pub async fn update_user_attributes(
+        &self,
+        user_id: &u32,
+        groups: &[String],
+        roles: &[String],
+        entitlements: &[String],
+    ) -> bool {
+        let query = self
+            .client
+            .execute(
+                "UPDATE public.users SET groups = $2::VARCHAR[], roles = $3::VARCHAR[], \
+                 entitlements = $4::VARCHAR[] WHERE id = $1::OID;",
+                &[user_id, &groups, &roles, &entitlements],
+            )
+            .await;
+
+        if query.is_err() {
+            error!("{}", query.err().unwrap());
+            return false;
+        }
+
+        true
+    }
+
+    pub async fn get_totp_secret(&self, user_id: &u32) -> Option<String> {
+        let query = self
+            .client
+            .query(
+                "SELECT secret FROM public.user_totp WHERE user_id = $1::OID LIMIT 1;",
+                &[user_id],
+            )
+            .await;
+
+        if query.is_err() {
+            error!("{}", query.err().unwrap());
+            return None;
+        }
+
+        query.unwrap().into_iter().next().map(|row| row.get(0))
+    }
+
+    pub async fn save_totp(
+        &self,
+        user_id: &u32,
+        secret: &str,
+        recovery_code_hashes: &[String],
+    ) -> bool {
+        // Replace the secret and all the recovery codes at once
+        let query = self
+            .client
+            .execute(
+                "WITH totp AS (\
+                     INSERT INTO public.user_totp (user_id, secret) VALUES ($1::OID, $2::VARCHAR) \
+                     ON CONFLICT (user_id) DO UPDATE SET secret = EXCLUDED.secret, created_at = NOW()\
+                 ), deleted AS (\
+                     DELETE FROM public.user_recovery_codes WHERE user_id = $1::OID\
+                 ) \
+                 INSERT INTO public.user_recovery_codes (user_id, code_hash) \
+                 SELECT $1::OID, UNNEST($3::VARCHAR[]);",
+                &[user_id, &secret, &recovery_code_hashes],
+            )
+            .await;
+
+        if query.is_err() {
+            error!("{}", query.err().unwrap());
+            return false;
+        }
+
+        true
+    }
+
+    pub async fn use_recovery_code(&self, user_id: &u32, code_hash: &str) -> bool {
+        let query = self
+            .client
+            .execute(
+                "UPDATE public.user_recovery_codes SET used_at = NOW() \
+                 WHERE user_id = $1::OID AND code_hash = $2::VARCHAR AND used_at IS NULL;",
+                &[user_id, &code_hash],
+            )
+            .await;
+
+        match query {
+            Ok(updated) => updated == 1,
+            Err(err) => {
+                error!("{}", err);
+                false
+            }
+        }
+    }
+
+    pub async fn get_webauthn_credentials(&self, user_id: &u32) -> Vec<WebauthnCredential> {
+        let query = self
+            .client
+            .query(
+                "SELECT credential_id, user_id, public_key, sign_count, name FROM public.webauthn_credentials \
+                 WHERE user_id = $1::OID ORDER BY created_at;",
+                &[user_id],
+            )
+            .await;
+
+        if query.is_err() {
+            error!("{}", query.err().unwrap());
+            return Vec::new();
+        }
+
+        query
+            .unwrap()
+            .into_iter()
+            .map(|row| WebauthnCredential {
+                credential_id: row.get(0),
+                user_id: row.get(1),
+                public_key: row.get(2),
+                sign_count: row.get(3),
+                name: row.get(4),
+            })
+            .collect()
+    }
+
+    pub async fn get_webauthn_credential(&self, credential_id: &str) -> Option<WebauthnCredential> {
+        let query = self
+            .client
+            .query(
+                "SELECT credential_id, user_id, public_key, sign_count, name FROM public.webauthn_credentials \
+                 WHERE credential_id = $1::VARCHAR LIMIT 1;",
+                &[&credential_id],
+            )
+            .await;
+
+        if query.is_err() {
+            error!("{}", query.err().unwrap());
+            return None;
+        }
+
+        query
+            .unwrap()
+            .into_iter()
+            .next()
+            .map(|row| WebauthnCredential {
+                credential_id: row.get(0),
+                user_id: row.get(1),
+                public_key: row.get(2),
+                sign_count: row.get(3),
+                name: row.get(4),
+            })
+    }
+
+    pub async fn save_webauthn_credential(&self, credential: &WebauthnCredential) -> bool {
+        let query = self
+            .client
+            .execute(
+                "INSERT INTO public.webauthn_credentials (credential_id, user_id, public_key, sign_count, name) \
+                 VALUES ($1::VARCHAR, $2::OID, $3::BYTEA, $4::BIGINT, $5::VARCHAR);",
+                &[
+                    &credential.credential_id,
+                    &credential.user_id,
+                    &credential.public_key,
+                    &credential.sign_count,
+                    &credential.name,
+                ],
+            )
+            .await;
+
+        if query.is_err() {
+            error!("{}", query.err().unwrap());
+            return false;
+        }
+
+        true
+    }
+
+    pub async fn update_webauthn_sign_count(&self, credential_id: &str, sign_count: i64) -> bool {
+        let query = self
+            .client
+            .execute(
+                "UPDATE public.webauthn_credentials SET sign_count = $2::BIGINT, last_used_at = NOW() \
+                 WHERE credential_id = $1::VARCHAR;",
+                &[&credential_id, &sign_count],
+            )
+            .await;
+
+        if query.is_err() {
+            error!("{}", query.err().unwrap());
+            return false;
+        }
+
+        true
+    }
+
+    pub async fn record_audit_event(
+        &self,
+        event: &str,
+        user_id: Option<u32>,
+        subject: &str,
+        ip_address: Option<&str>,
+        details: Option<&str>,
+    ) -> bool {
+        let query = self
+            .client
+            .execute(
+                "INSERT INTO public.audit_events (event, user_id, subject, ip_address, details) \
+                 VALUES ($1::VARCHAR, $2::OID, $3::VARCHAR, $4::VARCHAR, $5::VARCHAR);",
+                &[&event, &user_id, &subject, &ip_address, &details],
+            )
+            .await;
+
+        if query.is_err() {
+            error!("{}", query.err().unwrap());
+            return false;
+        }
+
+        true
+    }
+
+    pub async fn get_identity_providers(&self) -> Vec<IdentityProvider> {
+        let query = self
+            .client
+            .query(
+                "SELECT id, name, issuer, client_id, client_secret, scopes, authorization_endpoint, \
+                 token_endpoint, userinfo_endpoint, trust_email FROM public.identity_providers \
+                 WHERE enabled ORDER BY name;",
+                &[],
+            )
+            .await;
+
+        if query.is_err() {
+            error!("{}", query.err().unwrap());
+            return Vec::new();
+        }
+
+        query
+            .unwrap()
+            .into_iter()
+            .map(|row| IdentityProvider {
+                id: row.get(0),
+                name: row.get(1),
+                issuer: row.get(2),
+                client_id: row.get(3),
+                client_secret: row.get(4),
+                scopes: row.get(5),
+                authorization_endpoint: row.get(6),
+                token_endpoint: row.get(7),
+                userinfo_endpoint: row.get(8),
+                trust_email: row.get(9),
+            })
+            .collect()
+    }
+
+    pub async fn get_identity_provider(&self, provider_id: &str) -> Option<IdentityProvider> {
+        self.get_identity_providers()
+            .await
+            .into_iter()
+            .find(|provider| provider.id == provider_id)
+    }
+
+    /// Gets the user an LDAP entry is linked to.
+    pub async fn get_ldap_user(&self, entry_id: &str) -> Option<u32> {
+        let query = self
+            .client
+            .query(
+                "SELECT user_id FROM public.ldap_users WHERE entry_id = $1::VARCHAR LIMIT 1;",
+                &[&entry_id],
+            )
+            .await;
+
+        if query.is_err() {
+            error!("{}", query.err().unwrap());
+            return None;
+        }
+
+        query.unwrap().into_iter().next().map(|row| row.get(0))
+    }
+
+    /// Creates the shadow account of an LDAP entry together with its link, unless a user with
+    /// the email address already exists.
+    pub async fn create_ldap_user(
+        &self,
+        entry_id: &str,
+        dn: &str,
+        email: &str,
+        password_hash: &str,
+    ) -> Option<u32> {
+        let query = self
+            .client
+            .query(
+                "WITH new_user AS ( \
+                     INSERT INTO public.users (email, password_hash, email_verified) \
+                     VALUES ($3::VARCHAR, $4::VARCHAR, TRUE) \
+                     ON CONFLICT ((LOWER(email))) DO NOTHING RETURNING id \
+                 ) \
+                 INSERT INTO public.ldap_users (entry_id, user_id, dn) \
+                 SELECT $1::VARCHAR, id, $2::VARCHAR FROM new_user \
+                 RETURNING user_id;",
+                &[&entry_id, &dn, &email, &password_hash],
+            )
+            .await;
+
+        if query.is_err() {
+            error!("{}", query.err().unwrap());
+            return None;
+        }
+
+        query.unwrap().into_iter().next().map(|row| row.get(0))
+    }
+
+    /// Gets where the credentials of a user are managed, `None` if that can't be told.
+    pub async fn get_credential_source(&self, user_id: &u32) -> Option<CredentialSource> {
+        let query = self
+            .client
+            .query(
+                "SELECT EXISTS (SELECT 1 FROM public.ldap_users WHERE user_id = $1::OID), \
+                 EXISTS (SELECT 1 FROM public.user_identities WHERE user_id = $1::OID);",
+                &[user_id],
+            )
+            .await;
+
+        if query.is_err() {
+            error!("{}", query.err().unwrap());
+            return None;
+        }
+
+        let row = query.unwrap().into_iter().next()?;
+        Some(match (row.get(0), row.get(1)) {
+            (true, _) => CredentialSource::Ldap,
+            (false, true) => CredentialSource::Federated,
+            (false, false) => CredentialSource::Local,
+        })
+    }
+
+    /// Gets the user an upstream identity is linked to.
+    pub async fn get_user_identity(&self, provider_id: &str, subject: &str) -> Option<u32> {
+        let query = self
+            .client
+            .query(
+                "SELECT user_id FROM public.user_identities \
+                 WHERE provider_id = $1::VARCHAR AND subject = $2::VARCHAR LIMIT 1;",
+                &[&provider_id, &subject],
+            )
+            .await;
+
+        if query.is_err() {
+            error!("{}", query.err().unwrap());
+            return None;
+        }
+
+        query.unwrap().into_iter().next().map(|row| row.get(0))
+    }
+
+    pub async fn link_user_identity(
+        &self,
+        provider_id: &str,
+        subject: &str,
+        user_id: &u32,
+        email: Option<&str>,
+    ) -> bool {
+        let query = self
+            .client
+            .execute(
+                "INSERT INTO public.user_identities (provider_id, subject, user_id, email) \
+                 VALUES ($1::VARCHAR, $2::VARCHAR, $3::OID, $4::VARCHAR);",
+                &[&provider_id, &subject, user_id, &email],
+            )
+            .await;
+
+        if query.is_err() {
+            error!("{}", query.err().unwrap());
+            return false;
+        }
+
+        true
+    }
 }
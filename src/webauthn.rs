@@ -0,0 +1,650 @@
+use crate::config::{webauthn_origin, webauthn_rp_id};
+use base64::engine::general_purpose::URL_SAFE_NO_PAD;
+use base64::Engine;
+use ciborium::Value;
+use log::warn;
+use rand::RngCore;
+use ring::signature::{
+    RsaPublicKeyComponents, UnparsedPublicKey, ECDSA_P256_SHA256_ASN1, ED25519,
+    RSA_PKCS1_2048_8192_SHA256,
+};
+use serde::Deserialize;
+use sha2::{Digest, Sha256};
+
+// COSE algorithm identifiers accepted for credentials
+pub const COSE_ALG_ES256: i64 = -7;
+pub const COSE_ALG_EDDSA: i64 = -8;
+pub const COSE_ALG_RS256: i64 = -257;
+
+// Authenticator data flags
+const FLAG_USER_PRESENT: u8 = 0x01;
+const FLAG_USER_VERIFIED: u8 = 0x04;
+const FLAG_ATTESTED_CREDENTIAL_DATA: u8 = 0x40;
+
+/// The relying party a ceremony is verified for.
+pub struct RelyingParty {
+    pub id: String,
+    pub origin: String,
+}
+
+impl RelyingParty {
+    /// The relying party of this authorization server, from the configuration.
+    pub fn from_config() -> Self {
+        RelyingParty {
+            id: webauthn_rp_id(),
+            origin: webauthn_origin(),
+        }
+    }
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct ClientData {
+    #[serde(rename = "type")]
+    ceremony_type: String,
+    challenge: String,
+    origin: String,
+}
+
+/// The parts of the authenticator data used by the relying party.
+pub struct AuthenticatorData {
+    pub flags: u8,
+    pub sign_count: u32,
+    pub credential_id: Option<Vec<u8>>,
+    pub public_key: Option<Vec<u8>>,
+}
+
+impl AuthenticatorData {
+    pub fn user_verified(&self) -> bool {
+        self.flags & FLAG_USER_VERIFIED != 0
+    }
+}
+
+/// A credential created by a registration ceremony.
+pub struct RegisteredCredential {
+    pub credential_id: Vec<u8>,
+    pub public_key: Vec<u8>,
+    pub sign_count: u32,
+}
+
+pub fn generate_challenge() -> String {
+    let mut challenge = [0u8; 32];
+    rand::thread_rng().fill_bytes(&mut challenge);
+    encode_base64url(&challenge)
+}
+
+pub fn encode_base64url(data: &[u8]) -> String {
+    URL_SAFE_NO_PAD.encode(data)
+}
+
+pub fn decode_base64url(data: &str) -> Option<Vec<u8>> {
+    URL_SAFE_NO_PAD.decode(data.trim_end_matches('=')).ok()
+}
+
+/// Verifies a registration ceremony (`navigator.credentials.create`) and returns the new credential.
+/// Attestation statements are not verified, as with the `none` attestation conveyance.
+pub fn verify_registration(
+    relying_party: &RelyingParty,
+    challenge: &str,
+    client_data_json: &[u8],
+    attestation_object: &[u8],
+) -> Option<RegisteredCredential> {
+    verify_client_data(
+        relying_party,
+        "webauthn.create",
+        challenge,
+        client_data_json,
+    )?;
+
+    let attestation: Value = ciborium::from_reader(attestation_object)
+        .map_err(|err| warn!("Invalid attestation object: {}", err))
+        .ok()?;
+    let auth_data = attestation
+        .as_map()?
+        .iter()
+        .find(|(key, _)| key.as_text() == Some("authData"))?
+        .1
+        .as_bytes()?;
+
+    let authenticator_data = parse_authenticator_data(relying_party, auth_data)?;
+
+    Some(RegisteredCredential {
+        credential_id: authenticator_data.credential_id?,
+        public_key: authenticator_data.public_key?,
+        sign_count: authenticator_data.sign_count,
+    })
+}
+
+/// Verifies an authentication ceremony (`navigator.credentials.get`) against a stored public key.
+pub fn verify_assertion(
+    relying_party: &RelyingParty,
+    challenge: &str,
+    client_data_json: &[u8],
+    authenticator_data: &[u8],
+    signature: &[u8],
+    public_key: &[u8],
+) -> Option<AuthenticatorData> {
+    verify_client_data(relying_party, "webauthn.get", challenge, client_data_json)?;
+    let parsed_authenticator_data = parse_authenticator_data(relying_party, authenticator_data)?;
+
+    // The signature covers the authenticator data and the hash of the client data
+    let mut signed_data = authenticator_data.to_vec();
+    signed_data.extend_from_slice(&Sha256::digest(client_data_json));
+
+    if !verify_signature(public_key, &signed_data, signature) {
+        warn!("Invalid WebAuthn assertion signature");
+        return None;
+    }
+
+    Some(parsed_authenticator_data)
+}
+
+/// Whether the signature counter of an assertion is higher than the stored one. A counter that
+/// doesn't increase hints at a cloned authenticator, those without a counter always report 0.
+pub fn sign_count_increased(stored_sign_count: i64, sign_count: u32) -> bool {
+    let sign_count = sign_count as i64;
+    (sign_count == 0 && stored_sign_count == 0) || sign_count > stored_sign_count
+}
+
+fn verify_client_data(
+    relying_party: &RelyingParty,
+    ceremony_type: &str,
+    challenge: &str,
+    client_data_json: &[u8],
+) -> Option<()> {
+    let client_data: ClientData = serde_json::from_slice(client_data_json)
+        .map_err(|err| warn!("Invalid WebAuthn client data: {}", err))
+        .ok()?;
+
+    if client_data.ceremony_type != ceremony_type {
+        warn!(
+            "Unexpected WebAuthn ceremony type: {}",
+            client_data.ceremony_type
+        );
+        return None;
+    }
+    if client_data.challenge != challenge {
+        warn!("WebAuthn challenge mismatch");
+        return None;
+    }
+    if client_data.origin != relying_party.origin {
+        warn!("Unexpected WebAuthn origin: {}", client_data.origin);
+        return None;
+    }
+
+    Some(())
+}
+
+fn parse_authenticator_data(
+    relying_party: &RelyingParty,
+    data: &[u8],
+) -> Option<AuthenticatorData> {
+    // rpIdHash (32) | flags (1) | signCount (4) | attestedCredentialData (optional)
+    if data.len() < 37 {
+        warn!("WebAuthn authenticator data is too short");
+        return None;
+    }
+
+    if data[..32] != Sha256::digest(relying_party.id.as_bytes())[..] {
+        warn!("WebAuthn RP ID hash mismatch");
+        return None;
+    }
+
+    let flags = data[32];
+    if flags & FLAG_USER_PRESENT == 0 {
+        warn!("WebAuthn user presence flag is not set");
+        return None;
+    }
+
+    let sign_count = u32::from_be_bytes(data[33..37].try_into().ok()?);
+
+    if flags & FLAG_ATTESTED_CREDENTIAL_DATA == 0 {
+        return Some(AuthenticatorData {
+            flags,
+            sign_count,
+            credential_id: None,
+            public_key: None,
+        });
+    }
+
+    // aaguid (16) | credentialIdLength (2) | credentialId | credentialPublicKey (COSE)
+    let credential_id_length = u16::from_be_bytes(data.get(53..55)?.try_into().ok()?) as usize;
+    let credential_id = data.get(55..55 + credential_id_length)?.to_vec();
+
+    // The COSE key is followed by optional extensions, so decode it to find where it ends
+    let mut remaining = data.get(55 + credential_id_length..)?;
+    let available = remaining.len();
+    let _: Value = ciborium::from_reader(&mut remaining)
+        .map_err(|err| warn!("Invalid credential public key: {}", err))
+        .ok()?;
+    let public_key_length = available - remaining.len();
+    let public_key =
+        data[55 + credential_id_length..55 + credential_id_length + public_key_length].to_vec();
+
+    Some(AuthenticatorData {
+        flags,
+        sign_count,
+        credential_id: Some(credential_id),
+        public_key: Some(public_key),
+    })
+}
+
+fn verify_signature(cose_key: &[u8], message: &[u8], signature: &[u8]) -> bool {
+    let key: Value = match ciborium::from_reader(cose_key) {
+        Ok(key) => key,
+        Err(err) => {
+            warn!("Invalid stored COSE key: {}", err);
+            return false;
+        }
+    };
+
+    let parameter = |label: i64| {
+        key.as_map()?
+            .iter()
+            .find(|(key, _)| key.as_integer().map(i128::from) == Some(label as i128))
+            .map(|(_, value)| value)
+    };
+    let integer = |label: i64| {
+        parameter(label)
+            .and_then(Value::as_integer)
+            .map(i128::from)
+            .and_then(|value| i64::try_from(value).ok())
+    };
+    let bytes = |label: i64| parameter(label).and_then(Value::as_bytes);
+
+    match integer(3) {
+        Some(COSE_ALG_ES256) => {
+            let (Some(x), Some(y)) = (bytes(-2), bytes(-3)) else {
+                return false;
+            };
+
+            let mut public_key = vec![0x04];
+            public_key.extend_from_slice(x);
+            public_key.extend_from_slice(y);
+
+            UnparsedPublicKey::new(&ECDSA_P256_SHA256_ASN1, public_key)
+                .verify(message, signature)
+                .is_ok()
+        }
+        Some(COSE_ALG_RS256) => {
+            let (Some(n), Some(e)) = (bytes(-1), bytes(-2)) else {
+                return false;
+            };
+
+            RsaPublicKeyComponents { n, e }
+                .verify(&RSA_PKCS1_2048_8192_SHA256, message, signature)
+                .is_ok()
+        }
+        Some(COSE_ALG_EDDSA) => {
+            let Some(x) = bytes(-2) else {
+                return false;
+            };
+
+            UnparsedPublicKey::new(&ED25519, x)
+                .verify(message, signature)
+                .is_ok()
+        }
+        algorithm => {
+            warn!("Unsupported COSE algorithm: {:?}", algorithm);
+            false
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ring::rand::SystemRandom;
+    use ring::signature::{
+        EcdsaKeyPair, Ed25519KeyPair, KeyPair, RsaKeyPair, ECDSA_P256_SHA256_ASN1_SIGNING,
+        RSA_PKCS1_SHA256,
+    };
+    use serde_json::json;
+
+    // PKCS#8 keys of the software authenticators
+    const ES256_PKCS8: &str =
+        "MIGHAgEAMBMGByqGSM49AgEGCCqGSM49AwEHBG0wawIBAQQg0UELqkTISp/ygKDRjSyvXR6JutrOzMpQOiNa3yCw\
+        7vOhRANCAASpcRgRlSnbmXfC3xkDLfZeerFq+A3/xKcQOjmn5fUFDOjXpKwXfwkrc6Ehz+tGqJxGYs8rrKHwSmp+ctpuNKAa";
+    const EDDSA_PKCS8: &str = "MC4CAQAwBQYDK2VwBCIEIHdoGgkrEHpXptIylC1ubbjkbYC3TD/TXnWZlC3V22o/";
+    const RS256_PKCS8: &str =
+        "MIIEvQIBADANBgkqhkiG9w0BAQEFAASCBKcwggSjAgEAAoIBAQDdrwyYdgxvVFUY/Bhe6fqdtIB9c5rzVAG4xiVB\
+        nZ/lS5f2zmDWqbFu2BuSkY2skG1xfM+R+o0wS68QOEWW2585KD418NKOJIaxbCaSs2Nn3mI3b9bKh17NmJI4bZTz\
+        LkmuoCUeL4uloosApTNcmM8pRgzOesitGnZuD81EWyRjkLmHbQ2KhTcXXx6qg7z6sMHQJbCkbbevuLpS7U7OjoF4\
+        /zKOajxf0ePg7ZrxIQmXxMo1PkQusKa25E1+xBR2XU5v9l2xbR1yDPxgYnrOAsecbHvRFqmmFULtZNnUQXloE171\
+        pS6Xy/UZTRQHSqouXRArwZ32uZuFbusXgYr4c/WDAgMBAAECggEAIJNILYst7poc2yFdpMI11+tzSiatE/6PNjYK\
+        ZCb5HQ+g/V0m8RmvKhOgPEcejhy3CGCH0UgDwUoN0pCs9oBqrafSjx3xQtPukFnNHUI2ZEf9yhmpcsoLo03n3ATg\
+        82R6N//uabjLiNkRcKnV71BrzgEuK+qdBzAxS6EPenD42uxeW6y+sL7cQPuW9gadrMzTmxpMKZnkQNW//bO74gJl\
+        0pcy512PBHAhb3WMM70jnJYACxrT1Xy95UTuu9ftQEYPjUU7FXK9Ha1gb0kNK4wa0H3mFdZa6kucW0wuqJltcsOM\
+        IPqIvhEh69yQaO/Qux2byCcEC4X4g3gx2ytV7dAkSQKBgQD1buc1v9cZM/SQo12/jAYvfPVnBzw2h3C2A6lgREQS\
+        IoCzUwd+9MhB1BVzB6KT6PBZXHLR42/TPRQGVaE2lXj7Z/03WTYL5Sz8GMXDam+4PwqdA/TmxIAl47lEUrmIuTfP\
+        5UVoJ0HdZC2LfUfIx7Fn1g8pFx7lG4pMQhU3/uDTfwKBgQDnOmLr24KTCJL4h0Cv2l2vBvqKCRB+IlpxeP+koST3\
+        d9HCLcEUm/+9H0sOIxJkTh+gX7bNXd9UEJYSGUjDIYhcnmI/jbH/mI7rxFM9FXnirmDjnqma9FX632O0o91bnrsf\
+        ObyixrlJOOqAqEYpQ8RvcgEQq0WBV8oFL11tJWGP/QKBgEPQTNoxE4HHNT/xo6mVSvA8GqJJAX4flij0ggwzUIui\
+        NOVXf664e8yUiQirh2RO7HoEbUVJDlDTg4DskArA6his9u4gHCrVO8S8I4iHFC9YW9peiYBpsu7FKR+Tq+f3JVCw\
+        DKlmj3BYia0NBJSZeI80OiQkW0j6fw/rAmniZsSJAoGAAa3u4f+qJx880glJcv2HCKpoT3BZalEdjw1t7egtuI5q\
+        O5rReDDZ3qnHhcbDb28R07xeO4efA6FLNb5xRNmNf/62f/erf0vAXf4DDMKHLBpQIdcZLELyDOxqcPlvtiak/UkS\
+        EayX0+xR57VRLSOM3bJaR6C21IWqtfbuGsjWuJECgYEAnnumRP74s7oe8LMMIGHUZTd+6HQJibznWOBCzFYTI40G\
+        2VxHQrXgt1YgmD0SkbFoMKi0wWRjhJN8x7nrOeIyCj8TvnuoEkKqdA+ELHUz8gKWBkMQMecdmmNIkpaVLwAbmQhS\
+        moSkVYF13TU6hKl8ede8eDDI8WwGs1cl0EL8MNk=";
+
+    const RP_ID: &str = "auth.example.com";
+    const ORIGIN: &str = "https://auth.example.com";
+    const CHALLENGE: &str = "c29mdHdhcmUtYXV0aGVudGljYXRvci1jaGFsbGVuZ2U";
+    const CREDENTIAL_ID: &[u8] = b"software-authenticator-credential";
+    const ALGORITHMS: [i64; 3] = [COSE_ALG_ES256, COSE_ALG_EDDSA, COSE_ALG_RS256];
+
+    enum Authenticator {
+        Es256(EcdsaKeyPair),
+        EdDsa(Ed25519KeyPair),
+        Rs256(RsaKeyPair),
+    }
+
+    impl Authenticator {
+        fn new(algorithm: i64) -> Self {
+            let pkcs8 = |key: &str| {
+                base64::engine::general_purpose::STANDARD
+                    .decode(key)
+                    .unwrap()
+            };
+
+            match algorithm {
+                COSE_ALG_ES256 => Authenticator::Es256(
+                    EcdsaKeyPair::from_pkcs8(
+                        &ECDSA_P256_SHA256_ASN1_SIGNING,
+                        &pkcs8(ES256_PKCS8),
+                        &SystemRandom::new(),
+                    )
+                    .unwrap(),
+                ),
+                COSE_ALG_EDDSA => Authenticator::EdDsa(
+                    Ed25519KeyPair::from_pkcs8_maybe_unchecked(&pkcs8(EDDSA_PKCS8)).unwrap(),
+                ),
+                _ => Authenticator::Rs256(RsaKeyPair::from_pkcs8(&pkcs8(RS256_PKCS8)).unwrap()),
+            }
+        }
+
+        // The public key as a COSE_Key, as stored for the credential
+        fn cose_key(&self) -> Vec<u8> {
+            let entry = |label: i64, value: Value| (Value::Integer(label.into()), value);
+            let integer = |value: i64| Value::Integer(value.into());
+
+            let entries = match self {
+                Authenticator::Es256(key_pair) => {
+                    let point = key_pair.public_key().as_ref();
+                    vec![
+                        entry(1, integer(2)),
+                        entry(3, integer(COSE_ALG_ES256)),
+                        entry(-1, integer(1)),
+                        entry(-2, Value::Bytes(point[1..33].to_vec())),
+                        entry(-3, Value::Bytes(point[33..].to_vec())),
+                    ]
+                }
+                Authenticator::EdDsa(key_pair) => vec![
+                    entry(1, integer(1)),
+                    entry(3, integer(COSE_ALG_EDDSA)),
+                    entry(-1, integer(6)),
+                    entry(-2, Value::Bytes(key_pair.public_key().as_ref().to_vec())),
+                ],
+                Authenticator::Rs256(key_pair) => {
+                    let components: RsaPublicKeyComponents<Vec<u8>> = key_pair.public().into();
+                    vec![
+                        entry(1, integer(3)),
+                        entry(3, integer(COSE_ALG_RS256)),
+                        entry(-1, Value::Bytes(components.n)),
+                        entry(-2, Value::Bytes(components.e)),
+                    ]
+                }
+            };
+
+            let mut cose_key = Vec::new();
+            ciborium::into_writer(&Value::Map(entries), &mut cose_key).unwrap();
+            cose_key
+        }
+
+        fn sign(&self, message: &[u8]) -> Vec<u8> {
+            let rng = SystemRandom::new();
+            match self {
+                Authenticator::Es256(key_pair) => {
+                    key_pair.sign(&rng, message).unwrap().as_ref().to_vec()
+                }
+                Authenticator::EdDsa(key_pair) => key_pair.sign(message).as_ref().to_vec(),
+                Authenticator::Rs256(key_pair) => {
+                    let mut signature = vec![0; key_pair.public().modulus_len()];
+                    key_pair
+                        .sign(&RSA_PKCS1_SHA256, &rng, message, &mut signature)
+                        .unwrap();
+                    signature
+                }
+            }
+        }
+
+        fn register(&self, rp_id: &str) -> (Vec<u8>, Vec<u8>) {
+            let mut attested_credential_data = vec![0; 16];
+            attested_credential_data.extend_from_slice(&(CREDENTIAL_ID.len() as u16).to_be_bytes());
+            attested_credential_data.extend_from_slice(CREDENTIAL_ID);
+            attested_credential_data.extend_from_slice(&self.cose_key());
+
+            let auth_data = authenticator_data(
+                rp_id,
+                FLAG_USER_PRESENT | FLAG_USER_VERIFIED | FLAG_ATTESTED_CREDENTIAL_DATA,
+                0,
+                &attested_credential_data,
+            );
+            let attestation = Value::Map(vec![
+                (Value::Text("fmt".into()), Value::Text("none".into())),
+                (Value::Text("attStmt".into()), Value::Map(Vec::new())),
+                (Value::Text("authData".into()), Value::Bytes(auth_data)),
+            ]);
+            let mut attestation_object = Vec::new();
+            ciborium::into_writer(&attestation, &mut attestation_object).unwrap();
+
+            (
+                client_data("webauthn.create", CHALLENGE, ORIGIN),
+                attestation_object,
+            )
+        }
+
+        // Returns the client data, authenticator data and signature of an assertion
+        fn assert(&self, rp_id: &str, sign_count: u32) -> (Vec<u8>, Vec<u8>, Vec<u8>) {
+            let client_data_json = client_data("webauthn.get", CHALLENGE, ORIGIN);
+            let auth_data = authenticator_data(rp_id, FLAG_USER_PRESENT, sign_count, &[]);
+
+            let mut signed_data = auth_data.clone();
+            signed_data.extend_from_slice(&Sha256::digest(&client_data_json));
+
+            let signature = self.sign(&signed_data);
+            (client_data_json, auth_data, signature)
+        }
+    }
+
+    fn relying_party() -> RelyingParty {
+        RelyingParty {
+            id: RP_ID.to_string(),
+            origin: ORIGIN.to_string(),
+        }
+    }
+
+    fn client_data(ceremony_type: &str, challenge: &str, origin: &str) -> Vec<u8> {
+        serde_json::to_vec(&json!({
+            "type": ceremony_type,
+            "challenge": challenge,
+            "origin": origin,
+        }))
+        .unwrap()
+    }
+
+    fn authenticator_data(rp_id: &str, flags: u8, sign_count: u32, rest: &[u8]) -> Vec<u8> {
+        let mut data = Sha256::digest(rp_id.as_bytes()).to_vec();
+        data.push(flags);
+        data.extend_from_slice(&sign_count.to_be_bytes());
+        data.extend_from_slice(rest);
+        data
+    }
+
+    #[test]
+    fn registers_credentials() {
+        for algorithm in ALGORITHMS {
+            let authenticator = Authenticator::new(algorithm);
+            let (client_data_json, attestation_object) = authenticator.register(RP_ID);
+
+            let credential = verify_registration(
+                &relying_party(),
+                CHALLENGE,
+                &client_data_json,
+                &attestation_object,
+            )
+            .expect("registration is valid");
+
+            assert_eq!(credential.credential_id, CREDENTIAL_ID);
+            assert_eq!(credential.public_key, authenticator.cose_key());
+            assert_eq!(credential.sign_count, 0);
+        }
+    }
+
+    #[test]
+    fn verifies_assertions() {
+        for algorithm in ALGORITHMS {
+            let authenticator = Authenticator::new(algorithm);
+            let (client_data_json, auth_data, signature) = authenticator.assert(RP_ID, 7);
+
+            let verified = verify_assertion(
+                &relying_party(),
+                CHALLENGE,
+                &client_data_json,
+                &auth_data,
+                &signature,
+                &authenticator.cose_key(),
+            )
+            .expect("assertion is valid");
+
+            assert_eq!(verified.sign_count, 7);
+            assert!(!verified.user_verified());
+        }
+    }
+
+    #[test]
+    fn rejects_bad_signatures() {
+        for algorithm in ALGORITHMS {
+            let authenticator = Authenticator::new(algorithm);
+            let (client_data_json, auth_data, mut signature) = authenticator.assert(RP_ID, 1);
+            let last = signature.len() - 1;
+            signature[last] ^= 0x01;
+
+            assert!(verify_assertion(
+                &relying_party(),
+                CHALLENGE,
+                &client_data_json,
+                &auth_data,
+                &signature,
+                &authenticator.cose_key(),
+            )
+            .is_none());
+
+            // A valid signature over other data
+            let (_, _, other_signature) = authenticator.assert(RP_ID, 2);
+            assert!(verify_assertion(
+                &relying_party(),
+                CHALLENGE,
+                &client_data_json,
+                &auth_data,
+                &other_signature,
+                &authenticator.cose_key(),
+            )
+            .is_none());
+        }
+    }
+
+    #[test]
+    fn rejects_signatures_of_another_algorithm() {
+        let es256 = Authenticator::new(COSE_ALG_ES256);
+        let eddsa = Authenticator::new(COSE_ALG_EDDSA);
+        let (client_data_json, auth_data, signature) = eddsa.assert(RP_ID, 1);
+
+        assert!(verify_assertion(
+            &relying_party(),
+            CHALLENGE,
+            &client_data_json,
+            &auth_data,
+            &signature,
+            &es256.cose_key(),
+        )
+        .is_none());
+    }
+
+    #[test]
+    fn rejects_a_wrong_rp_id_hash() {
+        for algorithm in ALGORITHMS {
+            let authenticator = Authenticator::new(algorithm);
+
+            let (client_data_json, attestation_object) = authenticator.register("evil.example.com");
+            assert!(verify_registration(
+                &relying_party(),
+                CHALLENGE,
+                &client_data_json,
+                &attestation_object,
+            )
+            .is_none());
+
+            let (client_data_json, auth_data, signature) =
+                authenticator.assert("evil.example.com", 1);
+            assert!(verify_assertion(
+                &relying_party(),
+                CHALLENGE,
+                &client_data_json,
+                &auth_data,
+                &signature,
+                &authenticator.cose_key(),
+            )
+            .is_none());
+        }
+    }
+
+    #[test]
+    fn rejects_other_challenges_origins_and_ceremonies() {
+        let authenticator = Authenticator::new(COSE_ALG_ES256);
+        let (_, attestation_object) = authenticator.register(RP_ID);
+
+        for client_data_json in [
+            client_data("webauthn.create", "b3RoZXItY2hhbGxlbmdl", ORIGIN),
+            client_data("webauthn.create", CHALLENGE, "https://evil.example.com"),
+            client_data("webauthn.get", CHALLENGE, ORIGIN),
+        ] {
+            assert!(verify_registration(
+                &relying_party(),
+                CHALLENGE,
+                &client_data_json,
+                &attestation_object,
+            )
+            .is_none());
+        }
+    }
+
+    #[test]
+    fn rejects_assertions_without_user_presence() {
+        let authenticator = Authenticator::new(COSE_ALG_EDDSA);
+        let client_data_json = client_data("webauthn.get", CHALLENGE, ORIGIN);
+        let auth_data = authenticator_data(RP_ID, FLAG_USER_VERIFIED, 1, &[]);
+        let mut signed_data = auth_data.clone();
+        signed_data.extend_from_slice(&Sha256::digest(&client_data_json));
+
+        assert!(verify_assertion(
+            &relying_party(),
+            CHALLENGE,
+            &client_data_json,
+            &auth_data,
+            &authenticator.sign(&signed_data),
+            &authenticator.cose_key(),
+        )
+        .is_none());
+    }
+
+    #[test]
+    fn detects_sign_count_regressions() {
+        assert!(sign_count_increased(0, 0)); // Authenticators without a counter
+        assert!(sign_count_increased(0, 1));
+        assert!(sign_count_increased(41, 42));
+        assert!(!sign_count_increased(42, 42));
+        assert!(!sign_count_increased(42, 7));
+        assert!(!sign_count_increased(42, 0));
+    }
+}
@@ -0,0 +1,72 @@
+// Helpers for the WebAuthn ceremonies, binary values are exchanged as base64url strings
+function bufferToBase64url(buffer) {
+    const bytes = new Uint8Array(buffer);
+    let binary = "";
+    bytes.forEach(function (b) { binary += String.fromCharCode(b); });
+    return btoa(binary).replace(/\+/g, "-").replace(/\//g, "_").replace(/=+$/, "");
+}
+
+function base64urlToBuffer(value) {
+    const base64 = value.replace(/-/g, "+").replace(/_/g, "/");
+    const padded = base64 + "===".slice((base64.length + 3) % 4);
+    return Uint8Array.from(atob(padded), function (c) { return c.charCodeAt(0); }).buffer;
+}
+
+function decodeCredentialDescriptors(descriptors) {
+    return descriptors.map(function (descriptor) {
+        return Object.assign({}, descriptor, { id: base64urlToBuffer(descriptor.id) });
+    });
+}
+
+async function webauthnLogin(requestId) {
+    const response = await fetch("/webauthn/login/options", {
+        method: "POST",
+        headers: { "Content-Type": "application/json" },
+        body: JSON.stringify({ request_id: requestId })
+    });
+    const options = await response.json();
+    options.challenge = base64urlToBuffer(options.challenge);
+    options.allowCredentials = decodeCredentialDescriptors(options.allowCredentials);
+
+    const credential = await navigator.credentials.get({ publicKey: options });
+    const form = document.getElementById("webauthn-form");
+    form.credential_id.value = bufferToBase64url(credential.rawId);
+    form.client_data_json.value = bufferToBase64url(credential.response.clientDataJSON);
+    form.authenticator_data.value = bufferToBase64url(credential.response.authenticatorData);
+    form.signature.value = bufferToBase64url(credential.response.signature);
+    form.user_handle.value = credential.response.userHandle
+        ? bufferToBase64url(credential.response.userHandle)
+        : "";
+    form.submit();
+}
+
+async function webauthnRegister(name) {
+    const response = await fetch("/webauthn/register/options", { method: "POST" });
+    const options = await response.json();
+    options.challenge = base64urlToBuffer(options.challenge);
+    options.user.id = base64urlToBuffer(options.user.id);
+    options.excludeCredentials = decodeCredentialDescriptors(options.excludeCredentials);
+
+    const credential = await navigator.credentials.create({ publicKey: options });
+    const result = await fetch("/webauthn/register", {
+        method: "POST",
+        headers: { "Content-Type": "application/json" },
+        body: JSON.stringify({
+            name: name,
+            client_data_json: bufferToBase64url(credential.response.clientDataJSON),
+            attestation_object: bufferToBase64url(credential.response.attestationObject)
+        })
+    });
+    if (!result.ok) {
+        throw new Error(await result.text());
+    }
+}
+
+// Buttons for WebAuthn are only shown when the browser supports it
+document.addEventListener("DOMContentLoaded", function () {
+    if (window.PublicKeyCredential) {
+        document.querySelectorAll(".webauthn").forEach(function (element) {
+            element.hidden = false;
+        });
+    }
+});